@@ -1,26 +1,25 @@
+use alloy::{
+    eips::{eip2930::AccessList, BlockNumberOrTag},
+    primitives::{Address, Bytes, TxKind, U256},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::{TransactionInput, TransactionRequest},
+};
 use chrono::prelude::*;
 use criterion::{criterion_group, criterion_main, Criterion};
-use ethers::{
-    providers::{Http, Middleware, Provider, Ws},
-    types::{
-        transaction::eip2930::AccessList, BlockNumber, Bytes, Eip1559TransactionRequest,
-        NameOrAddress, H160, U256,
-    },
-};
-use std::{path::Path, str::FromStr, sync::Arc, time::Instant};
+use std::{collections::HashMap, path::Path, str::FromStr, sync::Arc, time::Instant};
 use tokio::runtime::Runtime;
 use tokio::sync::broadcast::{self, Sender};
 use tokio::task::JoinSet;
 
 use rust::bundler::{Bundler, Flashloan};
-use rust::constants::{Env, ZERO_ADDRESS};
-use rust::multi::{batch_get_uniswap_v2_reserves, get_uniswap_v2_reserves};
+use rust::constants::{get_v2_factories, Env, ZERO_ADDRESS};
+use rust::multi::{batch_get_uniswap_v2_reserves, get_uniswap_v2_reserves, PoolState};
 use rust::paths::generate_triangular_paths;
 use rust::pools::load_all_pools_from_v2;
-use rust::streams::{stream_new_blocks, stream_pending_transactions, Event};
+use rust::streams::Event;
 use rust::utils::{calculate_next_block_base_fee, get_touched_pool_reserves};
 
-pub async fn logging_event_handler(_: Arc<Provider<Ws>>, event_sender: Sender<Event>) {
+pub async fn logging_event_handler<P: Provider>(_: Arc<P>, event_sender: Sender<Event>) {
     let benchmark_file = Path::new("benches/.benchmark.csv");
     let mut writer = csv::Writer::from_path(benchmark_file).unwrap();
 
@@ -32,16 +31,22 @@ pub async fn logging_event_handler(_: Arc<Provider<Ws>>, event_sender: Sender<Ev
                 Event::Block(_) => {}
                 Event::PendingTx(tx) => {
                     let now = Local::now().timestamp_micros();
-                    writer.serialize((tx.hash, now)).unwrap();
+                    writer.serialize((tx.inner.tx_hash(), now)).unwrap();
                 }
                 Event::Log(_) => {}
+                Event::Connection(_) => {}
+                Event::Reorg(_) => {}
+                Event::BlockStateFinalized(_) => {}
             },
             Err(_) => {}
         }
     }
 }
 
-pub async fn touched_pools_event_handler(provider: Arc<Provider<Ws>>, event_sender: Sender<Event>) {
+pub async fn touched_pools_event_handler<P: Provider>(
+    provider: Arc<P>,
+    event_sender: Sender<Event>,
+) {
     let mut event_receiver = event_sender.subscribe();
 
     loop {
@@ -66,13 +71,16 @@ pub async fn touched_pools_event_handler(provider: Arc<Provider<Ws>>, event_send
                 }
                 Event::PendingTx(_) => {}
                 Event::Log(_) => {}
+                Event::Connection(_) => {}
+                Event::Reorg(_) => {}
+                Event::BlockStateFinalized(_) => {}
             },
             Err(_) => {}
         }
     }
 }
 
-pub async fn full_course_event_handler(provider: Arc<Provider<Ws>>, event_sender: Sender<Event>) {
+pub async fn full_course_event_handler<P: Provider>(provider: Arc<P>, event_sender: Sender<Event>) {
     // pass
 }
 
@@ -86,13 +94,13 @@ pub fn benchmark_function(_: &mut Criterion) {
     - Using node services like Infura/Alchemy will make this go considerably slower.
     */
     dotenv::dotenv().ok();
-    let env = Env::new();
+    let env = Env::new().unwrap();
 
     println!("Starting benchmark");
 
     // 1. Create HTTP provider
     let s = Instant::now();
-    let client = Provider::<Http>::try_from(env.https_url.clone()).unwrap();
+    let client = ProviderBuilder::new().connect_http(env.https_url.parse().unwrap());
     let client = Arc::new(client);
     let took = s.elapsed().as_micros();
     println!("1. HTTP provider created | Took: {:?} microsec", took);
@@ -107,11 +115,15 @@ pub fn benchmark_function(_: &mut Criterion) {
     loop {
         let task = async {
             let s = Instant::now();
-            let block = client.clone().get_block(BlockNumber::Latest).await.unwrap();
+            let block = client
+                .clone()
+                .get_block_by_number(BlockNumberOrTag::Latest)
+                .await
+                .unwrap();
             let took = s.elapsed().as_millis();
             println!(
                 "2. New block: #{:?} | Took: {:?} ms",
-                block.unwrap().number.unwrap(),
+                block.unwrap().header.number,
                 took
             );
         };
@@ -125,11 +137,10 @@ pub fn benchmark_function(_: &mut Criterion) {
 
     // 3. Retrieving cached pools data
     let task = async {
-        let factory_addresses = vec!["0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac"];
-        let factory_blocks = vec![10794229u64];
+        let factories = get_v2_factories();
 
         let s = Instant::now();
-        let pools = load_all_pools_from_v2(env.wss_url.clone(), factory_addresses, factory_blocks)
+        let pools = load_all_pools_from_v2(env.wss_url.clone(), &factories)
             .await
             .unwrap();
         let took = s.elapsed().as_millis();
@@ -143,12 +154,11 @@ pub fn benchmark_function(_: &mut Criterion) {
 
     // 4. Generate triangular arbitrage paths
     let task = async {
-        let factory_addresses = vec!["0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac"];
-        let factory_blocks = vec![10794229u64];
-        let pools = load_all_pools_from_v2(env.wss_url.clone(), factory_addresses, factory_blocks)
+        let factories = get_v2_factories();
+        let pools = load_all_pools_from_v2(env.wss_url.clone(), &factories)
             .await
             .unwrap();
-        let usdc_address = H160::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap();
+        let usdc_address = Address::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap();
 
         let s = Instant::now();
         let paths = generate_triangular_paths(&pools, usdc_address);
@@ -166,9 +176,8 @@ pub fn benchmark_function(_: &mut Criterion) {
 
     // Single multicall
     let task = async {
-        let factory_addresses = vec!["0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac"];
-        let factory_blocks = vec![10794229u64];
-        let pools = load_all_pools_from_v2(env.wss_url.clone(), factory_addresses, factory_blocks)
+        let factories = get_v2_factories();
+        let pools = load_all_pools_from_v2(env.wss_url.clone(), &factories)
             .await
             .unwrap();
 
@@ -187,9 +196,8 @@ pub fn benchmark_function(_: &mut Criterion) {
 
     // Batch multicall (thousands of requests asynchronously)
     let task = async {
-        let factory_addresses = vec!["0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac"];
-        let factory_blocks = vec![10794229u64];
-        let pools = load_all_pools_from_v2(env.wss_url.clone(), factory_addresses, factory_blocks)
+        let factories = get_v2_factories();
+        let pools = load_all_pools_from_v2(env.wss_url.clone(), &factories)
             .await
             .unwrap();
 
@@ -211,12 +219,12 @@ pub fn benchmark_function(_: &mut Criterion) {
        There may be better ways to pull this off, but the best use case benchmarks are those
        that benchmark the exact same setup that people use.
 
-       Most people running MEV bots using Rust will use ethers-rs Provider<Ws> to stream
+       Most people running MEV bots using Rust will use a websocket Provider to stream
        real-time data. And that is what I'm testing, without having to look under the hood.
     */
     // let task = async {
-    //     let ws = Ws::connect(env.wss_url.clone()).await.unwrap();
-    //     let provider = Arc::new(Provider::new(ws));
+    //     let ws = WsConnect::new(env.wss_url.clone());
+    //     let provider = Arc::new(ProviderBuilder::new().connect_ws(ws).await.unwrap());
 
     //     let (event_sender, _): (Sender<Event>, _) = broadcast::channel(512);
 
@@ -225,7 +233,11 @@ pub fn benchmark_function(_: &mut Criterion) {
     //     // try running the stream for n seconds
     //     set.spawn(tokio::time::timeout(
     //         std::time::Duration::from_secs(180),
-    //         stream_pending_transactions(provider.clone(), event_sender.clone()),
+    //         supervise_stream(
+    //             StreamKind::PendingTransactions,
+    //             env.wss_urls.clone(),
+    //             event_sender.clone(),
+    //         ),
     //     ));
 
     //     set.spawn(tokio::time::timeout(
@@ -242,8 +254,8 @@ pub fn benchmark_function(_: &mut Criterion) {
 
     // 7. Retrieving logs from a newly created block
     // let task = async {
-    //     let ws = Ws::connect(env.wss_url.clone()).await.unwrap();
-    //     let provider = Arc::new(Provider::new(ws));
+    //     let ws = WsConnect::new(env.wss_url.clone());
+    //     let provider = Arc::new(ProviderBuilder::new().connect_ws(ws).await.unwrap());
 
     //     let (event_sender, _): (Sender<Event>, _) = broadcast::channel(512);
 
@@ -252,7 +264,7 @@ pub fn benchmark_function(_: &mut Criterion) {
     //     // try running the stream for n seconds
     //     set.spawn(tokio::time::timeout(
     //         std::time::Duration::from_secs(60 * 5),
-    //         stream_new_blocks(provider.clone(), event_sender.clone()),
+    //         supervise_stream(StreamKind::NewBlocks, env.wss_urls.clone(), event_sender.clone()),
     //     ));
 
     //     set.spawn(tokio::time::timeout(
@@ -270,15 +282,19 @@ pub fn benchmark_function(_: &mut Criterion) {
 
     // 8. 3-hop path simulation
     let task = async {
-        let factory_addresses = vec!["0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac"];
-        let factory_blocks = vec![10794229u64];
-        let pools = load_all_pools_from_v2(env.wss_url.clone(), factory_addresses, factory_blocks)
+        let factories = get_v2_factories();
+        let pools = load_all_pools_from_v2(env.wss_url.clone(), &factories)
             .await
             .unwrap();
-        let usdc_address = H160::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap();
+        let usdc_address = Address::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap();
 
         let paths = generate_triangular_paths(&pools, usdc_address);
-        let reserves = batch_get_uniswap_v2_reserves(env.https_url.clone(), pools).await;
+        let reserves: HashMap<Address, PoolState> =
+            batch_get_uniswap_v2_reserves(env.https_url.clone(), pools)
+                .await
+                .into_iter()
+                .map(|(address, reserve)| (address, reserve.into()))
+                .collect();
 
         let took = paths.iter().map(|path| {
             let s = Instant::now();
@@ -300,12 +316,11 @@ pub fn benchmark_function(_: &mut Criterion) {
 
     // 9. Creating flashbots bundles
     let task = async {
-        let factory_addresses = vec!["0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac"];
-        let factory_blocks = vec![10794229u64];
-        let pools = load_all_pools_from_v2(env.wss_url.clone(), factory_addresses, factory_blocks)
+        let factories = get_v2_factories();
+        let pools = load_all_pools_from_v2(env.wss_url.clone(), &factories)
             .await
             .unwrap();
-        let usdc_address = H160::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap();
+        let usdc_address = Address::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap();
         let usdc_decimals = 6;
 
         let paths = generate_triangular_paths(&pools, usdc_address);
@@ -313,18 +328,12 @@ pub fn benchmark_function(_: &mut Criterion) {
         let unit = U256::from(10).pow(U256::from(usdc_decimals));
         let gwei = U256::from(10).pow(U256::from(9));
 
-        let bundler = Bundler::new();
+        let bundler = Bundler::new().unwrap();
         let block_number = bundler.provider.get_block_number().await.unwrap();
 
         let s = Instant::now();
-        let router_address = "0xd9e1cE17f2641f24aE83637ab66a2cca9C378B9F";
-        let routers = vec![
-            H160::from_str(router_address).unwrap(),
-            H160::from_str(router_address).unwrap(),
-            H160::from_str(router_address).unwrap(),
-        ];
         let path = &paths[0];
-        let path_params = path.to_path_params(&routers);
+        let path_params = path.to_path_params().unwrap();
         let amount_in = U256::from(1) * unit;
         let flashloan = Flashloan::NotUsed;
         let loan_from = *ZERO_ADDRESS;
@@ -355,17 +364,17 @@ pub fn benchmark_function(_: &mut Criterion) {
         let mut time_took = Vec::new();
 
         for n in 0..10 {
-            let bundler = Bundler::new();
+            let bundler = Bundler::new().unwrap();
             let block = bundler
                 .provider
-                .get_block(BlockNumber::Latest)
+                .get_block_by_number(BlockNumberOrTag::Latest)
                 .await
                 .unwrap()
                 .unwrap();
             let next_base_fee = U256::from(calculate_next_block_base_fee(
-                block.gas_used,
-                block.gas_limit,
-                block.base_fee_per_gas.unwrap_or_default(),
+                block.header.gas_used,
+                block.header.gas_limit,
+                block.header.base_fee_per_gas.unwrap_or_default(),
             ));
             let max_priority_fee_per_gas = U256::from(1);
             let max_fee_per_gas = next_base_fee + max_priority_fee_per_gas;
@@ -373,32 +382,28 @@ pub fn benchmark_function(_: &mut Criterion) {
             let _s = Instant::now();
             let s = Instant::now();
             let common = bundler._common_fields().await.unwrap();
-            let to = NameOrAddress::Address(common.0);
+            let to = TxKind::Call(common.0);
             let amount_in = U256::from(1) * U256::from(10).pow(U256::from(15)); // 0.001
-            let tx = Eip1559TransactionRequest {
+            let tx = TransactionRequest {
                 to: Some(to),
                 from: Some(common.0),
-                data: Some(Bytes(bytes::Bytes::new())),
+                input: TransactionInput::new(Bytes::new()),
                 value: Some(amount_in),
                 chain_id: Some(common.2),
-                max_priority_fee_per_gas: Some(max_priority_fee_per_gas),
-                max_fee_per_gas: Some(max_fee_per_gas),
-                gas: Some(U256::from(30000)),
+                max_priority_fee_per_gas: Some(max_priority_fee_per_gas.to::<u128>()),
+                max_fee_per_gas: Some(max_fee_per_gas.to::<u128>()),
+                gas: Some(30000),
                 nonce: Some(common.1),
-                access_list: AccessList::default(),
+                access_list: Some(AccessList::default()),
+                ..Default::default()
             };
             let signed_tx = bundler.sign_tx(tx).await.unwrap();
-            let bundle = bundler.to_bundle(vec![signed_tx], block.number.unwrap());
+            let bundle = bundler.to_bundle(vec![signed_tx], block.header.number);
             let took = s.elapsed().as_millis();
             println!("- Creating bundle took: {:?} ms", took);
 
             let s = Instant::now();
-            let simulated = bundler
-                .flashbots
-                .inner()
-                .simulate_bundle(&bundle)
-                .await
-                .unwrap();
+            let simulated = bundler.flashbots.simulate_bundle(&bundle).await.unwrap();
 
             for tx in &simulated.transactions {
                 if let Some(e) = &tx.error {
@@ -412,18 +417,13 @@ pub fn benchmark_function(_: &mut Criterion) {
             println!("- Running simulation took: {:?} ms", took);
 
             let s = Instant::now();
-            let pending_bundle = bundler
-                .flashbots
-                .inner()
-                .send_bundle(&bundle)
-                .await
-                .unwrap();
+            let bundle_hash = bundler.flashbots.send_bundle(&bundle).await.unwrap();
 
             let took = s.elapsed().as_millis();
             let total_took = _s.elapsed().as_millis();
             println!(
                 "10. Sending Flashbots bundle ({:?}) | Took: {:?} ms",
-                pending_bundle.bundle_hash, took
+                bundle_hash, took
             );
 
             time_took.push(total_took as u128);
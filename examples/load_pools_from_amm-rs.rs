@@ -2,15 +2,15 @@ use std::{fs::File, io::Write, sync::Arc};
 
 use amms::amms::{amm::AMM, factory::Factory, path::find_arb_paths_v2, uniswap_v2::UniswapV2Pool};
 use anyhow::Result;
-use futures::StreamExt;
 use itertools::Itertools;
 use log::info;
 use rust::{
     constants::{
-        Env, MIN_WETH_THRESHOLD, UNISWAP_V2_FACTORY_ADDRESS, UNISWAP_V3_FACTORY_ADDRESS, WEI,
-        WETH_ADDRESS, WETH_AMOUNT_IN, WHITELIST_TOKENS,
+        get_v2_factories, Env, MIN_WETH_THRESHOLD, UNISWAP_V2_FACTORY_ADDRESS,
+        UNISWAP_V3_FACTORY_ADDRESS, WEI, WETH_ADDRESS, WETH_AMOUNT_IN, WHITELIST_TOKENS,
     },
     math::{format_percent_bp, percentage_change_bp},
+    strategy::state_space_monitor,
 };
 use url::Url;
 
@@ -40,7 +40,7 @@ async fn main() -> Result<()> {
     //setup_logger()?;
     tracing_subscriber::fmt::init();
 
-    let env = Env::new();
+    let env = Env::new()?;
     // let factory_addresses = vec!["0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac"];
     // let router_addresses = vec!["0xd9e1cE17f2641f24aE83637ab66a2cca9C378B9F"];
     // let factory_blocks = vec![10794229u64];
@@ -104,19 +104,10 @@ async fn main() -> Result<()> {
     //     .create(true)
     //     .open("data/swaps.log")?;
 
-    /*
-    The subscribe method listens for new blocks and fetches
-    all logs matching any `sync_events()` specified by the AMM variants in the state space.
-    Under the hood, this method applies all state changes to any affected AMMs and returns a Vec of
-    addresses, indicating which AMMs have been updated.
-    */
-    let mut stream = _state_space_manager.subscribe()?;
-    while let Some(result) = stream.next().await {
-        if let Err(err) = result {
-            // Pass
-            println!("Error: {:#?}", err)
-        }
-    }
+    // Watch spreads on the manager's live state; its V2 pools all trade through the
+    // Uniswap V2 router
+    let router = get_v2_factories()[0].router;
+    state_space_monitor(_state_space_manager, router).await?;
 
     //let state = _state_space_manager.state.read().await;
     //println!("Full State: {:#?}", &*state);
@@ -1,5 +1,9 @@
-    use duners::{client::DuneClient, error::DuneRequestError};
-    use rust::constants::{Env, DUNE_QUERY_ID};
+    use anyhow::{anyhow, Result};
+    use duners::client::DuneClient;
+    use log::info;
+    use rust::constants::{get_env, get_v2_factories, Env, DUNE_API_URL, DUNE_QUERY_ID};
+    use rust::pools::load_pools_from_dune;
+    use rust::utils::setup_logger;
     use serde::Deserialize;
 
     #[derive(Deserialize, Debug, PartialEq)]
@@ -8,13 +12,29 @@
     }
 
     #[tokio::main]
-    async fn main() -> Result<(), DuneRequestError> {
+    async fn main() -> Result<()> {
         dotenv::dotenv().ok();
+        setup_logger()?;
 
-        let env = Env::new();
+        let env = Env::new()?;
         /* 24279386 */
         let dune = DuneClient::new(env.dune_api_key.as_str());
-        let results = dune.refresh::<ResultStruct>(DUNE_QUERY_ID, None, None).await?;
+        let results = dune
+            .refresh::<ResultStruct>(DUNE_QUERY_ID, None, None)
+            .await
+            .map_err(|e| anyhow!("{:?}", e))?;
         println!("{:?}", results.get_rows());
+
+        // Seed src/.cached-pools.csv from the pool list query
+        let pools_query_id: u32 = get_env("DUNE_POOLS_QUERY_ID").parse()?;
+        let pools = load_pools_from_dune(
+            env.https_url.clone(),
+            DUNE_API_URL,
+            &env.dune_api_key,
+            pools_query_id,
+            &get_v2_factories(),
+        )
+        .await?;
+        info!("Cached {} pools from Dune", pools.len());
         Ok(())
-    }
\ No newline at end of file
+    }
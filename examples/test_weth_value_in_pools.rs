@@ -28,7 +28,7 @@ async fn main() -> Result<()> {
     //setup_logger()?;
     tracing_subscriber::fmt::init();
 
-    let env = Env::new();
+    let env = Env::new()?;
     // let factory_addresses = vec!["0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac"];
     // let router_addresses = vec!["0xd9e1cE17f2641f24aE83637ab66a2cca9C378B9F"];
     // let factory_blocks = vec![10794229u64];
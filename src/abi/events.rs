@@ -0,0 +1,43 @@
+//! Pool events the runtime decodes from logs, as alloy `SolEvent`s.
+use alloy::sol;
+
+sol! {
+    interface IUniswapV2Pair {
+        /// Pair reserves after every mint, burn, swap or sync.
+        event Sync(uint112 reserve0, uint112 reserve1);
+    }
+
+    interface IUniswapV3Pool {
+        /// Carries the pool's post-swap slot0 and liquidity.
+        event Swap(
+            address indexed sender,
+            address indexed recipient,
+            int256 amount0,
+            int256 amount1,
+            uint160 sqrtPriceX96,
+            uint128 liquidity,
+            int24 tick
+        );
+
+        /// Liquidity added to `[tickLower, tickUpper)`.
+        event Mint(
+            address sender,
+            address indexed owner,
+            int24 indexed tickLower,
+            int24 indexed tickUpper,
+            uint128 amount,
+            uint256 amount0,
+            uint256 amount1
+        );
+
+        /// Liquidity removed from `[tickLower, tickUpper)`.
+        event Burn(
+            address indexed owner,
+            int24 indexed tickLower,
+            int24 indexed tickUpper,
+            uint128 amount,
+            uint256 amount0,
+            uint256 amount1
+        );
+    }
+}
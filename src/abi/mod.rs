@@ -1,12 +1,15 @@
-use ethers_core::abi::Abi;
+pub mod events;
+pub mod router;
+
+use alloy::json_abi::JsonAbi;
 use std::fs;
 
 pub struct ABI {
-    pub erc20: Abi,
-    pub weth: Abi,
-    pub uniswap_v2_factory: Abi,
-    pub uniswap_v2_pair: Abi,
-    pub v2_arb_bot: Abi,
+    pub erc20: JsonAbi,
+    pub weth: JsonAbi,
+    pub uniswap_v2_factory: JsonAbi,
+    pub uniswap_v2_pair: JsonAbi,
+    pub v2_arb_bot: JsonAbi,
 }
 
 impl ABI {
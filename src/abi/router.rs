@@ -0,0 +1,296 @@
+//! Decodes Uniswap V2 router calls from pending transactions into the swaps they make.
+//!
+//! Covers `UniswapV2Router02`'s swap functions and the V2 swaps `SwapRouter02` takes,
+//! including those it wraps in `multicall`. Any other call decodes to nothing.
+use alloy::{
+    primitives::{Address, U256},
+    sol,
+    sol_types::SolInterface,
+};
+
+sol! {
+    interface IUniswapV2Router02 {
+        function swapExactTokensForTokens(uint256 amountIn, uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external returns (uint256[] memory amounts);
+        function swapTokensForExactTokens(uint256 amountOut, uint256 amountInMax, address[] calldata path, address to, uint256 deadline) external returns (uint256[] memory amounts);
+        function swapExactETHForTokens(uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external payable returns (uint256[] memory amounts);
+        function swapTokensForExactETH(uint256 amountOut, uint256 amountInMax, address[] calldata path, address to, uint256 deadline) external returns (uint256[] memory amounts);
+        function swapExactTokensForETH(uint256 amountIn, uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external returns (uint256[] memory amounts);
+        function swapETHForExactTokens(uint256 amountOut, address[] calldata path, address to, uint256 deadline) external payable returns (uint256[] memory amounts);
+        function swapExactTokensForTokensSupportingFeeOnTransferTokens(uint256 amountIn, uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external;
+        function swapExactETHForTokensSupportingFeeOnTransferTokens(uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external payable;
+        function swapExactTokensForETHSupportingFeeOnTransferTokens(uint256 amountIn, uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external;
+    }
+
+    interface ISwapRouter02 {
+        function swapExactTokensForTokens(uint256 amountIn, uint256 amountOutMin, address[] calldata path, address to) external payable returns (uint256 amountOut);
+        function swapTokensForExactTokens(uint256 amountOut, uint256 amountInMax, address[] calldata path, address to) external payable returns (uint256 amountIn);
+        function multicall(bytes[] calldata data) external payable returns (bytes[] memory results);
+        function multicall(uint256 deadline, bytes[] calldata data) external payable returns (bytes[] memory results);
+        function multicall(bytes32 previousBlockhash, bytes[] calldata data) external payable returns (bytes[] memory results);
+    }
+}
+
+/// A router swap of `path[0]` for the last token in `path`. ETH-in swaps take
+/// `amount_in` from the transaction value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedSwap {
+    pub path: Vec<Address>,
+    /// The input, or for exact-output swaps the most the sender will pay.
+    pub amount_in: U256,
+    /// The least the sender accepts, or for exact-output swaps the exact output.
+    pub amount_out_min: U256,
+    pub deadline: U256,
+    pub recipient: Address,
+    /// Whether the router buys exactly `amount_out_min`, spending only as much of
+    /// `amount_in` as that takes.
+    pub exact_output: bool,
+}
+
+impl DecodedSwap {
+    fn new(
+        path: Vec<Address>,
+        amount_in: U256,
+        amount_out_min: U256,
+        deadline: U256,
+        recipient: Address,
+        exact_output: bool,
+    ) -> Option<Self> {
+        if path.len() < 2 {
+            return None;
+        }
+        Some(Self {
+            path,
+            amount_in,
+            amount_out_min,
+            deadline,
+            recipient,
+            exact_output,
+        })
+    }
+}
+
+/// The first swap in `input`, sent to a router with `value` wei attached.
+pub fn decode_router_swap(input: &[u8], value: U256) -> Option<DecodedSwap> {
+    decode_router_swaps(input, value).into_iter().next()
+}
+
+/// Every swap in `input`, in call order. A `multicall` yields the swaps among its calls
+/// and anything that isn't a swap yields none.
+pub fn decode_router_swaps(input: &[u8], value: U256) -> Vec<DecodedSwap> {
+    use ISwapRouter02::ISwapRouter02Calls as Call;
+
+    let (deadline, calls) = match Call::abi_decode(input) {
+        Ok(Call::multicall_0(call)) => (U256::MAX, call.data),
+        Ok(Call::multicall_1(call)) => (call.deadline, call.data),
+        Ok(Call::multicall_2(call)) => (U256::MAX, call.data),
+        _ => return _decode_swap(input, value, U256::MAX).into_iter().collect(),
+    };
+    // Multicalls aren't unwrapped again; the router rejects nested ones anyway
+    calls
+        .iter()
+        .filter_map(|call| _decode_swap(call, value, deadline))
+        .collect()
+}
+
+/// Decodes a single swap call. `deadline` applies to `SwapRouter02` swaps, which
+/// only have the one their `multicall` wrapper sets.
+fn _decode_swap(input: &[u8], value: U256, deadline: U256) -> Option<DecodedSwap> {
+    use ISwapRouter02::ISwapRouter02Calls as Call;
+    use IUniswapV2Router02::IUniswapV2Router02Calls as V2Call;
+
+    if let Ok(call) = V2Call::abi_decode(input) {
+        return match call {
+            V2Call::swapExactTokensForTokens(c) => {
+                DecodedSwap::new(c.path, c.amountIn, c.amountOutMin, c.deadline, c.to, false)
+            }
+            V2Call::swapTokensForExactTokens(c) => {
+                DecodedSwap::new(c.path, c.amountInMax, c.amountOut, c.deadline, c.to, true)
+            }
+            V2Call::swapExactETHForTokens(c) => {
+                DecodedSwap::new(c.path, value, c.amountOutMin, c.deadline, c.to, false)
+            }
+            V2Call::swapTokensForExactETH(c) => {
+                DecodedSwap::new(c.path, c.amountInMax, c.amountOut, c.deadline, c.to, true)
+            }
+            V2Call::swapExactTokensForETH(c) => {
+                DecodedSwap::new(c.path, c.amountIn, c.amountOutMin, c.deadline, c.to, false)
+            }
+            V2Call::swapETHForExactTokens(c) => {
+                DecodedSwap::new(c.path, value, c.amountOut, c.deadline, c.to, true)
+            }
+            V2Call::swapExactTokensForTokensSupportingFeeOnTransferTokens(c) => {
+                DecodedSwap::new(c.path, c.amountIn, c.amountOutMin, c.deadline, c.to, false)
+            }
+            V2Call::swapExactETHForTokensSupportingFeeOnTransferTokens(c) => {
+                DecodedSwap::new(c.path, value, c.amountOutMin, c.deadline, c.to, false)
+            }
+            V2Call::swapExactTokensForETHSupportingFeeOnTransferTokens(c) => {
+                DecodedSwap::new(c.path, c.amountIn, c.amountOutMin, c.deadline, c.to, false)
+            }
+        };
+    }
+
+    match Call::abi_decode(input).ok()? {
+        Call::swapExactTokensForTokens(c) => {
+            DecodedSwap::new(c.path, c.amountIn, c.amountOutMin, deadline, c.to, false)
+        }
+        Call::swapTokensForExactTokens(c) => {
+            DecodedSwap::new(c.path, c.amountInMax, c.amountOut, deadline, c.to, true)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod router_tests {
+    use super::*;
+    use alloy::{primitives::Bytes, sol_types::SolCall};
+
+    fn token(byte: u8) -> Address {
+        Address::repeat_byte(byte)
+    }
+
+    #[test]
+    fn decodes_exact_input_swaps() {
+        let input = IUniswapV2Router02::swapExactTokensForTokensCall {
+            amountIn: U256::from(100),
+            amountOutMin: U256::from(90),
+            path: vec![token(1), token(2), token(3)],
+            to: token(9),
+            deadline: U256::from(1_700_000_000),
+        }
+        .abi_encode();
+
+        let swap = decode_router_swap(&input, U256::ZERO).unwrap();
+        assert_eq!(
+            swap,
+            DecodedSwap {
+                path: vec![token(1), token(2), token(3)],
+                amount_in: U256::from(100),
+                amount_out_min: U256::from(90),
+                deadline: U256::from(1_700_000_000),
+                recipient: token(9),
+                exact_output: false,
+            }
+        );
+
+        let input = IUniswapV2Router02::swapExactTokensForETHSupportingFeeOnTransferTokensCall {
+            amountIn: U256::from(100),
+            amountOutMin: U256::from(90),
+            path: vec![token(1), token(2)],
+            to: token(9),
+            deadline: U256::from(1),
+        }
+        .abi_encode();
+        assert_eq!(
+            decode_router_swap(&input, U256::ZERO).unwrap().amount_in,
+            U256::from(100)
+        );
+    }
+
+    #[test]
+    fn eth_in_swaps_take_the_value() {
+        let input = IUniswapV2Router02::swapExactETHForTokensCall {
+            amountOutMin: U256::from(90),
+            path: vec![token(1), token(2)],
+            to: token(9),
+            deadline: U256::from(1),
+        }
+        .abi_encode();
+        let swap = decode_router_swap(&input, U256::from(5)).unwrap();
+        assert_eq!(
+            (swap.amount_in, swap.amount_out_min),
+            (U256::from(5), U256::from(90))
+        );
+
+        let input = IUniswapV2Router02::swapETHForExactTokensCall {
+            amountOut: U256::from(3),
+            path: vec![token(1), token(2)],
+            to: token(9),
+            deadline: U256::from(1),
+        }
+        .abi_encode();
+        let swap = decode_router_swap(&input, U256::from(5)).unwrap();
+        assert_eq!(
+            (swap.amount_in, swap.amount_out_min),
+            (U256::from(5), U256::from(3))
+        );
+        assert!(swap.exact_output);
+    }
+
+    #[test]
+    fn exact_output_swaps_are_flagged_with_the_input_cap() {
+        let input = IUniswapV2Router02::swapTokensForExactTokensCall {
+            amountOut: U256::from(50),
+            amountInMax: U256::from(60),
+            path: vec![token(1), token(2)],
+            to: token(9),
+            deadline: U256::from(1),
+        }
+        .abi_encode();
+
+        let swap = decode_router_swap(&input, U256::ZERO).unwrap();
+        assert_eq!(
+            (swap.amount_in, swap.amount_out_min),
+            (U256::from(60), U256::from(50))
+        );
+        assert!(swap.exact_output);
+    }
+
+    #[test]
+    fn unwraps_multicalls() {
+        let first = ISwapRouter02::swapExactTokensForTokensCall {
+            amountIn: U256::from(100),
+            amountOutMin: U256::from(90),
+            path: vec![token(1), token(2)],
+            to: token(9),
+        }
+        .abi_encode();
+        let second = ISwapRouter02::swapTokensForExactTokensCall {
+            amountOut: U256::from(50),
+            amountInMax: U256::from(60),
+            path: vec![token(2), token(3)],
+            to: token(9),
+        }
+        .abi_encode();
+        // e.g. refundETH(), which isn't a swap
+        let other = vec![0x12, 0x21, 0x0e, 0x8a];
+        let input = ISwapRouter02::multicall_1Call {
+            deadline: U256::from(1_700_000_000),
+            data: vec![Bytes::from(first), Bytes::from(other), Bytes::from(second)],
+        }
+        .abi_encode();
+
+        let swaps = decode_router_swaps(&input, U256::ZERO);
+        assert_eq!(swaps.len(), 2);
+        assert_eq!(swaps[0].path, vec![token(1), token(2)]);
+        assert_eq!(swaps[1].amount_in, U256::from(60));
+        assert!(!swaps[0].exact_output && swaps[1].exact_output);
+        assert!(swaps
+            .iter()
+            .all(|swap| swap.deadline == U256::from(1_700_000_000)));
+    }
+
+    #[test]
+    fn ignores_anything_else() {
+        // approve(address,uint256)
+        let mut input = vec![0x09, 0x5e, 0xa7, 0xb3];
+        input.extend_from_slice(&[0u8; 64]);
+        assert!(decode_router_swap(&input, U256::ZERO).is_none());
+        assert!(decode_router_swap(&[], U256::ZERO).is_none());
+
+        let call = |path: Vec<Address>| {
+            IUniswapV2Router02::swapExactTokensForTokensCall {
+                amountIn: U256::from(100),
+                amountOutMin: U256::from(90),
+                path,
+                to: token(9),
+                deadline: U256::from(1),
+            }
+            .abi_encode()
+        };
+        assert!(decode_router_swap(&call(vec![token(1)]), U256::ZERO).is_none());
+        let input = call(vec![token(1), token(2)]);
+        assert!(decode_router_swap(&input[..input.len() - 1], U256::ZERO).is_none());
+    }
+}
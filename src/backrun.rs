@@ -0,0 +1,557 @@
+//! Backruns pending Uniswap V2 router swaps. A decoded swap is applied to a copy of the
+//! reserves, the paths through the pools it moves are re-quoted against that copy, and
+//! the best one is bundled right behind the pending transaction.
+use alloy::{
+    consensus::Transaction as _,
+    eips::eip2718::Encodable2718,
+    primitives::{Address, Bytes, I256, U256},
+    rpc::types::Transaction,
+};
+use anyhow::{anyhow, Result};
+use std::{collections::HashMap, str::FromStr};
+
+use crate::abi::router::{decode_router_swaps, DecodedSwap};
+use crate::bundler::{Bundler, Flashloan};
+use crate::constants::BALANCER_VAULT_ADDRESS;
+use crate::flashbots::BundleRequest;
+use crate::multi::{PoolState, Reserve};
+use crate::optimizer::{SearchConfig, SearchResult};
+use crate::paths::ArbPath;
+use crate::pools::{DexVariant, Pool};
+use crate::simulator::UniswapV2Simulator;
+
+#[derive(Debug, Clone)]
+pub struct BackrunOpportunity {
+    pub victim: Transaction,
+    pub swaps: Vec<DecodedSwap>,
+    pub path_index: usize,
+    pub amount_in: U256,
+    pub profit: I256,
+}
+
+pub struct BackrunEngine {
+    /// V2 pools keyed by `(router, token0, token1)`, the pair a router resolves a hop to.
+    pairs: HashMap<(Address, Address, Address), Pool>,
+    /// Each address swaps are sent to, mapped to the router whose pairs it trades through.
+    routers: HashMap<Address, Address>,
+    /// Largest arb input searched, in whole input tokens.
+    max_amount_in: U256,
+    config: SearchConfig,
+}
+
+impl BackrunEngine {
+    pub fn new(pools: &[Pool], max_amount_in: U256, config: SearchConfig) -> Self {
+        let pairs: HashMap<_, _> = pools
+            .iter()
+            .filter(|pool| matches!(pool.version, DexVariant::UniswapV2))
+            .map(|pool| ((pool.router, pool.token0, pool.token1), pool.clone()))
+            .collect();
+        let routers = pairs
+            .keys()
+            .map(|(router, _, _)| (*router, *router))
+            .collect();
+        Self {
+            pairs,
+            routers,
+            max_amount_in,
+            config,
+        }
+    }
+
+    /// Also decodes swaps sent to `alias`, a router such as `SwapRouter02` that trades
+    /// through `router`'s pairs. Does nothing if none of `router`'s pairs are tracked.
+    pub fn with_router_alias(mut self, alias: Address, router: Address) -> Self {
+        if self.routers.contains_key(&router) {
+            self.routers.insert(alias, router);
+        }
+        self
+    }
+
+    pub fn is_router(&self, address: &Address) -> bool {
+        self.routers.contains_key(address)
+    }
+
+    fn pair(&self, router: Address, token_a: Address, token_b: Address) -> Option<&Pool> {
+        let key = if token_a < token_b {
+            (router, token_a, token_b)
+        } else {
+            (router, token_b, token_a)
+        };
+        self.pairs.get(&key)
+    }
+
+    /// `pool`'s reserves of `token_in` and of the other token, in that order.
+    fn directed_reserves(
+        pool: &Pool,
+        token_in: Address,
+        reserves: &HashMap<Address, PoolState>,
+    ) -> Option<(U256, U256)> {
+        match reserves.get(&pool.address) {
+            Some(PoolState::UniswapV2(reserve)) if token_in == pool.token0 => {
+                Some((reserve.reserve0, reserve.reserve1))
+            }
+            Some(PoolState::UniswapV2(reserve)) => Some((reserve.reserve1, reserve.reserve0)),
+            _ => None,
+        }
+    }
+
+    /// What an exact-output swap actually spends, back-solved hop by hop from its output
+    /// the way the router's `getAmountsIn` does. `None` if a hop isn't tracked, since the
+    /// input then can't be known, or if it exceeds the sender's cap and the swap reverts.
+    fn exact_output_amount_in(
+        &self,
+        router: Address,
+        swap: &DecodedSwap,
+        reserves: &HashMap<Address, PoolState>,
+    ) -> Option<U256> {
+        let mut amount = swap.amount_out_min;
+        for tokens in swap.path.windows(2).rev() {
+            let pool = self.pair(router, tokens[0], tokens[1])?;
+            let (reserve_in, reserve_out) = Self::directed_reserves(pool, tokens[0], reserves)?;
+            amount = UniswapV2Simulator::get_amount_in(
+                amount,
+                reserve_in,
+                reserve_out,
+                U256::from(pool.fee),
+            )?;
+        }
+        (amount <= swap.amount_in).then_some(amount)
+    }
+
+    /// Reserves after `swap` executes through `router`, plus the pools it moved. Hops are
+    /// applied until the first pair that isn't tracked. Returns `None` if no tracked pair
+    /// is moved, or if the whole path is tracked and pays out less than `amount_out_min`,
+    /// in which case the swap reverts. Exact-output swaps are applied with the input they
+    /// really spend, so every hop has to be tracked. Fee-on-transfer taxes aren't deducted.
+    pub fn apply_swap(
+        &self,
+        router: Address,
+        swap: &DecodedSwap,
+        reserves: &HashMap<Address, PoolState>,
+    ) -> Option<(HashMap<Address, PoolState>, Vec<Address>)> {
+        self.apply_swaps(router, std::slice::from_ref(swap), reserves)
+    }
+
+    /// Like `apply_swap` for every swap of one transaction, such as the calls of a
+    /// `multicall`, each applied to the reserves the previous one left. If any of them
+    /// reverts the whole transaction does, so `None` is returned.
+    pub fn apply_swaps(
+        &self,
+        router: Address,
+        swaps: &[DecodedSwap],
+        reserves: &HashMap<Address, PoolState>,
+    ) -> Option<(HashMap<Address, PoolState>, Vec<Address>)> {
+        let mut after = reserves.clone();
+        let mut touched = Vec::new();
+        for swap in swaps {
+            for pool in self._apply_swap(router, swap, &mut after)? {
+                if !touched.contains(&pool) {
+                    touched.push(pool);
+                }
+            }
+        }
+
+        if touched.is_empty() {
+            return None;
+        }
+        Some((after, touched))
+    }
+
+    /// Applies `swap` to `reserves` in place and returns the pools it moved, which may be
+    /// none. `None` if the swap reverts or its input can't be known.
+    fn _apply_swap(
+        &self,
+        router: Address,
+        swap: &DecodedSwap,
+        reserves: &mut HashMap<Address, PoolState>,
+    ) -> Option<Vec<Address>> {
+        let path = &swap.path;
+
+        let mut touched = Vec::new();
+        let mut amount = if swap.exact_output {
+            self.exact_output_amount_in(router, swap, reserves)?
+        } else {
+            swap.amount_in
+        };
+        for tokens in path.windows(2) {
+            let (token_in, token_out) = (tokens[0], tokens[1]);
+            let pool = match self.pair(router, token_in, token_out) {
+                Some(pool) => pool,
+                None => break,
+            };
+            let (reserve_in, reserve_out) = match Self::directed_reserves(pool, token_in, reserves)
+            {
+                Some(directed) => directed,
+                None => break,
+            };
+
+            let zero_for_one = token_in == pool.token0;
+            let amount_out = UniswapV2Simulator::get_amount_out(
+                amount,
+                reserve_in,
+                reserve_out,
+                U256::from(pool.fee),
+            )?;
+            let (reserve_in, reserve_out) = (
+                reserve_in.checked_add(amount)?,
+                reserve_out.checked_sub(amount_out)?,
+            );
+            let updated = if zero_for_one {
+                Reserve {
+                    reserve0: reserve_in,
+                    reserve1: reserve_out,
+                }
+            } else {
+                Reserve {
+                    reserve0: reserve_out,
+                    reserve1: reserve_in,
+                }
+            };
+            reserves.insert(pool.address, updated.into());
+            touched.push(pool.address);
+            amount = amount_out;
+        }
+
+        if touched.len() == path.len() - 1 && amount < swap.amount_out_min {
+            return None;
+        }
+        Some(touched)
+    }
+
+    /// The path through a pool `swaps` move that is most profitable right after them,
+    /// if that profit clears `min_profit`. Only all-V2 paths are considered, since the
+    /// bot can't execute any other hop.
+    pub fn find_for_swaps(
+        &self,
+        router: Address,
+        swaps: &[DecodedSwap],
+        paths: &[ArbPath],
+        reserves: &HashMap<Address, PoolState>,
+        min_profit: I256,
+    ) -> Option<(usize, SearchResult)> {
+        let (after, touched) = self.apply_swaps(router, swaps, reserves)?;
+
+        paths
+            .iter()
+            .enumerate()
+            .filter(|(_, path)| path.is_all_v2())
+            .filter(|(_, path)| touched.iter().any(|pool| path.has_pool(pool)))
+            .map(|(idx, path)| {
+                (
+                    idx,
+                    path.search_amount_in(self.max_amount_in, &self.config, &after),
+                )
+            })
+            .filter(|(_, result)| result.profit > min_profit)
+            .max_by_key(|(_, result)| result.profit)
+    }
+
+    /// Decodes a pending transaction sent to one of the tracked routers and looks for a
+    /// path to backrun it with.
+    pub fn find(
+        &self,
+        tx: &Transaction,
+        paths: &[ArbPath],
+        reserves: &HashMap<Address, PoolState>,
+        min_profit: I256,
+    ) -> Option<BackrunOpportunity> {
+        let router = *self.routers.get(&tx.to()?)?;
+        let swaps = decode_router_swaps(tx.input(), tx.value());
+        let (path_index, result) =
+            self.find_for_swaps(router, &swaps, paths, reserves, min_profit)?;
+        Some(BackrunOpportunity {
+            victim: tx.clone(),
+            swaps,
+            path_index,
+            amount_in: result.amount_in,
+            profit: result.profit,
+        })
+    }
+}
+
+/// Bundles the pending transaction and a flashloaned arb along `path` for the block
+/// after `block_number`, in that order.
+pub async fn build_backrun_bundle(
+    bundler: &Bundler,
+    opportunity: &BackrunOpportunity,
+    path: &ArbPath,
+    block_number: u64,
+    max_priority_fee_per_gas: U256,
+    max_fee_per_gas: U256,
+) -> Result<BundleRequest> {
+    let victim = Bytes::from(opportunity.victim.inner.inner().encoded_2718());
+    let path_params = path
+        .to_path_params()
+        .ok_or_else(|| anyhow!("path has a hop the bot can't execute"))?;
+    let loan_from = Address::from_str(BALANCER_VAULT_ADDRESS)?;
+    let order = bundler
+        .order_tx(
+            path_params,
+            opportunity.amount_in,
+            Flashloan::Balancer,
+            loan_from,
+            max_priority_fee_per_gas,
+            max_fee_per_gas,
+        )
+        .await?;
+    let arb = bundler.sign_tx(order).await?;
+    Ok(bundler.to_bundle(vec![victim, arb], block_number))
+}
+
+#[cfg(test)]
+mod backrun_tests {
+    use super::*;
+    use crate::paths::Hop;
+    use alloy::primitives::Address;
+
+    const ROUTER: u8 = 0xee;
+
+    fn token(byte: u8) -> Address {
+        Address::repeat_byte(byte)
+    }
+
+    fn pool(address: u8, token0: u8, token1: u8) -> Pool {
+        Pool {
+            address: token(address),
+            version: DexVariant::UniswapV2,
+            token0: token(token0),
+            token1: token(token1),
+            decimals0: 18,
+            decimals1: 18,
+            fee: 3000,
+            router: token(ROUTER),
+        }
+    }
+
+    fn whole(amount: u64) -> U256 {
+        U256::from(amount) * U256::from(10).pow(U256::from(18))
+    }
+
+    /// Balanced triangle 1 -> 2 -> 3 -> 1, where fees leave nothing to arb.
+    fn setup() -> (BackrunEngine, Vec<ArbPath>, HashMap<Address, PoolState>) {
+        let pools = vec![pool(0xa0, 1, 2), pool(0xa1, 2, 3), pool(0xa2, 1, 3)];
+        let paths = vec![ArbPath::new(vec![
+            Hop::new(pools[0].clone(), true),
+            Hop::new(pools[1].clone(), true),
+            Hop::new(pools[2].clone(), false),
+        ])];
+        let reserves = pools
+            .iter()
+            .map(|pool| {
+                let reserve = Reserve {
+                    reserve0: whole(1000),
+                    reserve1: whole(1000),
+                };
+                (pool.address, reserve.into())
+            })
+            .collect();
+        let engine = BackrunEngine::new(&pools, U256::from(1000), SearchConfig::default());
+        (engine, paths, reserves)
+    }
+
+    fn exact_output_swap(path: &[u8], amount_in_max: u64, amount_out: u64) -> DecodedSwap {
+        DecodedSwap {
+            exact_output: true,
+            ..swap(path, amount_in_max, amount_out)
+        }
+    }
+
+    fn swap(path: &[u8], amount_in: u64, amount_out_min: u64) -> DecodedSwap {
+        DecodedSwap {
+            path: path
+                .iter()
+                .map(|byte| Address::repeat_byte(*byte))
+                .collect(),
+            amount_in: whole(amount_in),
+            amount_out_min: whole(amount_out_min),
+            deadline: U256::MAX,
+            recipient: Address::repeat_byte(0x99),
+            exact_output: false,
+        }
+    }
+
+    #[test]
+    fn applies_swaps_to_a_copy_of_the_reserves() {
+        let (engine, _, reserves) = setup();
+
+        let (after, touched) = engine
+            .apply_swap(token(ROUTER), &swap(&[2, 1], 100, 0), &reserves)
+            .unwrap();
+        assert_eq!(touched, vec![token(0xa0)]);
+        match (&after[&token(0xa0)], &reserves[&token(0xa0)]) {
+            (PoolState::UniswapV2(after), PoolState::UniswapV2(before)) => {
+                assert_eq!(after.reserve1, whole(1100));
+                assert!(after.reserve0 < whole(910) && after.reserve0 > whole(909));
+                assert_eq!(before.reserve0, whole(1000));
+            }
+            other => panic!("unexpected states {:?}", other),
+        }
+
+        // The untracked last hop stops the walk, so its minimum can't be checked
+        let (_, touched) = engine
+            .apply_swap(token(ROUTER), &swap(&[2, 1, 9], 100, 1000), &reserves)
+            .unwrap();
+        assert_eq!(touched, vec![token(0xa0)]);
+    }
+
+    #[test]
+    fn skips_swaps_that_move_nothing_or_revert() {
+        let (engine, _, reserves) = setup();
+
+        assert!(engine
+            .apply_swap(token(ROUTER), &swap(&[9, 1], 100, 0), &reserves)
+            .is_none());
+        assert!(engine
+            .apply_swap(token(0xdd), &swap(&[2, 1], 100, 0), &reserves)
+            .is_none());
+        assert!(engine
+            .apply_swap(token(ROUTER), &swap(&[2, 1], 100, 100), &reserves)
+            .is_none());
+    }
+
+    #[test]
+    fn overflowing_swaps_are_skipped() {
+        let (engine, _, reserves) = setup();
+
+        let mut junk = swap(&[2, 1], 0, 0);
+        junk.amount_in = U256::MAX;
+        assert!(engine.apply_swap(token(ROUTER), &junk, &reserves).is_none());
+        assert!(UniswapV2Simulator::get_amount_out(
+            U256::MAX,
+            whole(1000),
+            whole(1000),
+            U256::from(3000)
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn exact_output_swaps_spend_the_back_solved_input() {
+        let (engine, _, reserves) = setup();
+
+        // 50 out of a 1000/1000 pool takes about 52.8 in, well under the 60 cap
+        let (after, touched) = engine
+            .apply_swap(
+                token(ROUTER),
+                &exact_output_swap(&[2, 1], 60, 50),
+                &reserves,
+            )
+            .unwrap();
+        assert_eq!(touched, vec![token(0xa0)]);
+        match &after[&token(0xa0)] {
+            PoolState::UniswapV2(after) => {
+                assert!(after.reserve0 <= whole(950));
+                assert!(after.reserve0 + U256::ONE >= whole(950));
+                assert!(after.reserve1 > whole(1052) && after.reserve1 < whole(1053));
+            }
+            other => panic!("unexpected state {:?}", other),
+        }
+
+        // Over the cap, the router reverts
+        assert!(engine
+            .apply_swap(
+                token(ROUTER),
+                &exact_output_swap(&[2, 1], 52, 50),
+                &reserves
+            )
+            .is_none());
+        // An untracked hop leaves the input unknown
+        assert!(engine
+            .apply_swap(
+                token(ROUTER),
+                &exact_output_swap(&[2, 1, 9], 60, 50),
+                &reserves
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn applies_every_swap_of_a_multicall_in_order() {
+        let (engine, _, reserves) = setup();
+
+        // 2 -> 1 then 1 -> 3, the second swap spending into the first one's output pool
+        let swaps = [swap(&[2, 1], 100, 0), swap(&[1, 3], 50, 0)];
+        let (after, touched) = engine
+            .apply_swaps(token(ROUTER), &swaps, &reserves)
+            .unwrap();
+        assert_eq!(touched, vec![token(0xa0), token(0xa2)]);
+        match (&after[&token(0xa0)], &after[&token(0xa2)]) {
+            (PoolState::UniswapV2(first), PoolState::UniswapV2(second)) => {
+                assert_eq!(first.reserve1, whole(1100));
+                assert_eq!(second.reserve0, whole(1050));
+            }
+            other => panic!("unexpected states {:?}", other),
+        }
+
+        // The same pool moved twice is applied on top of the first swap
+        let twice = [swap(&[2, 1], 100, 0), swap(&[2, 1], 100, 0)];
+        let (after, touched) = engine
+            .apply_swaps(token(ROUTER), &twice, &reserves)
+            .unwrap();
+        assert_eq!(touched, vec![token(0xa0)]);
+        match &after[&token(0xa0)] {
+            PoolState::UniswapV2(reserve) => assert_eq!(reserve.reserve1, whole(1200)),
+            other => panic!("unexpected state {:?}", other),
+        }
+
+        // One reverting call reverts the whole multicall
+        let reverting = [swap(&[2, 1], 100, 0), swap(&[1, 3], 50, 50)];
+        assert!(engine
+            .apply_swaps(token(ROUTER), &reverting, &reserves)
+            .is_none());
+    }
+
+    #[test]
+    fn aliased_routers_trade_through_the_router_pairs() {
+        let (engine, _, _) = setup();
+        let alias = token(0xef);
+        assert!(!engine.is_router(&alias));
+
+        let engine = engine
+            .with_router_alias(alias, token(ROUTER))
+            .with_router_alias(token(0xdf), token(0xdd));
+        assert!(engine.is_router(&alias));
+        assert_eq!(engine.routers[&alias], token(ROUTER));
+        // No pairs behind the untracked router, so there's nothing to alias
+        assert!(!engine.is_router(&token(0xdf)));
+    }
+
+    #[test]
+    fn finds_paths_opened_by_the_swap() {
+        let (engine, paths, reserves) = setup();
+
+        assert!(engine
+            .find_for_swaps(
+                token(ROUTER),
+                &[swap(&[2, 1], 1, 0)],
+                &paths,
+                &reserves,
+                I256::ZERO
+            )
+            .is_none());
+
+        let (idx, result) = engine
+            .find_for_swaps(
+                token(ROUTER),
+                &[swap(&[2, 1], 100, 0)],
+                &paths,
+                &reserves,
+                I256::ZERO,
+            )
+            .unwrap();
+        assert_eq!(idx, 0);
+        assert!(result.profit > I256::ZERO);
+        assert!(!result.amount_in.is_zero());
+
+        let unreachable = result.profit;
+        assert!(engine
+            .find_for_swaps(
+                token(ROUTER),
+                &[swap(&[2, 1], 100, 0)],
+                &paths,
+                &reserves,
+                unreachable
+            )
+            .is_none());
+    }
+}
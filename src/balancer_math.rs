@@ -0,0 +1,345 @@
+//! Ports of Balancer V2's `FixedPoint`, `LogExpMath` and `WeightedMath` so weighted
+//! pool swaps can be quoted off-chain with the same rounding as the Vault.
+use alloy::primitives::{I256, U256};
+use std::sync::LazyLock;
+
+pub const ONE: u128 = 1_000_000_000_000_000_000;
+// Max ratio of the in-balance that a single swap may add
+pub const MAX_IN_RATIO: u128 = 300_000_000_000_000_000;
+// FixedPoint.powUp pads LogExpMath.pow by this relative error (1e-14)
+pub const MAX_POW_RELATIVE_ERROR: u128 = 10_000;
+
+// FixedPoint
+
+pub fn mul_down(a: U256, b: U256) -> Option<U256> {
+    Some(a.checked_mul(b)? / U256::from(ONE))
+}
+
+pub fn mul_up(a: U256, b: U256) -> Option<U256> {
+    let product = a.checked_mul(b)?;
+    if product.is_zero() {
+        Some(U256::ZERO)
+    } else {
+        Some((product - U256::ONE) / U256::from(ONE) + U256::ONE)
+    }
+}
+
+pub fn div_down(a: U256, b: U256) -> Option<U256> {
+    if b.is_zero() {
+        return None;
+    }
+    Some(a.checked_mul(U256::from(ONE))? / b)
+}
+
+pub fn div_up(a: U256, b: U256) -> Option<U256> {
+    if b.is_zero() {
+        return None;
+    }
+    if a.is_zero() {
+        return Some(U256::ZERO);
+    }
+    Some((a.checked_mul(U256::from(ONE))? - U256::ONE) / b + U256::ONE)
+}
+
+pub fn complement(x: U256) -> U256 {
+    let one = U256::from(ONE);
+    if x < one {
+        one - x
+    } else {
+        U256::ZERO
+    }
+}
+
+/// `FixedPoint.powUp` from the V2+ weighted pools, with the exact shortcuts for
+/// exponents of one, two and four.
+pub fn pow_up(x: U256, y: U256) -> Option<U256> {
+    let one = U256::from(ONE);
+    if y == one {
+        return Some(x);
+    }
+    if y == one * U256::from(2) {
+        return mul_up(x, x);
+    }
+    if y == one * U256::from(4) {
+        let square = mul_up(x, x)?;
+        return mul_up(square, square);
+    }
+
+    let raw = pow(x, y)?;
+    let max_error = mul_up(raw, U256::from(MAX_POW_RELATIVE_ERROR))? + U256::ONE;
+    raw.checked_add(max_error)
+}
+
+// LogExpMath
+
+fn int(value: i128) -> I256 {
+    I256::unchecked_from(value)
+}
+
+const ONE_18: i128 = 1_000_000_000_000_000_000;
+const ONE_20: i128 = 100_000_000_000_000_000_000;
+static ONE_36: LazyLock<I256> = LazyLock::new(|| int(ONE_18) * int(ONE_18));
+
+const MAX_NATURAL_EXPONENT: i128 = 130 * ONE_18;
+const MIN_NATURAL_EXPONENT: i128 = -41 * ONE_18;
+const LN_36_LOWER_BOUND: i128 = ONE_18 - 100_000_000_000_000_000;
+const LN_36_UPPER_BOUND: i128 = ONE_18 + 100_000_000_000_000_000;
+
+// x0 = 2^7 and x1 = 2^6 in 18 decimals, with e^x0 and e^x1 as plain integers
+const X0: i128 = 128_000_000_000_000_000_000;
+static A0: LazyLock<I256> = LazyLock::new(|| {
+    "38877084059945950922200000000000000000000000000000000000"
+        .parse()
+        .unwrap()
+});
+const X1: i128 = 64_000_000_000_000_000_000;
+const A1: i128 = 6_235_149_080_811_616_882_910_000_000;
+
+// x2..x11 = 2^5..2^-4 in 20 decimals, with e^x in 20 decimals
+const XN: [(i128, i128); 10] = [
+    (
+        3_200_000_000_000_000_000_000,
+        7_896_296_018_268_069_516_100_000_000_000_000,
+    ),
+    (
+        1_600_000_000_000_000_000_000,
+        888_611_052_050_787_263_676_000_000,
+    ),
+    (800_000_000_000_000_000_000, 298_095_798_704_172_827_474_000),
+    (400_000_000_000_000_000_000, 5_459_815_003_314_423_907_810),
+    (200_000_000_000_000_000_000, 738_905_609_893_065_022_723),
+    (100_000_000_000_000_000_000, 271_828_182_845_904_523_536),
+    (50_000_000_000_000_000_000, 164_872_127_070_012_814_685),
+    (25_000_000_000_000_000_000, 128_402_541_668_774_148_407),
+    (12_500_000_000_000_000_000, 113_314_845_306_682_631_683),
+    (6_250_000_000_000_000_000, 106_449_445_891_785_942_956),
+];
+
+/// `LogExpMath.pow`: x^y for 18-decimal fixed point values.
+pub fn pow(x: U256, y: U256) -> Option<U256> {
+    if y.is_zero() {
+        return Some(U256::from(ONE));
+    }
+    if x.is_zero() {
+        return Some(U256::ZERO);
+    }
+    // x must fit in an int256 and y below 2^254 / 1e20
+    if x.bit(255) || y >= (U256::ONE << 254) / U256::from(ONE_20) {
+        return None;
+    }
+    let x = I256::from_raw(x);
+    let y = I256::from_raw(y);
+
+    let mut logx_times_y = if int(LN_36_LOWER_BOUND) < x && x < int(LN_36_UPPER_BOUND) {
+        let ln_36_x = ln_36(x);
+        (ln_36_x / int(ONE_18)) * y + ((ln_36_x % int(ONE_18)) * y) / int(ONE_18)
+    } else {
+        ln(x)? * y
+    };
+    logx_times_y /= int(ONE_18);
+
+    if logx_times_y < int(MIN_NATURAL_EXPONENT) || logx_times_y > int(MAX_NATURAL_EXPONENT) {
+        return None;
+    }
+    Some(exp(logx_times_y)?.into_raw())
+}
+
+/// `LogExpMath.exp`: e^x for an 18-decimal exponent.
+pub fn exp(x: I256) -> Option<I256> {
+    if x < int(MIN_NATURAL_EXPONENT) || x > int(MAX_NATURAL_EXPONENT) {
+        return None;
+    }
+    if x.is_negative() {
+        return Some(*ONE_36 / exp(-x)?);
+    }
+
+    let mut x = x;
+    let first_an = if x >= int(X0) {
+        x -= int(X0);
+        *A0
+    } else if x >= int(X1) {
+        x -= int(X1);
+        int(A1)
+    } else {
+        int(1)
+    };
+
+    // Switch to 20 decimals for the remaining terms
+    x *= int(100);
+
+    let mut product = int(ONE_20);
+    for (x_n, a_n) in &XN[..8] {
+        if x >= int(*x_n) {
+            x -= int(*x_n);
+            product = (product * int(*a_n)) / int(ONE_20);
+        }
+    }
+
+    // Taylor series for e^x with x < 2^-2, to the 12th term
+    let mut series_sum = int(ONE_20);
+    let mut term = x;
+    series_sum += term;
+    for k in 2..=12 {
+        term = ((term * x) / int(ONE_20)) / int(k);
+        series_sum += term;
+    }
+
+    Some((((product * series_sum) / int(ONE_20)) * first_an) / int(100))
+}
+
+/// `LogExpMath._ln`: natural log of an 18-decimal value.
+fn ln(a: I256) -> Option<I256> {
+    if !a.is_positive() {
+        return None;
+    }
+    if a < int(ONE_18) {
+        return Some(-ln(*ONE_36 / a)?);
+    }
+
+    let mut a = a;
+    let mut sum = I256::ZERO;
+    if a >= *A0 * int(ONE_18) {
+        a /= *A0;
+        sum += int(X0);
+    }
+    if a >= int(A1) * int(ONE_18) {
+        a /= int(A1);
+        sum += int(X1);
+    }
+
+    // Switch to 20 decimals for the remaining terms
+    sum *= int(100);
+    a *= int(100);
+
+    for (x_n, a_n) in &XN {
+        if a >= int(*a_n) {
+            a = (a * int(ONE_20)) / int(*a_n);
+            sum += int(*x_n);
+        }
+    }
+
+    // ln(a) = 2 * artanh(z) with z = (a - 1) / (a + 1), to the 11th power
+    let z = ((a - int(ONE_20)) * int(ONE_20)) / (a + int(ONE_20));
+    let z_squared = (z * z) / int(ONE_20);
+
+    let mut num = z;
+    let mut series_sum = num;
+    for k in [3, 5, 7, 9, 11] {
+        num = (num * z_squared) / int(ONE_20);
+        series_sum += num / int(k);
+    }
+    series_sum *= int(2);
+
+    Some((sum + series_sum) / int(100))
+}
+
+/// `LogExpMath._ln_36`: high-precision ln for values close to one, in 36 decimals.
+fn ln_36(x: I256) -> I256 {
+    let x = x * int(ONE_18);
+
+    let z = ((x - *ONE_36) * *ONE_36) / (x + *ONE_36);
+    let z_squared = (z * z) / *ONE_36;
+
+    let mut num = z;
+    let mut series_sum = num;
+    for k in [3, 5, 7, 9, 11, 13, 15] {
+        num = (num * z_squared) / *ONE_36;
+        series_sum += num / int(k);
+    }
+    series_sum * int(2)
+}
+
+// WeightedMath
+
+/// `WeightedMath._calcOutGivenIn` on upscaled (18-decimal) balances and amounts.
+pub fn calc_out_given_in(
+    balance_in: U256,
+    weight_in: U256,
+    balance_out: U256,
+    weight_out: U256,
+    amount_in: U256,
+) -> Option<U256> {
+    if amount_in > mul_down(balance_in, U256::from(MAX_IN_RATIO))? {
+        return None;
+    }
+
+    let denominator = balance_in.checked_add(amount_in)?;
+    let base = div_up(balance_in, denominator)?;
+    let exponent = div_down(weight_in, weight_out)?;
+    let power = pow_up(base, exponent)?;
+
+    mul_down(balance_out, complement(power))
+}
+
+#[cfg(test)]
+mod balancer_math_tests {
+    use super::*;
+
+    fn fp(value: &str) -> U256 {
+        U256::from_str_radix(value, 10).unwrap()
+    }
+
+    fn e18(numerator: u64, denominator: u64) -> U256 {
+        U256::from(numerator) * U256::from(10).pow(U256::from(18)) / U256::from(denominator)
+    }
+
+    // Expected values are exact integers from running Balancer's WeightedMath,
+    // LogExpMath and FixedPoint integer arithmetic, with Solidity's truncating signed
+    // division, outside this crate
+    #[test]
+    fn pow_matches_reference() {
+        assert_eq!(
+            pow(e18(2, 1), e18(1, 2)).unwrap(),
+            fp("1414213562373095047")
+        );
+        // On the lower bound of _ln_36, so through _ln
+        assert_eq!(
+            pow(e18(9, 10), e18(3, 2)).unwrap(),
+            fp("853814968245462420")
+        );
+    }
+
+    #[test]
+    fn calc_out_given_in_matches_reference() {
+        // 50/50: the exponent of one skips LogExpMath
+        let out = calc_out_given_in(e18(100, 1), e18(1, 2), e18(100, 1), e18(1, 2), e18(10, 1));
+        assert_eq!(out.unwrap(), fp("9090909090909090900"));
+
+        // 20/80, exponent 0.25
+        let out = calc_out_given_in(e18(50, 1), e18(1, 5), e18(1000, 1), e18(4, 5), e18(1, 1));
+        assert_eq!(out.unwrap(), fp("4938422520146689000"));
+
+        // 60/40, exponent 1.5, with the base close to one (_ln_36) and far from it (_ln)
+        let out = calc_out_given_in(e18(1000, 1), e18(3, 5), e18(500, 1), e18(2, 5), e18(10, 1));
+        assert_eq!(out.unwrap(), fp("7407331579208371500"));
+        let out = calc_out_given_in(e18(1000, 1), e18(3, 5), e18(500, 1), e18(2, 5), e18(250, 1));
+        assert_eq!(out.unwrap(), fp("142229123600030069500"));
+
+        // Over the 30% max in ratio
+        assert!(
+            calc_out_given_in(e18(1000, 1), e18(3, 5), e18(500, 1), e18(2, 5), e18(301, 1))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn pow_matches_float() {
+        let cases = [
+            (0.5, 0.25),
+            (0.9, 4.0),
+            (1.23, 0.3),
+            (2.0, 1.0),
+            (1.001, 0.7),
+        ];
+        for (x, y) in cases {
+            let x_fp = U256::from((x * 1e18) as u128);
+            let y_fp = U256::from((y * 1e18) as u128);
+            let result = pow(x_fp, y_fp).unwrap().to::<u128>() as f64 / 1e18;
+            let expected: f64 = f64::powf(x, y);
+            assert!(
+                (result - expected).abs() < 1e-12,
+                "{x}^{y}: {result} != {expected}"
+            );
+        }
+    }
+}
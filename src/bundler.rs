@@ -1,28 +1,26 @@
-use anyhow::{anyhow, Result};
-use ethers::prelude::*;
-use ethers::types::{
-    transaction::{eip2718::TypedTransaction, eip2930::AccessList},
-    Address, Eip1559TransactionRequest, U256,
-};
-use ethers::{
-    abi,
-    middleware::MiddlewareBuilder,
-    providers::{Http, Middleware, Provider},
-    signers::{LocalWallet, Signer},
+use alloy::{
+    dyn_abi::DynSolValue,
+    eips::{eip2718::Encodable2718, eip2930::AccessList},
+    network::{EthereumWallet, TransactionBuilder},
+    primitives::{Address, Bytes, TxKind, B256, U256},
+    providers::{DynProvider, Provider, ProviderBuilder},
+    rpc::types::{TransactionInput, TransactionRequest},
+    signers::{local::PrivateKeySigner, Signer},
+    sol,
 };
-use ethers_flashbots::*;
-use std::{str::FromStr, sync::Arc};
-use url::Url;
+use anyhow::{anyhow, Result};
+use std::{str::FromStr, time::Duration};
 
 use crate::constants::Env;
+use crate::flashbots::{BundleRequest, FlashbotsRelay, FLASHBOTS_RELAY_URL};
 
-abigen!(
-    ArbBot,
-    r#"[
+sol! {
+    #[sol(rpc)]
+    interface ArbBot {
         function recoverToken(address token) external;
         function approveRouter(address router, address[] memory tokens, bool force) external;
-    ]"#,
-);
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct PathParam {
@@ -32,11 +30,11 @@ pub struct PathParam {
 }
 
 impl PathParam {
-    pub fn make_params(&self) -> Vec<abi::Token> {
+    pub fn make_params(&self) -> Vec<DynSolValue> {
         vec![
-            abi::Token::Address(self.router.into()),
-            abi::Token::Address(self.token_in.into()),
-            abi::Token::Address(self.token_out.into()),
+            DynSolValue::Address(self.router),
+            DynSolValue::Address(self.token_in),
+            DynSolValue::Address(self.token_out),
         ]
     }
 }
@@ -48,91 +46,78 @@ pub enum Flashloan {
     UniswapV2 = 2,
 }
 
-type SignerProvider = SignerMiddleware<Provider<Http>, LocalWallet>;
-
 pub struct Bundler {
     pub env: Env,
-    pub sender: LocalWallet,
-    pub bot: ArbBot<SignerProvider>,
-    pub provider: SignerProvider,
-    pub flashbots: SignerMiddleware<FlashbotsMiddleware<SignerProvider, LocalWallet>, LocalWallet>,
+    pub sender: PrivateKeySigner,
+    pub bot: ArbBot::ArbBotInstance<DynProvider>,
+    pub provider: DynProvider,
+    pub flashbots: FlashbotsRelay,
 }
 
 impl Bundler {
-    pub fn new<'a>() -> Self {
-        let env = Env::new();
+    /// Builds the signers, provider and Flashbots relay client from the environment.
+    /// Fails if a setting is missing or a key or address doesn't parse.
+    pub fn new() -> Result<Self> {
+        let env = Env::new()?;
 
         let sender = env
             .private_key
-            .parse::<LocalWallet>()
-            .unwrap()
-            .with_chain_id(env.chain_id.as_u64());
+            .parse::<PrivateKeySigner>()?
+            .with_chain_id(Some(env.chain_id));
         let signer = env
             .signing_key
-            .parse::<LocalWallet>()
-            .unwrap()
-            .with_chain_id(env.chain_id.as_u64());
-
-        let provider = Provider::<Http>::try_from(&env.https_url)
-            .unwrap()
-            .with_signer(sender.clone());
-
-        let flashbots = SignerMiddleware::new(
-            FlashbotsMiddleware::new(
-                provider.clone(),
-                Url::parse("https://relay.flashbots.net").unwrap(),
-                signer,
-            ),
-            sender.clone(),
-        );
-
-        let client = Arc::new(provider.clone());
-        let bot = ArbBot::new(env.bot_address.parse::<Address>().unwrap(), client.clone());
-
-        Self {
+            .parse::<PrivateKeySigner>()?
+            .with_chain_id(Some(env.chain_id));
+
+        let provider = ProviderBuilder::new()
+            .wallet(EthereumWallet::from(sender.clone()))
+            .connect_http(env.https_url.parse()?)
+            .erased();
+
+        let flashbots = FlashbotsRelay::new(FLASHBOTS_RELAY_URL, signer);
+
+        let bot = ArbBot::new(env.bot_address.parse::<Address>()?, provider.clone());
+
+        Ok(Self {
             env,
             sender,
             bot,
-            provider: provider,
-            flashbots: flashbots,
-        }
+            provider,
+            flashbots,
+        })
     }
 
-    pub async fn _common_fields(&self) -> Result<(H160, U256, U64)> {
+    pub async fn _common_fields(&self) -> Result<(Address, u64, u64)> {
         let nonce = self
             .provider
-            .get_transaction_count(self.sender.address(), None)
+            .get_transaction_count(self.sender.address())
             .await?;
-        Ok((self.sender.address(), U256::from(nonce), self.env.chain_id))
+        Ok((self.sender.address(), nonce, self.env.chain_id))
     }
 
-    pub async fn sign_tx(&self, tx: Eip1559TransactionRequest) -> Result<Bytes> {
-        let typed = TypedTransaction::Eip1559(tx);
-        let signature = self.sender.sign_transaction(&typed).await?;
-        let signed = typed.rlp_signed(&signature);
-        Ok(signed)
+    pub async fn sign_tx(&self, tx: TransactionRequest) -> Result<Bytes> {
+        let wallet = EthereumWallet::from(self.sender.clone());
+        let signed = tx.build(&wallet).await?;
+        Ok(Bytes::from(signed.encoded_2718()))
     }
 
-    pub fn to_bundle<T: Into<BundleTransaction>>(
+    pub fn to_bundle<T: Into<Bytes>>(
         &self,
         signed_txs: Vec<T>,
-        block_number: U64,
+        block_number: u64,
     ) -> BundleRequest {
-        let mut bundle = BundleRequest::new();
-
-        for tx in signed_txs {
-            let bundle_tx: BundleTransaction = tx.into();
-            bundle = bundle.push_transaction(bundle_tx);
+        BundleRequest {
+            transactions: signed_txs.into_iter().map(Into::into).collect(),
+            block: block_number + 1,
+            simulation_block: block_number,
+            simulation_timestamp: Some(0),
         }
-
-        bundle
-            .set_block(block_number + 1)
-            .set_simulation_block(block_number)
-            .set_simulation_timestamp(0)
     }
 
-    pub async fn send_bundle(&self, bundle: BundleRequest) -> Result<TxHash> {
-        let simulated = self.flashbots.inner().simulate_bundle(&bundle).await?;
+    /// Simulates the bundle, sends it if every transaction succeeds and waits for its
+    /// target block. Fails unless all of the bundle's transactions landed in that block.
+    pub async fn send_bundle(&self, bundle: BundleRequest) -> Result<B256> {
+        let simulated = self.flashbots.simulate_bundle(&bundle).await?;
 
         for tx in &simulated.transactions {
             if let Some(e) = &tx.error {
@@ -143,14 +128,35 @@ impl Bundler {
             }
         }
 
-        let pending_bundle = self.flashbots.inner().send_bundle(&bundle).await?;
-        let bundle_hash = pending_bundle.await?.expect("bundler error");
+        let bundle_hash = self.flashbots.send_bundle(&bundle).await?;
+        self._wait_for_inclusion(&bundle).await?;
         Ok(bundle_hash)
     }
 
-    pub async fn send_tx(&self, tx: Eip1559TransactionRequest) -> Result<TxHash> {
-        let pending_tx = self.provider.send_transaction(tx, None).await?;
-        let receipt = pending_tx.await?.ok_or_else(|| anyhow!("Tx dropped"))?;
+    async fn _wait_for_inclusion(&self, bundle: &BundleRequest) -> Result<()> {
+        while self.provider.get_block_number().await? < bundle.block {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        let block = self
+            .provider
+            .get_block_by_number(bundle.block.into())
+            .await?
+            .ok_or_else(|| anyhow!("block {} not found", bundle.block))?;
+        let mined: Vec<B256> = block.transactions.hashes().collect();
+        if bundle
+            .transaction_hashes()
+            .iter()
+            .all(|hash| mined.contains(hash))
+        {
+            Ok(())
+        } else {
+            Err(anyhow!("Bundle not included in block {}", bundle.block))
+        }
+    }
+
+    pub async fn send_tx(&self, tx: TransactionRequest) -> Result<B256> {
+        let pending_tx = self.provider.send_transaction(tx).await?;
+        let receipt = pending_tx.get_receipt().await?;
         Ok(receipt.transaction_hash)
     }
 
@@ -159,20 +165,21 @@ impl Bundler {
         amount_in: U256,
         max_priority_fee_per_gas: U256,
         max_fee_per_gas: U256,
-    ) -> Result<Eip1559TransactionRequest> {
+    ) -> Result<TransactionRequest> {
         let common = self._common_fields().await?;
-        let to = NameOrAddress::Address(H160::from_str(&self.env.bot_address).unwrap());
-        Ok(Eip1559TransactionRequest {
+        let to = TxKind::Call(Address::from_str(&self.env.bot_address).unwrap());
+        Ok(TransactionRequest {
             to: Some(to),
             from: Some(common.0),
-            data: Some(Bytes(bytes::Bytes::new())),
+            input: TransactionInput::new(Bytes::new()),
             value: Some(amount_in),
             chain_id: Some(common.2),
-            max_priority_fee_per_gas: Some(max_priority_fee_per_gas),
-            max_fee_per_gas: Some(max_fee_per_gas),
-            gas: Some(U256::from(60000)),
+            max_priority_fee_per_gas: Some(max_priority_fee_per_gas.to::<u128>()),
+            max_fee_per_gas: Some(max_fee_per_gas.to::<u128>()),
+            gas: Some(60000),
             nonce: Some(common.1),
-            access_list: AccessList::default(),
+            access_list: Some(AccessList::default()),
+            ..Default::default()
         })
     }
 
@@ -181,23 +188,24 @@ impl Bundler {
         token: &str,
         max_priority_fee_per_gas: U256,
         max_fee_per_gas: U256,
-    ) -> Result<Eip1559TransactionRequest> {
+    ) -> Result<TransactionRequest> {
         let token_address = Address::from_str(token).unwrap();
-        let calldata = self.bot.encode("recoverToken", (token_address,))?;
+        let calldata = self.bot.recoverToken(token_address).calldata().clone();
 
         let common = self._common_fields().await?;
-        let to = NameOrAddress::Address(H160::from_str(&self.env.bot_address).unwrap());
-        Ok(Eip1559TransactionRequest {
+        let to = TxKind::Call(Address::from_str(&self.env.bot_address).unwrap());
+        Ok(TransactionRequest {
             to: Some(to),
             from: Some(common.0),
-            data: Some(calldata),
-            value: Some(U256::zero()),
+            input: TransactionInput::new(calldata),
+            value: Some(U256::ZERO),
             chain_id: Some(common.2),
-            max_priority_fee_per_gas: Some(max_priority_fee_per_gas),
-            max_fee_per_gas: Some(max_fee_per_gas),
-            gas: Some(U256::from(50000)),
+            max_priority_fee_per_gas: Some(max_priority_fee_per_gas.to::<u128>()),
+            max_fee_per_gas: Some(max_fee_per_gas.to::<u128>()),
+            gas: Some(50000),
             nonce: Some(common.1),
-            access_list: AccessList::default(),
+            access_list: Some(AccessList::default()),
+            ..Default::default()
         })
     }
 
@@ -208,7 +216,7 @@ impl Bundler {
         force: bool,
         max_priority_fee_per_gas: U256,
         max_fee_per_gas: U256,
-    ) -> Result<Eip1559TransactionRequest> {
+    ) -> Result<TransactionRequest> {
         let router_address = Address::from_str(router).unwrap();
         let token_addresses: Vec<Address> = tokens
             .iter()
@@ -216,22 +224,25 @@ impl Bundler {
             .collect();
         let calldata = self
             .bot
-            .encode("approveRouter", (router_address, token_addresses, force))?;
+            .approveRouter(router_address, token_addresses, force)
+            .calldata()
+            .clone();
 
-        let token_cnt = tokens.len();
+        let token_cnt = tokens.len() as u64;
         let common = self._common_fields().await?;
-        let to = NameOrAddress::Address(H160::from_str(&self.env.bot_address).unwrap());
-        Ok(Eip1559TransactionRequest {
+        let to = TxKind::Call(Address::from_str(&self.env.bot_address).unwrap());
+        Ok(TransactionRequest {
             to: Some(to),
             from: Some(common.0),
-            data: Some(calldata),
-            value: Some(U256::zero()),
+            input: TransactionInput::new(calldata),
+            value: Some(U256::ZERO),
             chain_id: Some(common.2),
-            max_priority_fee_per_gas: Some(max_priority_fee_per_gas),
-            max_fee_per_gas: Some(max_fee_per_gas),
-            gas: Some(U256::from(55000) * U256::from(token_cnt)),
+            max_priority_fee_per_gas: Some(max_priority_fee_per_gas.to::<u128>()),
+            max_fee_per_gas: Some(max_fee_per_gas.to::<u128>()),
+            gas: Some(55000 * token_cnt),
             nonce: Some(common.1),
-            access_list: AccessList::default(),
+            access_list: Some(AccessList::default()),
+            ..Default::default()
         })
     }
 
@@ -243,36 +254,37 @@ impl Bundler {
         loan_from: Address,
         max_priority_fee_per_gas: U256,
         max_fee_per_gas: U256,
-    ) -> Result<Eip1559TransactionRequest> {
+    ) -> Result<TransactionRequest> {
         let nhop = paths.len();
 
         let mut params = Vec::new();
         params.extend(vec![
-            abi::Token::Uint(amount_in),
-            abi::Token::Uint(U256::from(flashloan as u64)),
-            abi::Token::Address(loan_from),
+            DynSolValue::Uint(amount_in, 256),
+            DynSolValue::Uint(U256::from(flashloan as u64), 256),
+            DynSolValue::Address(loan_from),
         ]);
 
         for i in 0..nhop {
             params.extend(paths[i].make_params());
         }
 
-        let encoded = abi::encode(&params);
+        let encoded = DynSolValue::Tuple(params).abi_encode_params();
         let calldata = Bytes::from(encoded);
 
         let common = self._common_fields().await?;
-        let to = NameOrAddress::Address(H160::from_str(&self.env.bot_address).unwrap());
-        Ok(Eip1559TransactionRequest {
+        let to = TxKind::Call(Address::from_str(&self.env.bot_address).unwrap());
+        Ok(TransactionRequest {
             to: Some(to),
             from: Some(common.0),
-            data: Some(calldata),
-            value: Some(U256::zero()),
+            input: TransactionInput::new(calldata),
+            value: Some(U256::ZERO),
             chain_id: Some(common.2),
-            max_priority_fee_per_gas: Some(max_priority_fee_per_gas),
-            max_fee_per_gas: Some(max_fee_per_gas),
-            gas: Some(U256::from(600000)),
+            max_priority_fee_per_gas: Some(max_priority_fee_per_gas.to::<u128>()),
+            max_fee_per_gas: Some(max_fee_per_gas.to::<u128>()),
+            gas: Some(600000),
             nonce: Some(common.1),
-            access_list: AccessList::default(),
+            access_list: Some(AccessList::default()),
+            ..Default::default()
         })
     }
 }
@@ -284,7 +296,7 @@ mod bundler_tests {
 
     #[tokio::test]
     async fn bundler_test() {
-        let bundler = Bundler::new();
+        let bundler = Bundler::new().unwrap();
 
         let tx = bundler
             .transfer_in_tx(
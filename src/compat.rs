@@ -0,0 +1,38 @@
+//! Conversions between the alloy primitives the crate is built on and their ethers
+//! equivalents, for callers that still hold ethers types. Only built with the `ethers`
+//! feature.
+use alloy::primitives::{Address, U256 as AlloyU256};
+use ethers::types::{H160, U256};
+
+pub fn to_h160(address: &Address) -> H160 {
+    H160::from_slice(address.as_slice())
+}
+
+pub fn to_address(address: &H160) -> Address {
+    Address::from_slice(address.as_bytes())
+}
+
+pub fn to_u256(value: &AlloyU256) -> U256 {
+    U256::from_big_endian(&value.to_be_bytes::<32>())
+}
+
+pub fn to_alloy_u256(value: &U256) -> AlloyU256 {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    AlloyU256::from_be_bytes(bytes)
+}
+
+#[cfg(test)]
+mod compat_tests {
+    use super::*;
+
+    #[test]
+    fn conversions_round_trip() {
+        let address = H160::repeat_byte(0xab);
+        assert_eq!(to_h160(&to_address(&address)), address);
+
+        let value = U256::MAX - U256::from(12345);
+        assert_eq!(to_u256(&to_alloy_u256(&value)), value);
+        assert_eq!(to_alloy_u256(&U256::from(7)), AlloyU256::from(7));
+    }
+}
@@ -1,35 +1,59 @@
-use alloy::primitives::{address, Address as HexAddress};
-use ethers::{
-    abi::Tokenizable,
-    prelude::Lazy,
-    types::{Address, H160, U256, U64},
-};
-use std::{ops::{Add, Mul}, str::FromStr};
-
-pub const UNISWAP_V2_FACTORY_ADDRESS: HexAddress =
+use alloy::primitives::{address, Address, U256};
+use anyhow::{anyhow, Result};
+use std::{str::FromStr, sync::LazyLock};
+
+use crate::pools::V2Factory;
+
+pub const UNISWAP_V2_FACTORY_ADDRESS: Address =
     address!("5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f");
-pub const UNISWAP_V3_FACTORY_ADDRESS: HexAddress =
+pub const UNISWAP_V3_FACTORY_ADDRESS: Address =
     address!("1F98431c8aD98523631AE4a59f267346ea31F984");
-pub const WETH_ADDRESS: HexAddress = address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
-pub const USDC_ADDRESS: HexAddress = address!("A0b86991c6218b36c1d19d4a2e9eb0ce3606eb48");
-pub const USDT_ADDRESS: HexAddress = address!("dAC17F958D2ee523a2206206994597C13D831ec7");
-pub const DAI_ADDRESS: HexAddress = address!("6B175474E89094C44Da98b954EedeAC495271d0F");
-pub const WBTC_ADDRESS: HexAddress = address!("2260FAC5E5542a773Aa44fBCfeDf7C193bc2C599");
+pub const WETH_ADDRESS: Address = address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+pub const USDC_ADDRESS: Address = address!("A0b86991c6218b36c1d19d4a2e9eb0ce3606eb48");
+pub const USDT_ADDRESS: Address = address!("dAC17F958D2ee523a2206206994597C13D831ec7");
+pub const DAI_ADDRESS: Address = address!("6B175474E89094C44Da98b954EedeAC495271d0F");
+pub const WBTC_ADDRESS: Address = address!("2260FAC5E5542a773Aa44fBCfeDf7C193bc2C599");
 pub const MIN_WETH_THRESHOLD: u128 = 10u128.pow(19); // 10 WETH (18 decimals)
+// Blocks between re-runs of the pool liquidity filter (about an hour)
+pub const LIQUIDITY_FILTER_REFRESH_BLOCKS: u64 = 300;
+// Blocks of reserve diffs kept for rolling back reorgs
+pub const REORG_HISTORY_DEPTH: usize = 64;
 pub const WETH_AMOUNT_IN: u128 = 5_800_000_000_000_000;
+// Tick bitmap words fetched on each side of a V3 pool's current tick
+pub const V3_TICK_WORD_RADIUS: i16 = 2;
+// Priority fee on backrun transactions, in gwei
+pub const BACKRUN_PRIORITY_FEE_GWEI: u64 = 2;
 
 
-pub static WEI: Lazy<U256> = Lazy::new(|| U256::from(10).pow(U256::from(18)));
-pub static GWEI: Lazy<U256> = Lazy::new(|| U256::from(10).pow(U256::from(9)));
+// Curve main registry, which lists the StableSwap pools and exposes their state
+pub const CURVE_REGISTRY_ADDRESS: &str = "0x90E00ACe148ca3b23Ac1bC8C240C2a7Dd9c2d7f5";
+// Uniswap V2's router, and SwapRouter02, which swaps through the same V2 pairs
+pub const UNISWAP_V2_ROUTER_ADDRESS: &str = "0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D";
+pub const SWAP_ROUTER_02_ADDRESS: &str = "0x68b3465833fb72A70ecDF485E0e4C7bD8665Fc45";
+// Balancer V2 Vault, which also emits PoolRegistered for every pool it hosts
+pub const BALANCER_VAULT_ADDRESS: &str = "0xBA12222222228d8Ba445958a75a0704d566BF2C8";
+pub const BALANCER_VAULT_DEPLOY_BLOCK: u64 = 12272146;
+// Binary snapshot of loaded pools and generated paths, reused across restarts
+pub const PATH_SNAPSHOT_PATH: &str = "src/.path-snapshot.bin";
+// Age at which the snapshot is rebuilt, so pools too shallow to enter a path get re-valued
+pub const PATH_SNAPSHOT_MAX_AGE_SECS: u64 = 86_400;
+
+pub static WEI: LazyLock<U256> = LazyLock::new(|| U256::from(10).pow(U256::from(18)));
+pub static GWEI: LazyLock<U256> = LazyLock::new(|| U256::from(10).pow(U256::from(9)));
 pub static DUNE_QUERY_ID: u32 = 6572025;
+pub const DUNE_API_URL: &str = "https://api.dune.com/api/v1";
 
-pub static ZERO_ADDRESS: Lazy<Address> =
-    Lazy::new(|| Address::from_str("0x0000000000000000000000000000000000000000").unwrap());
+pub static ZERO_ADDRESS: LazyLock<Address> =
+    LazyLock::new(|| Address::from_str("0x0000000000000000000000000000000000000000").unwrap());
 
 pub fn get_env(key: &str) -> String {
     std::env::var(key).unwrap()
 }
 
+fn require_env(key: &str) -> Result<String> {
+    std::env::var(key).map_err(|_| anyhow!("{} is not set", key))
+}
+
 // pub struct Erc20Addresses {
 //     weth: Address,
 //     usdc: Address,
@@ -46,29 +70,50 @@ pub fn get_env(key: &str) -> String {
 pub struct Env {
     pub https_url: String,
     pub wss_url: String,
-    pub chain_id: U64,
+    /// Every endpoint in `WSS_URL`, which may list several separated by commas.
+    /// `wss_url` is the first of them.
+    pub wss_urls: Vec<String>,
+    pub chain_id: u64,
     pub private_key: String,
     pub signing_key: String,
     pub bot_address: String,
     pub dune_api_key: String,
+    /// Backrun pending router swaps; off unless `BACKRUN=true`.
+    pub backrun: bool,
 }
 
 impl Env {
-    pub fn new() -> Self {
-        Env {
-            https_url: get_env("HTTPS_URL"),
-            wss_url: get_env("WSS_URL"),
-            chain_id: U64::from_str(&get_env("CHAIN_ID")).unwrap(),
-            private_key: get_env("PRIVATE_KEY"),
-            signing_key: get_env("SIGNING_KEY"),
-            bot_address: get_env("BOT_ADDRESS"),
-            dune_api_key: get_env("DUNE_API_KEY"),
-        }
+    /// Reads the settings from the environment. Fails if one is missing or invalid, or
+    /// if `WSS_URL` lists no endpoint.
+    pub fn new() -> Result<Self> {
+        let wss_urls: Vec<String> = require_env("WSS_URL")?
+            .split(',')
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty())
+            .collect();
+        let wss_url = wss_urls
+            .first()
+            .cloned()
+            .ok_or_else(|| anyhow!("WSS_URL lists no endpoint"))?;
+        let chain_id = require_env("CHAIN_ID")?;
+        Ok(Env {
+            https_url: require_env("HTTPS_URL")?,
+            wss_url,
+            wss_urls,
+            chain_id: chain_id
+                .parse()
+                .map_err(|e| anyhow!("invalid CHAIN_ID {:?}: {:?}", chain_id, e))?,
+            private_key: require_env("PRIVATE_KEY")?,
+            signing_key: require_env("SIGNING_KEY")?,
+            bot_address: require_env("BOT_ADDRESS")?,
+            dune_api_key: require_env("DUNE_API_KEY")?,
+            backrun: std::env::var("BACKRUN").is_ok_and(|flag| flag == "true"),
+        })
     }
 }
 
 
-pub const WHITELIST_TOKENS: [HexAddress; 5] = [
+pub const WHITELIST_TOKENS: [Address; 5] = [
     WETH_ADDRESS,
     USDT_ADDRESS,
     USDC_ADDRESS,
@@ -77,10 +122,44 @@ pub const WHITELIST_TOKENS: [HexAddress; 5] = [
 ];
 
 
-pub fn get_blacklist_tokens() -> Vec<H160> {
+/// V2-fork factories whose pairs are loaded, each with its router, pair fee and deploy block.
+pub fn get_v2_factories() -> Vec<V2Factory> {
+    vec![
+        // Uniswap V2
+        V2Factory::new(
+            "0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f",
+            UNISWAP_V2_ROUTER_ADDRESS,
+            3000,
+            10000835,
+        ),
+        // SushiSwap
+        V2Factory::new(
+            "0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac",
+            "0xd9e1cE17f2641f24aE83637ab66a2cca9C378B9F",
+            3000,
+            10794229,
+        ),
+        // ShibaSwap
+        V2Factory::new(
+            "0x115934131916C8b277DD010Ee02de363c09d037c",
+            "0x03f7724180AA6b939894B5Ca4314783B0b36b329",
+            3000,
+            12771526,
+        ),
+        // PancakeSwap V2 (Ethereum)
+        V2Factory::new(
+            "0x1097053Fd2ea711dad45caCcc45EfF7548fCB362",
+            "0xEfF92A263d31888d860bD50809A8D171709b7b1c",
+            2500,
+            15614590,
+        ),
+    ]
+}
+
+pub fn get_blacklist_tokens() -> Vec<Address> {
     vec!["0x9469603F3Efbcf17e4A5868d81C701BDbD222555"]
         .into_iter()
-        .map(|addr| H160::from_str(addr).unwrap())
+        .map(|addr| Address::from_str(addr).unwrap())
         .collect()
 }
 
@@ -0,0 +1,317 @@
+use alloy::primitives::{Address, U256};
+use std::collections::HashMap;
+
+use crate::multi::PoolState;
+use crate::paths::{ArbPath, Hop};
+use crate::pools::Pool;
+use crate::simulator::CurveStableSimulator;
+use crate::v3_math::FEE_PIPS_DENOMINATOR;
+
+// Ignore relaxations smaller than this so float noise doesn't show up as a cycle
+const RELAX_EPSILON: f64 = 1e-12;
+
+#[derive(Debug, Clone)]
+pub struct RateEdge {
+    pub pool_idx: usize,
+    pub from: usize,
+    pub to: usize,
+    pub zero_for_one: bool,
+    pub weight: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct NegativeCycle {
+    pub edges: Vec<RateEdge>,
+    pub tokens: Vec<Address>,
+}
+
+impl NegativeCycle {
+    /// Sum of edge weights; the cycle's gross rate (before price impact) is `exp(-weight)`.
+    pub fn weight(&self) -> f64 {
+        self.edges.iter().map(|edge| edge.weight).sum()
+    }
+
+    pub fn contains_token(&self, token: &Address) -> bool {
+        self.tokens.contains(token)
+    }
+}
+
+/// Weighted token graph where every pool contributes one edge per direction,
+/// with weight `-ln(rate after fee)`. A negative cycle is a sequence of swaps whose
+/// marginal rates multiply to more than one.
+pub struct RateGraph<'a> {
+    pub pools: &'a [Pool],
+    pub tokens: Vec<Address>,
+    pub token_index: HashMap<Address, usize>,
+    pub edges: Vec<RateEdge>,
+}
+
+impl<'a> RateGraph<'a> {
+    pub fn from_reserves(pools: &'a [Pool], reserves: &HashMap<Address, PoolState>) -> Self {
+        let mut graph = Self {
+            pools,
+            tokens: Vec::new(),
+            token_index: HashMap::new(),
+            edges: Vec::new(),
+        };
+
+        for (idx, pool) in pools.iter().enumerate() {
+            if pool.token0 == pool.token1 {
+                continue;
+            }
+            // Fraction kept after fees; `Pool.fee` is in pips for every variant
+            let fee_factor = 1.0 - pool.fee as f64 / FEE_PIPS_DENOMINATOR as f64;
+            // Marginal token1-per-token0 price before fees
+            let price = match reserves.get(&pool.address) {
+                Some(PoolState::UniswapV2(reserve)) => {
+                    if reserve.reserve0.is_zero() || reserve.reserve1.is_zero() {
+                        continue;
+                    }
+                    u256_to_f64(reserve.reserve1) / u256_to_f64(reserve.reserve0)
+                }
+                Some(PoolState::UniswapV3(state)) => {
+                    if state.liquidity == 0 || state.sqrt_price_x96.is_zero() {
+                        continue;
+                    }
+                    let sqrt_price = u256_to_f64(state.sqrt_price_x96) / 2f64.powi(96);
+                    sqrt_price * sqrt_price
+                }
+                Some(PoolState::CurveStable(state)) => {
+                    // No closed-form spot price, so quote a swap of 1e-6 of the pool's balance
+                    let i = match state.coins.iter().position(|coin| *coin == pool.token0) {
+                        Some(i) => i,
+                        None => continue,
+                    };
+                    let dx = state.balances[i] / U256::from(1_000_000);
+                    let dy =
+                        CurveStableSimulator::get_amount_out(state, dx, pool.token0, pool.token1);
+                    match dy {
+                        // The quote already includes the fee; back it out so it's charged
+                        // symmetrically in both directions below
+                        Some(dy) if !dx.is_zero() && !dy.is_zero() => {
+                            u256_to_f64(dy) / u256_to_f64(dx) / fee_factor
+                        }
+                        _ => continue,
+                    }
+                }
+                Some(PoolState::BalancerWeighted(state)) => {
+                    let i = state.tokens.iter().position(|token| *token == pool.token0);
+                    let j = state.tokens.iter().position(|token| *token == pool.token1);
+                    let (i, j) = match (i, j) {
+                        (Some(i), Some(j)) => (i, j),
+                        _ => continue,
+                    };
+                    // Spot price of a weighted pool: (B_out / W_out) / (B_in / W_in)
+                    let (b0, b1) = (
+                        u256_to_f64(state.balances[i]),
+                        u256_to_f64(state.balances[j]),
+                    );
+                    let (w0, w1) = (u256_to_f64(state.weights[i]), u256_to_f64(state.weights[j]));
+                    if b0 == 0.0 || w1 == 0.0 {
+                        continue;
+                    }
+                    (b1 / w1) / (b0 / w0)
+                }
+                None => continue,
+            };
+            if fee_factor <= 0.0 || price <= 0.0 || !price.is_finite() {
+                continue;
+            }
+
+            let t0 = graph._token_idx(pool.token0);
+            let t1 = graph._token_idx(pool.token1);
+            graph.edges.push(RateEdge {
+                pool_idx: idx,
+                from: t0,
+                to: t1,
+                zero_for_one: true,
+                weight: -(price * fee_factor).ln(),
+            });
+            graph.edges.push(RateEdge {
+                pool_idx: idx,
+                from: t1,
+                to: t0,
+                zero_for_one: false,
+                weight: -(fee_factor / price).ln(),
+            });
+        }
+
+        graph
+    }
+
+    fn _token_idx(&mut self, token: Address) -> usize {
+        if let Some(idx) = self.token_index.get(&token) {
+            return *idx;
+        }
+        let idx = self.tokens.len();
+        self.tokens.push(token);
+        self.token_index.insert(token, idx);
+        idx
+    }
+
+    /// Bellman-Ford from `source`, returning one negative cycle reachable from it.
+    /// The returned cycle does not necessarily pass through `source`.
+    pub fn find_negative_cycle(&self, source: Address) -> Option<NegativeCycle> {
+        let source = *self.token_index.get(&source)?;
+        let n = self.tokens.len();
+
+        let mut dist = vec![f64::INFINITY; n];
+        let mut pred: Vec<Option<usize>> = vec![None; n];
+        dist[source] = 0.0;
+
+        for _ in 0..n.saturating_sub(1) {
+            let mut relaxed = false;
+            for (edge_idx, edge) in self.edges.iter().enumerate() {
+                if dist[edge.from].is_infinite() {
+                    continue;
+                }
+                let candidate = dist[edge.from] + edge.weight;
+                if candidate < dist[edge.to] - RELAX_EPSILON {
+                    dist[edge.to] = candidate;
+                    pred[edge.to] = Some(edge_idx);
+                    relaxed = true;
+                }
+            }
+            if !relaxed {
+                return None;
+            }
+        }
+
+        for (edge_idx, edge) in self.edges.iter().enumerate() {
+            if dist[edge.from].is_infinite() {
+                continue;
+            }
+            if dist[edge.from] + edge.weight < dist[edge.to] - RELAX_EPSILON {
+                pred[edge.to] = Some(edge_idx);
+                return self._extract_cycle(edge.to, &pred);
+            }
+        }
+
+        None
+    }
+
+    /// Finds a negative cycle through `base_token` and rotates it to start there,
+    /// so it can be simulated and bundled like any generated path.
+    pub fn find_arb_path(&self, base_token: Address) -> Option<ArbPath> {
+        let cycle = self.find_negative_cycle(base_token)?;
+        self.to_arb_path(&cycle, base_token)
+    }
+
+    pub fn to_arb_path(&self, cycle: &NegativeCycle, start_token: Address) -> Option<ArbPath> {
+        let start = *self.token_index.get(&start_token)?;
+        let offset = cycle.edges.iter().position(|edge| edge.from == start)?;
+
+        let hops = cycle
+            .edges
+            .iter()
+            .cycle()
+            .skip(offset)
+            .take(cycle.edges.len())
+            .map(|edge| Hop::new(self.pools[edge.pool_idx].clone(), edge.zero_for_one))
+            .collect();
+        Some(ArbPath::new(hops))
+    }
+
+    fn _extract_cycle(&self, from: usize, pred: &[Option<usize>]) -> Option<NegativeCycle> {
+        // Walk back n steps to guarantee we are standing on the cycle itself
+        let mut on_cycle = from;
+        for _ in 0..self.tokens.len() {
+            on_cycle = self.edges[pred[on_cycle]?].from;
+        }
+
+        let mut edges = Vec::new();
+        let mut current = on_cycle;
+        loop {
+            let edge = &self.edges[pred[current]?];
+            edges.push(edge.clone());
+            current = edge.from;
+            if current == on_cycle {
+                break;
+            }
+        }
+        edges.reverse();
+
+        let tokens = edges.iter().map(|edge| self.tokens[edge.from]).collect();
+        Some(NegativeCycle { edges, tokens })
+    }
+}
+
+fn u256_to_f64(value: U256) -> f64 {
+    // sqrtPriceX96 can use up to 160 bits, so avoid as_u128 panicking
+    if value.bit_len() <= 128 {
+        value.to::<u128>() as f64
+    } else {
+        let shift = value.bit_len() - 64;
+        ((value >> shift).to::<u64>() as f64) * 2f64.powi(shift as i32)
+    }
+}
+
+#[cfg(test)]
+mod cycles_tests {
+    use super::*;
+    use crate::multi::Reserve;
+    use crate::pools::DexVariant;
+
+    fn token(byte: u8) -> Address {
+        Address::repeat_byte(byte)
+    }
+
+    fn pool(address: u8, token0: u8, token1: u8) -> Pool {
+        Pool {
+            address: token(address),
+            version: DexVariant::UniswapV2,
+            token0: token(token0),
+            token1: token(token1),
+            decimals0: 18,
+            decimals1: 18,
+            fee: 3000,
+            router: token(0xee),
+        }
+    }
+
+    fn reserves(pools: &[Pool], amounts: &[(u64, u64)]) -> HashMap<Address, PoolState> {
+        let whole = U256::from(10).pow(U256::from(18));
+        pools
+            .iter()
+            .zip(amounts)
+            .map(|(pool, (reserve0, reserve1))| {
+                let reserve = Reserve {
+                    reserve0: U256::from(*reserve0) * whole,
+                    reserve1: U256::from(*reserve1) * whole,
+                };
+                (pool.address, reserve.into())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detects_a_known_negative_cycle() {
+        let pools = vec![pool(0xa0, 1, 2), pool(0xa1, 2, 3), pool(0xa2, 1, 3)];
+        // Token 3 buys 1.2 of token 1 in the last pool, so 1 -> 2 -> 3 -> 1 gains ~19%
+        let reserves = reserves(&pools, &[(1000, 1000), (1000, 1000), (1200, 1000)]);
+        let graph = RateGraph::from_reserves(&pools, &reserves);
+
+        let cycle = graph.find_negative_cycle(token(1)).unwrap();
+        assert_eq!(cycle.edges.len(), 3);
+        let expected = -(1.2 * 0.997f64.powi(3)).ln();
+        assert!((cycle.weight() - expected).abs() < 1e-9);
+        assert!(cycle.contains_token(&token(2)));
+
+        let path = graph.find_arb_path(token(1)).unwrap();
+        let route: Vec<Address> = path.hops.iter().map(|hop| hop.token_out()).collect();
+        assert_eq!(path.hops[0].token_in(), token(1));
+        assert_eq!(route, vec![token(2), token(3), token(1)]);
+        let (amount_in, profit) = path.optimal_amount_in_v2(&reserves).unwrap();
+        assert!(!amount_in.is_zero() && !profit.is_zero());
+    }
+
+    #[test]
+    fn fees_leave_balanced_pools_without_a_cycle() {
+        let pools = vec![pool(0xa0, 1, 2), pool(0xa1, 2, 3), pool(0xa2, 1, 3)];
+        let reserves = reserves(&pools, &[(1000, 1000), (1000, 1000), (1000, 1000)]);
+        let graph = RateGraph::from_reserves(&pools, &reserves);
+
+        assert!(graph.find_negative_cycle(token(1)).is_none());
+        assert!(graph.find_negative_cycle(token(9)).is_none());
+    }
+}
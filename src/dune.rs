@@ -0,0 +1,115 @@
+use anyhow::{anyhow, Result};
+use serde::{de::DeserializeOwned, Deserialize};
+
+#[derive(Deserialize, Debug)]
+struct QueryResultsResponse<T> {
+    result: Option<QueryResult<T>>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct QueryResult<T> {
+    rows: Vec<T>,
+}
+
+/// Reads the rows of a Dune query's latest execution. The base URL is a parameter so
+/// tests can point it at a local server.
+pub async fn fetch_query_rows<T: DeserializeOwned>(
+    base_url: &str,
+    api_key: &str,
+    query_id: u32,
+) -> Result<Vec<T>> {
+    let url = format!(
+        "{}/query/{}/results",
+        base_url.trim_end_matches('/'),
+        query_id
+    );
+    let response = reqwest::Client::new()
+        .get(&url)
+        .header("X-Dune-API-Key", api_key)
+        .send()
+        .await?;
+
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        return Err(anyhow!(
+            "Dune query {} returned {}: {}",
+            query_id,
+            status,
+            body
+        ));
+    }
+
+    let parsed: QueryResultsResponse<T> = serde_json::from_str(&body)?;
+    match (parsed.result, parsed.error) {
+        (Some(result), _) => Ok(result.rows),
+        (None, Some(error)) => Err(anyhow!("Dune query {} failed: {}", query_id, error)),
+        (None, None) => Err(anyhow!("Dune query {} has no results", query_id)),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod dune_tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Serves one canned HTTP response on a local port and returns its base URL, plus a
+    /// handle resolving to the raw request it received.
+    pub(crate) async fn mock_server(
+        status: &'static str,
+        body: String,
+    ) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![0u8; 4096];
+            let read = socket.read(&mut request).await.unwrap();
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request[..read]).to_string()
+        });
+        (url, handle)
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct MaxBlock {
+        max_block: u64,
+    }
+
+    #[tokio::test]
+    async fn fetches_rows_with_api_key() {
+        let body = r#"{"execution_id":"01","result":{"rows":[{"max_block":24279386}]}}"#;
+        let (url, request) = mock_server("200 OK", body.to_string()).await;
+
+        let rows: Vec<MaxBlock> = fetch_query_rows(&url, "secret", 6572025).await.unwrap();
+        assert_eq!(
+            rows,
+            vec![MaxBlock {
+                max_block: 24279386
+            }]
+        );
+
+        let request = request.await.unwrap().to_lowercase();
+        assert!(request.starts_with("get /query/6572025/results"));
+        assert!(request.contains("x-dune-api-key: secret"));
+    }
+
+    #[tokio::test]
+    async fn surfaces_api_errors() {
+        let body = r#"{"error":"invalid API Key"}"#;
+        let (url, _) = mock_server("401 Unauthorized", body.to_string()).await;
+
+        let result: Result<Vec<MaxBlock>> = fetch_query_rows(&url, "bad", 1).await;
+        assert!(result.unwrap_err().to_string().contains("401"));
+    }
+}
@@ -0,0 +1,200 @@
+//! Minimal Flashbots relay client. Bundles are simulated with `eth_callBundle` and sent
+//! with `eth_sendBundle`, each request signed by the relay identity key in the
+//! `X-Flashbots-Signature` header.
+use alloy::{
+    primitives::{keccak256, Bytes, B256},
+    signers::{local::PrivateKeySigner, Signer},
+};
+use anyhow::{anyhow, Result};
+use reqwest::header::CONTENT_TYPE;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+
+pub const FLASHBOTS_RELAY_URL: &str = "https://relay.flashbots.net";
+
+/// Signed transactions that must land in `block` together and in order. The relay
+/// simulates them on top of `simulation_block`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BundleRequest {
+    pub transactions: Vec<Bytes>,
+    pub block: u64,
+    pub simulation_block: u64,
+    pub simulation_timestamp: Option<u64>,
+}
+
+impl BundleRequest {
+    /// Hashes of the bundle's transactions, as they appear once mined.
+    pub fn transaction_hashes(&self) -> Vec<B256> {
+        self.transactions.iter().map(keccak256).collect()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulatedTransaction {
+    pub tx_hash: B256,
+    #[serde(default)]
+    pub gas_used: u64,
+    pub error: Option<String>,
+    pub revert: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulatedBundle {
+    pub bundle_hash: B256,
+    #[serde(rename = "results")]
+    pub transactions: Vec<SimulatedTransaction>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SentBundle {
+    bundle_hash: B256,
+}
+
+pub struct FlashbotsRelay {
+    url: String,
+    signer: PrivateKeySigner,
+    client: reqwest::Client,
+}
+
+impl FlashbotsRelay {
+    /// `signer` only identifies the searcher to the relay; it never signs transactions.
+    pub fn new(url: &str, signer: PrivateKeySigner) -> Self {
+        Self {
+            url: url.to_string(),
+            signer,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn simulate_bundle(&self, bundle: &BundleRequest) -> Result<SimulatedBundle> {
+        let mut params = json!({
+            "txs": bundle.transactions,
+            "blockNumber": format!("0x{:x}", bundle.block),
+            "stateBlockNumber": format!("0x{:x}", bundle.simulation_block),
+        });
+        if let Some(timestamp) = bundle.simulation_timestamp {
+            params["timestamp"] = json!(timestamp);
+        }
+        self._request("eth_callBundle", params).await
+    }
+
+    /// Submits the bundle and returns its hash. Being accepted doesn't mean it lands.
+    pub async fn send_bundle(&self, bundle: &BundleRequest) -> Result<B256> {
+        let params = json!({
+            "txs": bundle.transactions,
+            "blockNumber": format!("0x{:x}", bundle.block),
+        });
+        let sent: SentBundle = self._request("eth_sendBundle", params).await?;
+        Ok(sent.bundle_hash)
+    }
+
+    async fn _request<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T> {
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": [params],
+        })
+        .to_string();
+        // The relay expects an EIP-191 signature over the hex string of the body's hash
+        let digest = keccak256(body.as_bytes()).to_string();
+        let signature = self.signer.sign_message(digest.as_bytes()).await?;
+        let response = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(
+                "X-Flashbots-Signature",
+                format!(
+                    "{:?}:0x{}",
+                    self.signer.address(),
+                    hex::encode(signature.as_bytes())
+                ),
+            )
+            .body(body)
+            .send()
+            .await?;
+
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            return Err(anyhow!(
+                "Flashbots {} returned {}: {}",
+                method,
+                status,
+                text
+            ));
+        }
+        let mut response: Value = serde_json::from_str(&text)?;
+        if let Some(error) = response.get("error") {
+            return Err(anyhow!("Flashbots {} failed: {}", method, error));
+        }
+        match response.get_mut("result").map(Value::take) {
+            Some(result) => Ok(serde_json::from_value(result)?),
+            None => Err(anyhow!("Flashbots {} returned no result", method)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod flashbots_tests {
+    use super::*;
+    use crate::dune::dune_tests::mock_server;
+
+    fn bundle() -> BundleRequest {
+        BundleRequest {
+            transactions: vec![Bytes::from(vec![0x02, 0xaa]), Bytes::from(vec![0x02, 0xbb])],
+            block: 0x10,
+            simulation_block: 0x0f,
+            simulation_timestamp: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn simulation_reports_reverts() {
+        let body = format!(
+            r#"{{"jsonrpc":"2.0","id":1,"result":{{"bundleHash":"{}","results":[{{"txHash":"{}","gasUsed":21000}},{{"txHash":"{}","gasUsed":90000,"revert":"TRANSFER_FAILED"}}]}}}}"#,
+            B256::repeat_byte(1),
+            B256::repeat_byte(2),
+            B256::repeat_byte(3)
+        );
+        let (url, request) = mock_server("200 OK", body).await;
+        let signer = PrivateKeySigner::random();
+        let relay = FlashbotsRelay::new(&url, signer.clone());
+
+        let simulated = relay.simulate_bundle(&bundle()).await.unwrap();
+        assert_eq!(simulated.bundle_hash, B256::repeat_byte(1));
+        assert_eq!(simulated.transactions.len(), 2);
+        assert_eq!(simulated.transactions[0].revert, None);
+        assert_eq!(
+            simulated.transactions[1].revert.as_deref(),
+            Some("TRANSFER_FAILED")
+        );
+
+        let request = request.await.unwrap().to_lowercase();
+        let identity = format!("x-flashbots-signature: {:?}:0x", signer.address());
+        assert!(request.contains(&identity));
+    }
+
+    #[tokio::test]
+    async fn relay_errors_fail_the_request() {
+        let body = r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"bundle too old"}}"#;
+        let (url, _) = mock_server("200 OK", body.to_string()).await;
+        let relay = FlashbotsRelay::new(&url, PrivateKeySigner::random());
+
+        let result = relay.send_bundle(&bundle()).await;
+        assert!(result.unwrap_err().to_string().contains("bundle too old"));
+    }
+
+    #[test]
+    fn transaction_hashes_hash_the_raw_transactions() {
+        let bundle = bundle();
+        assert_eq!(
+            bundle.transaction_hashes(),
+            vec![keccak256([0x02, 0xaa]), keccak256([0x02, 0xbb])]
+        );
+    }
+}
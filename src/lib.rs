@@ -1,11 +1,26 @@
 pub mod abi;
+pub mod backrun;
+pub mod balancer_math;
 pub mod bundler;
+#[cfg(feature = "ethers")]
+pub mod compat;
 pub mod constants;
+pub mod cycles;
+pub mod dune;
+pub mod flashbots;
 pub mod multi;
+pub mod optimizer;
 pub mod paths;
 pub mod pools;
+pub mod reorg;
+pub mod reserve_state;
 pub mod simulator;
+pub mod snapshot;
+pub mod state_space;
 pub mod strategy;
 pub mod streams;
+pub mod tax_detector;
+pub mod tokens;
 pub mod utils;
+pub mod v3_math;
 pub mod math;
@@ -1,5 +1,5 @@
+use alloy::providers::ProviderBuilder;
 use anyhow::{Ok, Result};
-use ethers::providers::{Provider, Ws};
 use log::info;
 use std::sync::Arc;
 use tokio::sync::broadcast::{self, Sender};
@@ -7,9 +7,7 @@ use tokio::task::JoinSet;
 
 use rust::constants::Env;
 use rust::strategy::event_handler;
-use rust::streams::{
-    stream_new_blocks, stream_pending_transactions, stream_uniswap_v2_events, Event,
-};
+use rust::streams::{supervise_stream, Event, StreamKind};
 use rust::utils::setup_logger;
 
 #[tokio::main]
@@ -17,24 +15,36 @@ async fn main() -> Result<()> {
     dotenv::dotenv().ok();
     setup_logger()?;
 
-    let env = Env::new();
+    let env = Env::new()?;
 
-    // Start async websocket streams
-    println!("Preparing to start");
-    let ws = Ws::connect(env.wss_url).await?;
-    let provider = Arc::new(Provider::new(ws));
-    println!("STarted");
+    // The handler's own reads (logs for skipped blocks, reorg ancestors) go over HTTPS,
+    // so they don't depend on any one of the streamed endpoints staying up
+    let provider = Arc::new(ProviderBuilder::new().connect_http(env.https_url.parse()?));
 
     let (event_sender, _): (Sender<Event>, _) = broadcast::channel(512);
 
     let mut set = JoinSet::new();
 
-    set.spawn(stream_new_blocks(provider.clone(), event_sender.clone()));
-    // we're not using the mempool data here, but uncomment it to use pending txs
-    // set.spawn(stream_pending_transactions(
-    //     provider.clone(),
-    //     event_sender.clone(),
-    // ));
+    // Blocks race every endpoint in WSS_URL and reconnect on their own
+    set.spawn(supervise_stream(
+        StreamKind::NewBlocks,
+        env.wss_urls.clone(),
+        event_sender.clone(),
+    ));
+    // Pool logs drive the reserve updates for each block
+    set.spawn(supervise_stream(
+        StreamKind::PoolEvents,
+        env.wss_urls.clone(),
+        event_sender.clone(),
+    ));
+    // Pending transactions are only needed to backrun router swaps
+    if env.backrun {
+        set.spawn(supervise_stream(
+            StreamKind::PendingTransactions,
+            env.wss_urls.clone(),
+            event_sender.clone(),
+        ));
+    }
     set.spawn(event_handler(provider.clone(), event_sender.clone()));
 
     while let Some(res) = set.join_next().await {
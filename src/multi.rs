@@ -1,14 +1,90 @@
-use anyhow::{Ok, Result};
-use ethers::{
-    abi,
-    providers::{Http, Provider},
-    types::{H160, H256, U256},
+use alloy::{
+    primitives::{aliases::I24, Address, U256},
+    providers::{Provider, ProviderBuilder},
+    sol,
 };
-use ethers_contract::{Contract, Multicall};
+use anyhow::Result;
 use log::info;
-use std::{collections::HashMap, sync::Arc, time::Instant};
+use std::{collections::HashMap, str::FromStr, time::Instant};
 
-use crate::{abi::ABI, pools::Pool};
+use crate::{
+    constants::{BALANCER_VAULT_ADDRESS, CURVE_REGISTRY_ADDRESS},
+    pools::{DexVariant, Pool},
+    v3_math::tick_word,
+};
+
+sol! {
+    #[sol(rpc)]
+    interface UniswapV2Pair {
+        function getReserves() external view returns (uint112 reserve0, uint112 reserve1, uint32 blockTimestampLast);
+    }
+
+    #[sol(rpc)]
+    interface UniswapV3Pool {
+        function slot0() external view returns (uint160 sqrtPriceX96, int24 tick, uint16 observationIndex, uint16 observationCardinality, uint16 observationCardinalityNext, uint8 feeProtocol, bool unlocked);
+        function liquidity() external view returns (uint128);
+        function tickSpacing() external view returns (int24);
+        function fee() external view returns (uint24);
+        function tickBitmap(int16 wordPosition) external view returns (uint256);
+        function ticks(int24 tick) external view returns (uint128 liquidityGross, int128 liquidityNet, uint256 feeGrowthOutside0X128, uint256 feeGrowthOutside1X128, int56 tickCumulativeOutside, uint160 secondsPerLiquidityOutsideX128, uint32 secondsOutside, bool initialized);
+    }
+
+    #[sol(rpc)]
+    interface PoolTokens {
+        function token0() external view returns (address);
+        function token1() external view returns (address);
+    }
+
+    #[sol(rpc)]
+    interface UniswapV2Factory {
+        event PairCreated(address indexed token0, address indexed token1, address pair, uint256 allPairsLength);
+    }
+
+    #[sol(rpc)]
+    interface CurveRegistry {
+        function pool_count() external view returns (uint256);
+        function pool_list(uint256 i) external view returns (address);
+        function is_meta(address pool) external view returns (bool);
+        function get_n_coins(address pool) external view returns (uint256[2]);
+        function get_coins(address pool) external view returns (address[8]);
+        function get_decimals(address pool) external view returns (uint256[8]);
+        function get_balances(address pool) external view returns (uint256[8]);
+        function get_rates(address pool) external view returns (uint256[8]);
+        function get_fees(address pool) external view returns (uint256[2]);
+        function get_A(address pool) external view returns (uint256);
+    }
+
+    #[sol(rpc)]
+    interface CurveStablePool {
+        function A_precise() external view returns (uint256);
+    }
+
+    #[sol(rpc)]
+    interface BalancerVault {
+        event PoolRegistered(bytes32 indexed poolId, address indexed poolAddress, uint8 specialization);
+        function getPoolTokens(bytes32 poolId) external view returns (address[] tokens, uint256[] balances, uint256 lastChangeBlock);
+    }
+
+    #[sol(rpc)]
+    interface BalancerWeightedPool {
+        function getPoolId() external view returns (bytes32);
+        function getNormalizedWeights() external view returns (uint256[]);
+        function getSwapFeePercentage() external view returns (uint256);
+    }
+
+    #[sol(rpc)]
+    interface Erc20Token {
+        function balanceOf(address account) external view returns (uint256);
+        function transfer(address to, uint256 amount) external returns (bool);
+    }
+
+    #[sol(rpc)]
+    interface Erc20Metadata {
+        function decimals() external view returns (uint8);
+        function symbol() external view returns (string);
+        function name() external view returns (string);
+    }
+}
 
 #[derive(Default, Debug, Clone)]
 pub struct Reserve {
@@ -16,43 +92,163 @@ pub struct Reserve {
     pub reserve1: U256,
 }
 
+#[derive(Default, Debug, Clone)]
+pub struct TickInfo {
+    pub liquidity_gross: u128,
+    pub liquidity_net: i128,
+}
+
+/// Snapshot of a Uniswap V3 pool: slot0, in-range liquidity and the tick bitmap
+/// words (with tick liquidity for every initialized tick) around the current price.
+#[derive(Default, Debug, Clone)]
+pub struct UniswapV3State {
+    pub sqrt_price_x96: U256,
+    pub liquidity: u128,
+    pub tick: i32,
+    pub tick_spacing: i32,
+    pub fee: u32,
+    pub tick_bitmap: HashMap<i16, U256>,
+    pub ticks: HashMap<i32, TickInfo>,
+}
+
+impl UniswapV3State {
+    /// Applies the post-swap slot0 and liquidity emitted in a `Swap` event.
+    pub fn apply_swap(&mut self, sqrt_price_x96: U256, liquidity: u128, tick: i32) {
+        self.sqrt_price_x96 = sqrt_price_x96;
+        self.liquidity = liquidity;
+        self.tick = tick;
+    }
+
+    /// Applies a `Mint` (positive delta) or `Burn` (negative delta) on `[tick_lower, tick_upper)`.
+    pub fn update_position(&mut self, tick_lower: i32, tick_upper: i32, liquidity_delta: i128) {
+        self._update_tick(tick_lower, liquidity_delta, false);
+        self._update_tick(tick_upper, liquidity_delta, true);
+
+        if tick_lower <= self.tick && self.tick < tick_upper {
+            self.liquidity = _add_delta(self.liquidity, liquidity_delta);
+        }
+    }
+
+    fn _update_tick(&mut self, tick: i32, liquidity_delta: i128, upper: bool) {
+        let info = self.ticks.entry(tick).or_default();
+        let was_initialized = info.liquidity_gross != 0;
+
+        info.liquidity_gross = _add_delta(info.liquidity_gross, liquidity_delta);
+        info.liquidity_net = if upper {
+            info.liquidity_net - liquidity_delta
+        } else {
+            info.liquidity_net + liquidity_delta
+        };
+
+        let is_initialized = info.liquidity_gross != 0;
+        if !is_initialized {
+            self.ticks.remove(&tick);
+        }
+        if was_initialized != is_initialized {
+            self._flip_tick(tick);
+        }
+    }
+
+    fn _flip_tick(&mut self, tick: i32) {
+        let compressed = tick / self.tick_spacing;
+        let word = tick_word(tick, self.tick_spacing);
+        // Words outside the fetched range stay unknown; the simulator refuses to enter them
+        if let Some(bitmap) = self.tick_bitmap.get_mut(&word) {
+            *bitmap = *bitmap ^ (U256::ONE << (compressed & 0xff) as u8);
+        }
+    }
+}
+
+fn _add_delta(liquidity: u128, delta: i128) -> u128 {
+    if delta < 0 {
+        liquidity.saturating_sub(delta.unsigned_abs())
+    } else {
+        liquidity.saturating_add(delta as u128)
+    }
+}
+
+/// Curve StableSwap pool state as read by `get_dy`. `rates` are the pool's
+/// `RATES`/`stored_rates` (10^(36 - decimals) for plain coins), `fee` is out of 1e10
+/// and `amp` is scaled by `a_precision` (1 for legacy pools, 100 for newer ones).
+#[derive(Default, Debug, Clone)]
+pub struct CurveStableState {
+    pub coins: Vec<Address>,
+    pub balances: Vec<U256>,
+    pub rates: Vec<U256>,
+    pub amp: U256,
+    pub a_precision: U256,
+    pub fee: U256,
+}
+
+/// Balancer V2 weighted pool state. Weights and the swap fee are 18-decimal fixed
+/// point; `scaling_factors` are 10^(18 - decimals) per token.
+#[derive(Default, Debug, Clone)]
+pub struct BalancerWeightedState {
+    pub pool_id: [u8; 32],
+    pub tokens: Vec<Address>,
+    pub balances: Vec<U256>,
+    pub weights: Vec<U256>,
+    pub scaling_factors: Vec<U256>,
+    pub swap_fee: U256,
+}
+
+/// Current state of any supported pool, keyed by pool address in the strategy.
+#[derive(Debug, Clone)]
+pub enum PoolState {
+    UniswapV2(Reserve),
+    UniswapV3(UniswapV3State),
+    CurveStable(CurveStableState),
+    BalancerWeighted(BalancerWeightedState),
+}
+
+impl From<Reserve> for PoolState {
+    fn from(reserve: Reserve) -> Self {
+        PoolState::UniswapV2(reserve)
+    }
+}
+
+impl From<UniswapV3State> for PoolState {
+    fn from(state: UniswapV3State) -> Self {
+        PoolState::UniswapV3(state)
+    }
+}
+
+impl From<CurveStableState> for PoolState {
+    fn from(state: CurveStableState) -> Self {
+        PoolState::CurveStable(state)
+    }
+}
+
+impl From<BalancerWeightedState> for PoolState {
+    fn from(state: BalancerWeightedState) -> Self {
+        PoolState::BalancerWeighted(state)
+    }
+}
+
 pub async fn get_uniswap_v2_reserves(
     https_url: String,
     pools: Vec<Pool>,
-) -> Result<HashMap<H160, Reserve>> {
-    let client = Provider::<Http>::try_from(https_url)?;
-    let client = Arc::new(client);
-
-    let abi = ABI::new();
-    let mut multicall = Multicall::new(client.clone(), None).await?;
+) -> Result<HashMap<Address, Reserve>> {
+    let provider = ProviderBuilder::new().connect_http(https_url.parse()?);
 
+    let mut multicall = provider
+        .multicall()
+        .dynamic::<UniswapV2Pair::getReservesCall>();
     for pool in &pools {
-        let contract = Contract::<Provider<Http>>::new(
-            pool.address,
-            abi.uniswap_v2_pair.clone(),
-            client.clone(),
-        );
-        let call = contract.method::<_, H256>("getReserves", ())?;
-        multicall.add_call(call, false);
+        multicall =
+            multicall.add_dynamic(UniswapV2Pair::new(pool.address, &provider).getReserves());
     }
 
-    let result = multicall.call_raw().await?;
+    let result = multicall.aggregate().await?;
 
     let mut reserves = HashMap::new();
 
-    for i in 0..result.len() {
-        let pool = &pools[i];
-        let reserve = result[i].clone();
-        match reserve.unwrap() {
-            abi::Token::Tuple(response) => {
-                let reserve_data = Reserve {
-                    reserve0: response[0].clone().into_uint().unwrap(),
-                    reserve1: response[1].clone().into_uint().unwrap(),
-                };
-                reserves.insert(pool.address.clone(), reserve_data);
-            }
-            _ => {}
-        }
+    for (pool, response) in pools.iter().zip(result) {
+        let reserve_data = Reserve {
+            reserve0: U256::from(response.reserve0),
+            reserve1: U256::from(response.reserve1),
+        };
+        reserves.insert(pool.address, reserve_data);
     }
 
     Ok(reserves)
@@ -61,11 +257,11 @@ pub async fn get_uniswap_v2_reserves(
 pub async fn batch_get_uniswap_v2_reserves(
     https_url: String,
     pools: Vec<Pool>,
-) -> HashMap<H160, Reserve> {
+) -> HashMap<Address, Reserve> {
     let start_time = Instant::now();
 
     let pools_cnt = pools.len();
-    let batch = ((pools_cnt / 250) as f32).ceil();
+    let batch = ((pools_cnt as f32) / 250.0).ceil();
     let pools_per_batch = ((pools_cnt as f32) / batch).ceil() as usize;
 
     let mut handles = vec![];
@@ -80,7 +276,7 @@ pub async fn batch_get_uniswap_v2_reserves(
         handles.push(handle);
     }
 
-    let mut reserves: HashMap<H160, Reserve> = HashMap::new();
+    let mut reserves: HashMap<Address, Reserve> = HashMap::new();
 
     for handle in handles {
         let result = handle.await.unwrap();
@@ -93,3 +289,357 @@ pub async fn batch_get_uniswap_v2_reserves(
     );
     reserves
 }
+
+/// Fetches a V3 pool's state, including `word_radius` bitmap words on either side
+/// of the current tick and the liquidityNet of every initialized tick in them.
+pub async fn get_uniswap_v3_state(
+    https_url: String,
+    pool: Address,
+    word_radius: i16,
+) -> Result<UniswapV3State> {
+    let provider = ProviderBuilder::new().connect_http(https_url.parse()?);
+    let contract = UniswapV3Pool::new(pool, &provider);
+
+    // The calls are bound first since each `call()` future borrows its call
+    let (slot0, liquidity, tick_spacing, fee) = (
+        contract.slot0(),
+        contract.liquidity(),
+        contract.tickSpacing(),
+        contract.fee(),
+    );
+    let (slot0, liquidity, tick_spacing, fee) = tokio::try_join!(
+        slot0.call(),
+        liquidity.call(),
+        tick_spacing.call(),
+        fee.call(),
+    )?;
+    let (sqrt_price_x96, tick) = (U256::from(slot0.sqrtPriceX96), slot0.tick.as_i32());
+    let (tick_spacing, fee) = (tick_spacing.as_i32(), fee.to::<u32>());
+
+    let center = tick_word(tick, tick_spacing);
+    let words: Vec<i16> =
+        (center.saturating_sub(word_radius)..=center.saturating_add(word_radius)).collect();
+
+    let mut multicall = provider
+        .multicall()
+        .dynamic::<UniswapV3Pool::tickBitmapCall>();
+    for word in &words {
+        multicall = multicall.add_dynamic(contract.tickBitmap(*word));
+    }
+    let bitmaps: Vec<U256> = multicall.aggregate().await?;
+    let tick_bitmap: HashMap<i16, U256> = words.into_iter().zip(bitmaps).collect();
+
+    let mut initialized_ticks = Vec::new();
+    for (word, bitmap) in &tick_bitmap {
+        for bit in 0..256 {
+            if bitmap.bit(bit) {
+                let compressed = ((*word as i32) << 8) + bit as i32;
+                initialized_ticks.push(compressed * tick_spacing);
+            }
+        }
+    }
+
+    let mut ticks = HashMap::new();
+    if !initialized_ticks.is_empty() {
+        let mut multicall = provider.multicall().dynamic::<UniswapV3Pool::ticksCall>();
+        for tick in &initialized_ticks {
+            multicall = multicall.add_dynamic(contract.ticks(I24::unchecked_from(*tick)));
+        }
+        let tick_infos = multicall.aggregate().await?;
+        for (tick, info) in initialized_ticks.into_iter().zip(tick_infos) {
+            ticks.insert(
+                tick,
+                TickInfo {
+                    liquidity_gross: info.liquidityGross,
+                    liquidity_net: info.liquidityNet,
+                },
+            );
+        }
+    }
+
+    Ok(UniswapV3State {
+        sqrt_price_x96,
+        liquidity,
+        tick,
+        tick_spacing,
+        fee,
+        tick_bitmap,
+        ticks,
+    })
+}
+
+/// The pool's `RATES` from the registry's per-coin rates, which are 1e18 for plain coins
+/// and the exchange rate for lending ones. The pool also scales every coin up to 18
+/// decimals, so a plain coin ends up at `10^(36 - decimals)`.
+pub fn curve_rates(registry_rates: &[U256], decimals: &[U256]) -> Vec<U256> {
+    registry_rates
+        .iter()
+        .zip(decimals)
+        .map(|(rate, decimals)| {
+            let precision = U256::from(18).saturating_sub(*decimals);
+            *rate * U256::from(10).pow(precision)
+        })
+        .collect()
+}
+
+/// Reads a Curve pool's balances, rates, fee and amplification through the registry,
+/// preferring the pool's own `A_precise()` where it exists.
+pub async fn get_curve_stable_state(
+    https_url: String,
+    registry: Address,
+    pool: Address,
+) -> Result<CurveStableState> {
+    let provider = ProviderBuilder::new().connect_http(https_url.parse()?);
+    let registry = CurveRegistry::new(registry, &provider);
+
+    let (n_coins, coins, decimals, balances, rates, fees, amp) = (
+        registry.get_n_coins(pool),
+        registry.get_coins(pool),
+        registry.get_decimals(pool),
+        registry.get_balances(pool),
+        registry.get_rates(pool),
+        registry.get_fees(pool),
+        registry.get_A(pool),
+    );
+    let (n_coins, coins, decimals, balances, rates, fees, amp) = tokio::try_join!(
+        n_coins.call(),
+        coins.call(),
+        decimals.call(),
+        balances.call(),
+        rates.call(),
+        fees.call(),
+        amp.call(),
+    )?;
+    let n = n_coins[0].to::<usize>();
+
+    let (amp, a_precision) = match CurveStablePool::new(pool, &provider)
+        .A_precise()
+        .call()
+        .await
+    {
+        std::result::Result::Ok(amp_precise) => (amp_precise, U256::from(100)),
+        Err(_) => (amp, U256::ONE),
+    };
+
+    Ok(CurveStableState {
+        coins: coins[..n].to_vec(),
+        balances: balances[..n].to_vec(),
+        rates: curve_rates(&rates[..n], &decimals[..n]),
+        amp,
+        a_precision,
+        fee: fees[0],
+    })
+}
+
+pub async fn batch_get_curve_stable_states(
+    https_url: String,
+    registry: Address,
+    pools: Vec<Pool>,
+) -> HashMap<Address, CurveStableState> {
+    // A Curve pool appears once per coin pair, but only needs fetching once
+    let mut addresses: Vec<Address> = pools.iter().map(|pool| pool.address).collect();
+    addresses.sort();
+    addresses.dedup();
+
+    let mut handles = vec![];
+    for address in addresses {
+        let handle = tokio::spawn(get_curve_stable_state(https_url.clone(), registry, address));
+        handles.push((address, handle));
+    }
+
+    let mut states = HashMap::new();
+    for (address, handle) in handles {
+        let result = handle
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|res| res);
+        match result {
+            std::result::Result::Ok(state) => {
+                states.insert(address, state);
+            }
+            Err(e) => info!("Error fetching Curve state for {:?}: {:?}", address, e),
+        }
+    }
+    states
+}
+
+pub async fn get_balancer_weighted_state(
+    https_url: String,
+    vault: Address,
+    pool: Address,
+) -> Result<BalancerWeightedState> {
+    let provider = ProviderBuilder::new().connect_http(https_url.parse()?);
+    let vault = BalancerVault::new(vault, &provider);
+    let contract = BalancerWeightedPool::new(pool, &provider);
+
+    let (pool_id, weights, swap_fee) = (
+        contract.getPoolId(),
+        contract.getNormalizedWeights(),
+        contract.getSwapFeePercentage(),
+    );
+    let (pool_id, weights, swap_fee) =
+        tokio::try_join!(pool_id.call(), weights.call(), swap_fee.call())?;
+    let pool_tokens = vault.getPoolTokens(pool_id).call().await?;
+    let (tokens, balances) = (pool_tokens.tokens, pool_tokens.balances);
+
+    let mut multicall = provider
+        .multicall()
+        .dynamic::<Erc20Metadata::decimalsCall>();
+    for token in &tokens {
+        multicall = multicall.add_dynamic(Erc20Metadata::new(*token, &provider).decimals());
+    }
+    let decimals: Vec<u8> = multicall.aggregate().await?;
+    let scaling_factors = decimals
+        .iter()
+        .map(|d| U256::from(10).pow(U256::from(18u8.saturating_sub(*d))))
+        .collect();
+
+    Ok(BalancerWeightedState {
+        pool_id: pool_id.0,
+        tokens,
+        balances,
+        weights,
+        scaling_factors,
+        swap_fee,
+    })
+}
+
+pub async fn batch_get_balancer_weighted_states(
+    https_url: String,
+    vault: Address,
+    pools: Vec<Pool>,
+) -> HashMap<Address, BalancerWeightedState> {
+    // Like Curve, a weighted pool appears once per token pair
+    let mut addresses: Vec<Address> = pools.iter().map(|pool| pool.address).collect();
+    addresses.sort();
+    addresses.dedup();
+
+    let mut handles = vec![];
+    for address in addresses {
+        let handle = tokio::spawn(get_balancer_weighted_state(
+            https_url.clone(),
+            vault,
+            address,
+        ));
+        handles.push((address, handle));
+    }
+
+    let mut states = HashMap::new();
+    for (address, handle) in handles {
+        let result = handle
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|res| res);
+        match result {
+            std::result::Result::Ok(state) => {
+                states.insert(address, state);
+            }
+            Err(e) => info!("Error fetching Balancer state for {:?}: {:?}", address, e),
+        }
+    }
+    states
+}
+
+pub async fn batch_get_uniswap_v3_states(
+    https_url: String,
+    pools: Vec<Pool>,
+    word_radius: i16,
+) -> HashMap<Address, UniswapV3State> {
+    let start_time = Instant::now();
+
+    let mut states = HashMap::new();
+
+    // Each pool already batches its own calls, so keep a bounded number in flight
+    for chunk in pools.chunks(25) {
+        let mut handles = vec![];
+        for pool in chunk {
+            let handle = tokio::spawn(get_uniswap_v3_state(
+                https_url.clone(),
+                pool.address,
+                word_radius,
+            ));
+            handles.push((pool.address, handle));
+        }
+
+        for (address, handle) in handles {
+            let result = handle
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|res| res);
+            match result {
+                std::result::Result::Ok(state) => {
+                    states.insert(address, state);
+                }
+                Err(e) => info!("Error fetching V3 state for {:?}: {:?}", address, e),
+            }
+        }
+    }
+
+    info!(
+        "Batch V3 state call took: {} seconds",
+        start_time.elapsed().as_secs()
+    );
+    states
+}
+
+/// Fetches reserves for every V2 pool and full state for every other pool kind in `pools`.
+pub async fn batch_get_pool_states(
+    https_url: String,
+    pools: Vec<Pool>,
+    word_radius: i16,
+) -> HashMap<Address, PoolState> {
+    let mut v2_pools = Vec::new();
+    let mut v3_pools = Vec::new();
+    let mut curve_pools = Vec::new();
+    let mut balancer_pools = Vec::new();
+    for pool in pools {
+        match pool.version {
+            DexVariant::UniswapV2 => v2_pools.push(pool),
+            DexVariant::UniswapV3 => v3_pools.push(pool),
+            DexVariant::CurveStable => curve_pools.push(pool),
+            DexVariant::BalancerWeighted => balancer_pools.push(pool),
+        }
+    }
+
+    let mut states: HashMap<Address, PoolState> = HashMap::new();
+    if !v2_pools.is_empty() {
+        let reserves = batch_get_uniswap_v2_reserves(https_url.clone(), v2_pools).await;
+        states.extend(reserves.into_iter().map(|(k, v)| (k, v.into())));
+    }
+    if !v3_pools.is_empty() {
+        let v3_states = batch_get_uniswap_v3_states(https_url.clone(), v3_pools, word_radius).await;
+        states.extend(v3_states.into_iter().map(|(k, v)| (k, v.into())));
+    }
+    if !curve_pools.is_empty() {
+        let registry = Address::from_str(CURVE_REGISTRY_ADDRESS).unwrap();
+        let curve_states =
+            batch_get_curve_stable_states(https_url.clone(), registry, curve_pools).await;
+        states.extend(curve_states.into_iter().map(|(k, v)| (k, v.into())));
+    }
+    if !balancer_pools.is_empty() {
+        let vault = Address::from_str(BALANCER_VAULT_ADDRESS).unwrap();
+        let balancer_states =
+            batch_get_balancer_weighted_states(https_url, vault, balancer_pools).await;
+        states.extend(balancer_states.into_iter().map(|(k, v)| (k, v.into())));
+    }
+    states
+}
+
+#[cfg(test)]
+mod multi_tests {
+    use super::*;
+
+    #[test]
+    fn curve_rates_scale_registry_rates_to_pool_rates() {
+        let unit = |decimals: u32| U256::from(10).pow(U256::from(decimals));
+        // A plain coin's registry rate is 1e18, which becomes 10^(36 - decimals)
+        let dai = curve_rates(&[unit(18)], &[U256::from(18)]);
+        assert_eq!(dai, vec![unit(18)]);
+        let usdc = curve_rates(&[unit(18)], &[U256::from(6)]);
+        assert_eq!(usdc, vec![unit(30)]);
+
+        // A lending coin's exchange rate carries through the decimal scaling
+        let rates = curve_rates(&[unit(16) * U256::from(2)], &[U256::from(8)]);
+        assert_eq!(rates, vec![unit(26) * U256::from(2)]);
+    }
+}
@@ -0,0 +1,154 @@
+use alloy::primitives::{I256, U256};
+
+// (sqrt(5) - 1) / 2 scaled by 1e6
+const INV_PHI_NUM: u64 = 618_034;
+const INV_PHI_DEN: u64 = 1_000_000;
+
+#[derive(Debug, Clone)]
+pub struct SearchConfig {
+    /// Stop once the bracket is no wider than this many millionths of the initial one,
+    /// so the precision is the same whatever the input token's decimals.
+    pub tolerance_ppm: u64,
+    /// Hard cap on bracket-shrinking iterations.
+    pub max_iterations: usize,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            tolerance_ppm: 1,
+            max_iterations: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub amount_in: U256,
+    pub profit: I256,
+    pub simulations: usize,
+}
+
+pub fn profit(amount_in: U256, amount_out: U256) -> I256 {
+    I256::from_raw(amount_out) - I256::from_raw(amount_in)
+}
+
+/// Golden-section search for the input maximizing `simulate(amount_in) - amount_in`
+/// on `[lower, upper]`, assuming the profit curve is unimodal there. A failed
+/// simulation (`None`) counts as the worst possible profit.
+pub fn golden_section_search<F>(
+    mut simulate: F,
+    lower: U256,
+    upper: U256,
+    config: &SearchConfig,
+) -> SearchResult
+where
+    F: FnMut(U256) -> Option<U256>,
+{
+    let mut simulations = 0;
+    let mut eval = |amount_in: U256| {
+        simulations += 1;
+        match simulate(amount_in) {
+            Some(amount_out) => profit(amount_in, amount_out),
+            None => I256::MIN,
+        }
+    };
+
+    let step = |lo: U256, hi: U256| (hi - lo) * U256::from(INV_PHI_NUM) / U256::from(INV_PHI_DEN);
+
+    let mut lo = lower;
+    let mut hi = upper.max(lower);
+    let mut x1 = hi - step(lo, hi);
+    let mut x2 = lo + step(lo, hi);
+    let mut f1 = eval(x1);
+    let mut f2 = eval(x2);
+
+    let tolerance =
+        ((hi - lo) * U256::from(config.tolerance_ppm) / U256::from(1_000_000)).max(U256::from(2));
+    for _ in 0..config.max_iterations {
+        if hi - lo <= tolerance || x1 >= x2 {
+            break;
+        }
+        if f1 < f2 {
+            lo = x1;
+            x1 = x2;
+            f1 = f2;
+            x2 = lo + step(lo, hi);
+            f2 = eval(x2);
+        } else {
+            hi = x2;
+            x2 = x1;
+            f2 = f1;
+            x1 = hi - step(lo, hi);
+            f1 = eval(x1);
+        }
+    }
+
+    let (amount_in, best) = if f1 >= f2 { (x1, f1) } else { (x2, f2) };
+
+    SearchResult {
+        amount_in,
+        profit: best,
+        simulations,
+    }
+}
+
+#[cfg(test)]
+mod optimizer_tests {
+    use super::*;
+    use crate::simulator::UniswapV2Simulator;
+
+    /// Two 6-decimal pools quoting the same pair 5% apart, traded as a round trip.
+    fn round_trip(amount_in: U256) -> Option<U256> {
+        let unit = U256::from(10).pow(U256::from(6));
+        let mid = UniswapV2Simulator::get_amount_out(
+            amount_in,
+            U256::from(1_000_000) * unit,
+            U256::from(1_050_000) * unit,
+            U256::from(3000),
+        )?;
+        UniswapV2Simulator::get_amount_out(
+            mid,
+            U256::from(1_000_000) * unit,
+            U256::from(1_000_000) * unit,
+            U256::from(3000),
+        )
+    }
+
+    #[test]
+    fn searches_six_decimal_inputs() {
+        let upper = U256::from(100_000) * U256::from(10).pow(U256::from(6));
+        let result = golden_section_search(round_trip, U256::ZERO, upper, &SearchConfig::default());
+
+        // Brute force over 1 USDC steps for the reference optimum
+        let (best_in, best_profit) = (1..100_000u64)
+            .map(|whole| U256::from(whole) * U256::from(10).pow(U256::from(6)))
+            .map(|amount_in| (amount_in, profit(amount_in, round_trip(amount_in).unwrap())))
+            .max_by_key(|(_, profit)| *profit)
+            .unwrap();
+
+        // Near the optimum the curve is flat to within a wei of rounding
+        assert!(result.simulations > 10);
+        assert!(result.profit >= best_profit - I256::from_raw(U256::from(10)));
+        let distance = if result.amount_in > best_in {
+            result.amount_in - best_in
+        } else {
+            best_in - result.amount_in
+        };
+        assert!(distance <= U256::from(10) * U256::from(10).pow(U256::from(6)));
+    }
+
+    #[test]
+    fn narrow_brackets_still_converge() {
+        // The whole bracket is 1000 USDC, far narrower than any fixed wei tolerance
+        let upper = U256::from(1000) * U256::from(10).pow(U256::from(6));
+        let result = golden_section_search(
+            |amount_in| round_trip(amount_in).map(|out| out + amount_in / U256::from(1000)),
+            U256::ZERO,
+            upper,
+            &SearchConfig::default(),
+        );
+        assert!(result.simulations > 10);
+        assert!(result.profit > I256::ZERO);
+    }
+}
@@ -1,275 +1,492 @@
-use alloy::primitives::Address;
-use amms::amms::amm::{AMM, UniswapPool};
-use amms::amms::uniswap_v2::UniswapV2Pool;
-use ethers::types::{H160, U256};
-use indicatif::{ProgressBar, ProgressStyle};
-use itertools::Itertools;
+use alloy::primitives::{Address, I256, U256};
+use log::{debug, info};
 use std::vec;
-use std::{collections::HashMap, time::Instant};
+use std::{collections::HashMap, ops::RangeInclusive, time::Instant};
 
 use crate::bundler::PathParam;
-use crate::multi::Reserve;
-use crate::pools::{self, Pool};
-use crate::simulator::UniswapV2Simulator;
+use crate::multi::PoolState;
+use crate::optimizer::{self, golden_section_search, SearchConfig, SearchResult};
+use crate::pools::{DexVariant, Pool};
+use crate::simulator::{
+    BalancerWeightedSimulator, CurveStableSimulator, UniswapV2Simulator, UniswapV3Simulator,
+};
+use crate::tokens::TokenRegistry;
 
 #[derive(Debug, Clone)]
-pub struct ArbPath {
-    pub nhop: u8,
-    pub pool_1: Pool,
-    pub pool_2: Pool,
-    pub pool_3: Pool,
-    pub zero_for_one_1: bool,
-    pub zero_for_one_2: bool,
-    pub zero_for_one_3: bool,
+pub struct Hop {
+    pub pool: Pool,
+    pub zero_for_one: bool,
 }
 
-pub struct UniswapArbPath {
-    pub nhop: u8,
-    pub pool_1: UniswapPool,
-    pub pool_2: UniswapPool,
-    pub pool_3: UniswapPool,
-    pub zero_for_one_1: bool,
-    pub zero_for_one_2: bool,
-    pub zero_for_one_3: bool,
+impl Hop {
+    pub fn new(pool: Pool, zero_for_one: bool) -> Self {
+        Self { pool, zero_for_one }
+    }
+
+    pub fn token_in(&self) -> Address {
+        if self.zero_for_one {
+            self.pool.token0
+        } else {
+            self.pool.token1
+        }
+    }
+
+    pub fn token_out(&self) -> Address {
+        if self.zero_for_one {
+            self.pool.token1
+        } else {
+            self.pool.token0
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ArbPath {
+    pub hops: Vec<Hop>,
 }
 
 impl ArbPath {
-    pub fn has_pool(&self, pool: &H160) -> bool {
-        let is_pool_1 = self.pool_1.address == *pool;
-        let is_pool_2 = self.pool_2.address == *pool;
-        let is_pool_3 = self.pool_3.address == *pool;
-        return is_pool_1 || is_pool_2 || is_pool_3;
+    pub fn new(hops: Vec<Hop>) -> Self {
+        Self { hops }
+    }
+
+    pub fn nhop(&self) -> usize {
+        self.hops.len()
+    }
+
+    pub fn pools(&self) -> impl Iterator<Item = &Pool> {
+        self.hops.iter().map(|hop| &hop.pool)
+    }
+
+    pub fn has_pool(&self, pool: &Address) -> bool {
+        self.pools().any(|p| p.address == *pool)
+    }
+
+    pub fn _get_pool(&self, i: usize) -> Option<&Pool> {
+        self.hops.get(i).map(|hop| &hop.pool)
     }
 
-    pub fn _get_pool(&self, i: u8) -> &Pool {
-        match i {
-            0 => Some(&self.pool_1),
-            1 => Some(&self.pool_2),
-            2 => Some(&self.pool_3),
-            _ => None,
+    pub fn _get_zero_for_one(&self, i: usize) -> Option<bool> {
+        self.hops.get(i).map(|hop| hop.zero_for_one)
+    }
+
+    pub fn token_in_decimals(&self) -> u8 {
+        let first = &self.hops[0];
+        if first.zero_for_one {
+            first.pool.decimals0
+        } else {
+            first.pool.decimals1
         }
-        .unwrap()
     }
 
-    pub fn _get_zero_for_one(&self, i: u8) -> bool {
-        match i {
-            0 => Some(self.zero_for_one_1),
-            1 => Some(self.zero_for_one_2),
-            2 => Some(self.zero_for_one_3),
-            _ => None,
+    pub fn should_blacklist(&self, blacklist_tokens: &Vec<Address>) -> bool {
+        self.pools().any(|pool| {
+            blacklist_tokens.contains(&pool.token0) || blacklist_tokens.contains(&pool.token1)
+        })
+    }
+
+    pub fn is_all_v2(&self) -> bool {
+        self.pools()
+            .all(|pool| matches!(pool.version, DexVariant::UniswapV2))
+    }
+
+    /// Simulates the path for `amount_in` whole units of the input token.
+    pub fn simulate_v2_path(
+        &self,
+        amount_in: U256,
+        reserves: &HashMap<Address, PoolState>,
+    ) -> Option<U256> {
+        let unit = U256::from(10).pow(U256::from(self.token_in_decimals()));
+        self.simulate_v2_path_wei(amount_in * unit, reserves)
+    }
+
+    /// Simulates the path for `amount_in` in the input token's smallest unit, using
+    /// whichever simulator matches each hop's pool state.
+    pub fn simulate_v2_path_wei(
+        &self,
+        amount_in: U256,
+        reserves: &HashMap<Address, PoolState>,
+    ) -> Option<U256> {
+        let mut amount_out = amount_in;
+        for hop in &self.hops {
+            amount_out = self._simulate_hop(hop, amount_out, reserves)?;
         }
-        .unwrap()
+        Some(amount_out)
     }
 
-    pub fn should_blacklist(&self, blacklist_tokens: &Vec<H160>) -> bool {
-        for i in 0..self.nhop {
-            let pool = self._get_pool(i);
-            return blacklist_tokens.contains(&pool.token0)
-                || blacklist_tokens.contains(&pool.token1);
+    /// Like `simulate_v2_path_wei`, but each hop's output loses the token's measured
+    /// transfer tax before it reaches the next pool.
+    pub fn simulate_path_with_taxes(
+        &self,
+        amount_in: U256,
+        reserves: &HashMap<Address, PoolState>,
+        registry: &TokenRegistry,
+    ) -> Option<U256> {
+        let bps = U256::from(10_000);
+        let mut amount_out = amount_in;
+        for hop in &self.hops {
+            amount_out = self._simulate_hop(hop, amount_out, reserves)?;
+            let tax_bps = U256::from(registry.tax_bps(&hop.token_out()));
+            amount_out = amount_out * (bps - tax_bps.min(bps)) / bps;
         }
-        false
+        Some(amount_out)
     }
 
-    pub fn simulate_v2_path(
+    fn _simulate_hop(
         &self,
+        hop: &Hop,
         amount_in: U256,
-        reserves: &HashMap<H160, Reserve>,
+        reserves: &HashMap<Address, PoolState>,
     ) -> Option<U256> {
-        let token_in_decimals = if self.zero_for_one_1 {
-            self.pool_1.decimals0
-        } else {
-            self.pool_1.decimals1
-        };
-        let unit = U256::from(10).pow(U256::from(token_in_decimals));
-        let mut amount_out = amount_in * unit;
-
-        for i in 0..self.nhop {
-            let pool = self._get_pool(i);
-            let zero_for_one = self._get_zero_for_one(i);
-
-            let reserve = reserves.get(&pool.address)?;
-            let reserve0 = reserve.reserve0;
-            let reserve1 = reserve.reserve1;
-            let fee = U256::from(pool.fee);
-
-            let reserve_in;
-            let reserve_out;
-            if zero_for_one {
-                reserve_in = reserve0;
-                reserve_out = reserve1;
-            } else {
-                reserve_in = reserve1;
-                reserve_out = reserve0;
+        match reserves.get(&hop.pool.address)? {
+            PoolState::UniswapV2(_) => {
+                let (reserve_in, reserve_out) = self._hop_reserves(hop, reserves)?;
+                let fee = U256::from(hop.pool.fee);
+                UniswapV2Simulator::get_amount_out(amount_in, reserve_in, reserve_out, fee)
             }
+            PoolState::UniswapV3(state) => {
+                UniswapV3Simulator::get_amount_out(state, amount_in, hop.zero_for_one)
+            }
+            PoolState::CurveStable(state) => CurveStableSimulator::get_amount_out(
+                state,
+                amount_in,
+                hop.token_in(),
+                hop.token_out(),
+            ),
+            PoolState::BalancerWeighted(state) => BalancerWeightedSimulator::get_amount_out(
+                state,
+                amount_in,
+                hop.token_in(),
+                hop.token_out(),
+            ),
+        }
+    }
+
+    fn _hop_reserves(
+        &self,
+        hop: &Hop,
+        reserves: &HashMap<Address, PoolState>,
+    ) -> Option<(U256, U256)> {
+        let reserve = match reserves.get(&hop.pool.address)? {
+            PoolState::UniswapV2(reserve) => reserve,
+            _ => return None,
+        };
+        if hop.zero_for_one {
+            Some((reserve.reserve0, reserve.reserve1))
+        } else {
+            Some((reserve.reserve1, reserve.reserve0))
+        }
+    }
 
-            amount_out =
-                UniswapV2Simulator::get_amount_out(amount_out, reserve_in, reserve_out, fee)?;
+    /// Analytic optimum for a cycle made only of constant-product pools: the hops
+    /// are folded into one virtual pool and its closed-form optimum is taken.
+    /// Returns `(amount_in, profit)` in wei, or `None` if a hop isn't V2 or has no reserves.
+    pub fn optimal_amount_in_v2(
+        &self,
+        reserves: &HashMap<Address, PoolState>,
+    ) -> Option<(U256, U256)> {
+        if self.hops.is_empty() || !self.is_all_v2() {
+            return None;
         }
 
-        Some(amount_out)
+        let first = &self.hops[0];
+        let (mut virtual_in, mut virtual_out) = self._hop_reserves(first, reserves)?;
+        for hop in &self.hops[1..] {
+            let (reserve_in, reserve_out) = self._hop_reserves(hop, reserves)?;
+            (virtual_in, virtual_out) = UniswapV2Simulator::fold_virtual_reserves(
+                virtual_in,
+                virtual_out,
+                reserve_in,
+                reserve_out,
+                U256::from(hop.pool.fee),
+            )?;
+        }
+
+        let fee = U256::from(first.pool.fee);
+        let amount_in = UniswapV2Simulator::optimal_amount_in(virtual_in, virtual_out, fee)?;
+        if amount_in.is_zero() {
+            return Some((U256::ZERO, U256::ZERO));
+        }
+
+        // Re-simulate hop by hop so the profit reflects on-chain integer rounding
+        let amount_out = self.simulate_v2_path_wei(amount_in, reserves)?;
+        let profit = amount_out.saturating_sub(amount_in);
+        Some((amount_in, profit))
     }
 
+    /// Returns `(amount_in, profit)` in wei, with profit signed so unprofitable paths
+    /// show up as losses. See `search_amount_in`.
     pub fn optimize_amount_in(
         &self,
         max_amount_in: U256,
-        step_size: usize,
-        reserves: &HashMap<H160, Reserve>,
-    ) -> (U256, U256) {
-        let token_in_decimals = if self.zero_for_one_1 {
-            self.pool_1.decimals0
-        } else {
-            self.pool_1.decimals1
-        };
+        config: &SearchConfig,
+        reserves: &HashMap<Address, PoolState>,
+    ) -> (U256, I256) {
+        let result = self.search_amount_in(max_amount_in, config, reserves);
+        (result.amount_in, result.profit)
+    }
 
-        let mut optimized_in = U256::zero();
-        let mut profit = 0;
-
-        for amount_in in (0..max_amount_in.as_u64()).step_by(step_size) {
-            let amount_in = U256::from(amount_in);
-            let unit = U256::from(10).pow(U256::from(token_in_decimals));
-            if let Some(amount_out) = self.simulate_v2_path(amount_in, &reserves) {
-                let this_profit =
-                    (amount_out.as_u128() as i128) - ((amount_in * unit).as_u128() as i128);
-                if this_profit >= profit {
-                    optimized_in = amount_in;
-                    profit = this_profit;
-                } else {
-                    break;
-                }
-            }
+    /// V2-only paths use the closed-form optimum capped at `max_amount_in` whole tokens;
+    /// anything else runs a golden-section search over `[0, max_amount_in]` in wei.
+    pub fn search_amount_in(
+        &self,
+        max_amount_in: U256,
+        config: &SearchConfig,
+        reserves: &HashMap<Address, PoolState>,
+    ) -> SearchResult {
+        let unit = U256::from(10).pow(U256::from(self.token_in_decimals()));
+        let max_in_wei = max_amount_in * unit;
+
+        if let Some((amount_in, _)) = self.optimal_amount_in_v2(reserves) {
+            // Profit is concave in the input, so the cap is the best we can do past it
+            let amount_in = amount_in.min(max_in_wei);
+            let profit = match self.simulate_v2_path_wei(amount_in, reserves) {
+                Some(amount_out) => optimizer::profit(amount_in, amount_out),
+                None => I256::ZERO,
+            };
+            return SearchResult {
+                amount_in,
+                profit,
+                simulations: 1,
+            };
         }
 
-        (optimized_in, U256::from(profit))
+        let result = golden_section_search(
+            |amount_in| self.simulate_v2_path_wei(amount_in, reserves),
+            U256::ZERO,
+            max_in_wei,
+            config,
+        );
+        debug!(
+            "Golden-section search used {} simulations for a {}-hop path",
+            result.simulations,
+            self.nhop()
+        );
+        result
     }
 
-    pub fn to_path_params(&self, routers: &Vec<H160>) -> Vec<PathParam> {
+    /// The bot's per-hop parameters, or `None` if any hop isn't a Uniswap V2 pool. The bot
+    /// only swaps through V2 routers, so any other hop would revert on chain.
+    pub fn to_path_params(&self) -> Option<Vec<PathParam>> {
+        if !self.is_all_v2() {
+            return None;
+        }
         let mut path_params = Vec::new();
-        for i in 0..self.nhop {
-            let pool = self._get_pool(i);
-            let zero_for_one = self._get_zero_for_one(i);
-
-            let token_in;
-            let token_out;
-            if zero_for_one {
-                token_in = pool.token0;
-                token_out = pool.token1;
-            } else {
-                token_in = pool.token1;
-                token_out = pool.token0;
-            }
-
+        for hop in &self.hops {
             let param = PathParam {
-                router: routers[i as usize],
-                token_in: token_in,
-                token_out: token_out,
+                router: hop.pool.router,
+                token_in: hop.token_in(),
+                token_out: hop.token_out(),
             };
             path_params.push(param);
         }
-        path_params
+        Some(path_params)
     }
 }
 
+/// Token adjacency index over a pool set: token -> indices of the pools that trade it.
+pub struct TokenGraph<'a> {
+    pub pools: &'a [Pool],
+    pub adjacency: HashMap<Address, Vec<usize>>,
+}
 
+impl<'a> TokenGraph<'a> {
+    pub fn new(pools: &'a [Pool]) -> Self {
+        let mut adjacency: HashMap<Address, Vec<usize>> = HashMap::new();
+        for (idx, pool) in pools.iter().enumerate() {
+            if pool.token0 == pool.token1 {
+                continue;
+            }
+            adjacency.entry(pool.token0).or_default().push(idx);
+            adjacency.entry(pool.token1).or_default().push(idx);
+        }
+        Self { pools, adjacency }
+    }
 
-pub fn generate_triangular_paths(pools: &Vec<Pool>, token_in: H160) -> Vec<ArbPath> {
-    let start_time = Instant::now();
+    pub fn pools_for(&self, token: &Address) -> &[usize] {
+        self.adjacency
+            .get(token)
+            .map(|indices| indices.as_slice())
+            .unwrap_or(&[])
+    }
 
-    let token_out = token_in.clone();
-    let mut paths = Vec::new();
+    /// Enumerates every simple cycle that starts and ends at `base_token`, using
+    /// between `min_depth` and `max_depth` hops and never reusing a pool or an
+    /// intermediate token.
+    pub fn find_cycles(
+        &self,
+        base_token: Address,
+        min_depth: usize,
+        max_depth: usize,
+    ) -> Vec<ArbPath> {
+        let mut paths = Vec::new();
+        let mut stack: Vec<(usize, bool)> = Vec::new();
+        let mut visited_tokens = vec![base_token];
+
+        self._dfs(
+            base_token,
+            base_token,
+            &(min_depth.max(2)..=max_depth),
+            &mut stack,
+            &mut visited_tokens,
+            &mut paths,
+        );
+        paths
+    }
 
-    let pb = ProgressBar::new(pools.len() as u64);
-    pb.set_style(
-        ProgressStyle::with_template(
-            "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
-        )
-        .unwrap()
-        .progress_chars("##-"),
-    );
+    fn _dfs(
+        &self,
+        base_token: Address,
+        token: Address,
+        depth: &RangeInclusive<usize>,
+        stack: &mut Vec<(usize, bool)>,
+        visited_tokens: &mut Vec<Address>,
+        paths: &mut Vec<ArbPath>,
+    ) {
+        if stack.len() >= *depth.end() {
+            return;
+        }
 
-    for i in 0..pools.len() {
-        let pool_1 = &pools[i];
-        let can_trade_1 = (pool_1.token0 == token_in) || (pool_1.token1 == token_in);
+        for &idx in self.pools_for(&token) {
+            let pool = &self.pools[idx];
+            let used = stack
+                .iter()
+                .any(|(used_idx, _)| self.pools[*used_idx].address == pool.address);
+            if used {
+                continue;
+            }
 
-        if can_trade_1 {
-            let zero_for_one_1 = pool_1.token0 == token_in;
-            let (token_in_1, token_out_1) = if zero_for_one_1 {
-                (pool_1.token0, pool_1.token1)
+            let zero_for_one = pool.token0 == token;
+            let token_out = if zero_for_one {
+                pool.token1
             } else {
-                (pool_1.token1, pool_1.token0)
+                pool.token0
             };
-            if token_in_1 != token_in {
-                continue;
-            }
 
-            for j in 0..pools.len() {
-                let pool_2 = &pools[j];
-                let can_trade_2 = (pool_2.token0 == token_out_1) || (pool_2.token1 == token_out_1);
-
-                if can_trade_2 {
-                    let zero_for_one_2 = pool_2.token0 == token_out_1;
-                    let (token_in_2, token_out_2) = if zero_for_one_2 {
-                        (pool_2.token0, pool_2.token1)
-                    } else {
-                        (pool_2.token1, pool_2.token0)
-                    };
-                    if token_out_1 != token_in_2 {
-                        continue;
-                    }
-
-                    for k in 0..pools.len() {
-                        let pool_3 = &pools[k];
-                        let can_trade_3 =
-                            (pool_3.token0 == token_out_2) || (pool_3.token1 == token_out_2);
-
-                        if can_trade_3 {
-                            let zero_for_one_3 =
-                                (pool_3.token0 == token_out_2) || (pool_3.token1 == token_out_2);
-                            let (token_in_3, token_out_3) = if zero_for_one_3 {
-                                (pool_3.token0, pool_3.token1)
-                            } else {
-                                (pool_3.token1, pool_3.token0)
-                            };
-                            if token_out_2 != token_in_3 {
-                                continue;
-                            }
-
-                            if token_out_3 == token_out {
-                                let unique_pool_cnt =
-                                    vec![pool_1.address, pool_2.address, pool_3.address]
-                                        .into_iter()
-                                        .unique()
-                                        .collect::<Vec<H160>>()
-                                        .len();
-
-                                if unique_pool_cnt < 3 {
-                                    continue;
-                                }
-
-                                let arb_path = ArbPath {
-                                    nhop: 3,
-                                    pool_1: pool_1.clone(),
-                                    pool_2: pool_2.clone(),
-                                    pool_3: pool_3.clone(),
-                                    zero_for_one_1: zero_for_one_1,
-                                    zero_for_one_2: zero_for_one_2,
-                                    zero_for_one_3: zero_for_one_3,
-                                };
-
-                                paths.push(arb_path);
-                            }
-                        }
-                    }
+            stack.push((idx, zero_for_one));
+            if token_out == base_token {
+                if depth.contains(&stack.len()) {
+                    paths.push(self._to_arb_path(stack));
                 }
+            } else if !visited_tokens.contains(&token_out) {
+                visited_tokens.push(token_out);
+                self._dfs(base_token, token_out, depth, stack, visited_tokens, paths);
+                visited_tokens.pop();
             }
+            stack.pop();
         }
+    }
 
-        pb.inc(1);
+    fn _to_arb_path(&self, stack: &[(usize, bool)]) -> ArbPath {
+        ArbPath::new(
+            stack
+                .iter()
+                .map(|(idx, zero_for_one)| Hop::new(self.pools[*idx].clone(), *zero_for_one))
+                .collect(),
+        )
     }
+}
+
+pub fn generate_cycle_paths(
+    pools: &Vec<Pool>,
+    token_in: Address,
+    min_depth: usize,
+    max_depth: usize,
+) -> Vec<ArbPath> {
+    let start_time = Instant::now();
 
-    pb.finish_with_message(format!(
-        "Generated {} 3-hop arbitrage paths in {} seconds",
+    let graph = TokenGraph::new(pools);
+    let paths = graph.find_cycles(token_in, min_depth, max_depth);
+
+    info!(
+        "Generated {} arbitrage paths ({}-{} hops) from {} pools in {} ms",
         paths.len(),
-        start_time.elapsed().as_secs()
-    ));
+        min_depth,
+        max_depth,
+        pools.len(),
+        start_time.elapsed().as_millis()
+    );
     paths
 }
+
+pub fn generate_triangular_paths(pools: &Vec<Pool>, token_in: Address) -> Vec<ArbPath> {
+    generate_cycle_paths(pools, token_in, 3, 3)
+}
+
+#[cfg(test)]
+mod paths_tests {
+    use super::*;
+
+    fn token(byte: u8) -> Address {
+        Address::repeat_byte(byte)
+    }
+
+    fn pool(address: u8, token0: u8, token1: u8) -> Pool {
+        Pool {
+            address: token(address),
+            version: DexVariant::UniswapV2,
+            token0: token(token0),
+            token1: token(token1),
+            decimals0: 18,
+            decimals1: 18,
+            fee: 3000,
+            router: token(0xee),
+        }
+    }
+
+    #[test]
+    fn finds_every_simple_cycle_through_the_base_token() {
+        // Two pools for 1/2, a triangle 1-2-3, a triangle 1-3-4 and a square 1-2-3-4
+        let pools = vec![
+            pool(0xa0, 1, 2),
+            pool(0xa1, 1, 2),
+            pool(0xa2, 2, 3),
+            pool(0xa3, 1, 3),
+            pool(0xa4, 3, 4),
+            pool(0xa5, 1, 4),
+        ];
+        let graph = TokenGraph::new(&pools);
+
+        // 1-2-3-1 and 1-3-4-1 in both directions, with either 1/2 pool on the first
+        assert_eq!(graph.find_cycles(token(1), 3, 3).len(), 6);
+        // Plus 1-2-1 through both 1/2 pools, in either order
+        assert_eq!(graph.find_cycles(token(1), 2, 3).len(), 8);
+        // Plus 1-2-3-4-1 in both directions, with either 1/2 pool
+        let paths = graph.find_cycles(token(1), 2, 4);
+        assert_eq!(paths.len(), 12);
+
+        for path in &paths {
+            assert_eq!(path.hops[0].token_in(), token(1));
+            assert_eq!(path.hops[path.nhop() - 1].token_out(), token(1));
+            for pair in path.hops.windows(2) {
+                assert_eq!(pair[0].token_out(), pair[1].token_in());
+            }
+            let mut addresses: Vec<Address> = path.pools().map(|pool| pool.address).collect();
+            addresses.sort();
+            addresses.dedup();
+            assert_eq!(addresses.len(), path.nhop());
+        }
+
+        assert!(graph.find_cycles(token(9), 2, 4).is_empty());
+    }
+
+    #[test]
+    fn only_v2_paths_have_path_params() {
+        let mut pools = vec![pool(0xa0, 1, 2), pool(0xa1, 2, 3), pool(0xa2, 1, 3)];
+        let hops = |pools: &[Pool]| {
+            ArbPath::new(vec![
+                Hop::new(pools[0].clone(), true),
+                Hop::new(pools[1].clone(), true),
+                Hop::new(pools[2].clone(), false),
+            ])
+        };
+
+        let params = hops(&pools).to_path_params().unwrap();
+        assert_eq!(params.len(), 3);
+        assert_eq!(params[2].token_in, token(3));
+        assert_eq!(params[2].router, token(0xee));
+
+        pools[1].version = DexVariant::CurveStable;
+        assert!(hops(&pools).to_path_params().is_none());
+    }
+}
@@ -1,156 +1,1027 @@
-use anyhow::{Ok, Result};
-use cfmms::{
-    dex::{Dex, DexVariant as CfmmsDexVariant},
-    pool::Pool as CfmmsPool,
-    sync::sync_pairs,
+use alloy::{
+    primitives::{Address, U256},
+    providers::{MulticallItem, Provider, ProviderBuilder, WsConnect},
+    rpc::types::Filter,
+    sol_types::SolEvent,
 };
+use anyhow::{anyhow, Ok, Result};
 use csv::StringRecord;
-use ethers::{
-    providers::{Provider, Ws},
-    types::H160,
-};
+use itertools::Itertools;
 use log::info;
-use std::{path::Path, str::FromStr, sync::Arc};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+    str::FromStr,
+};
+use thiserror::Error;
+
+use crate::dune::fetch_query_rows;
+use crate::multi::{
+    BalancerVault, BalancerWeightedPool, CurveRegistry, Erc20Metadata, PoolState, PoolTokens,
+    UniswapV2Factory,
+};
 
 #[derive(Debug, Clone)]
 pub enum DexVariant {
     UniswapV2,
     UniswapV3,
+    CurveStable,
+    BalancerWeighted,
 }
 
 #[derive(Debug, Clone)]
 pub struct Pool {
-    pub address: H160,
+    pub address: Address,
     pub version: DexVariant,
-    pub token0: H160,
-    pub token1: H160,
+    pub token0: Address,
+    pub token1: Address,
     pub decimals0: u8,
     pub decimals1: u8,
+    /// Swap fee in pips (millionths) whatever the variant, so 3000 is 0.30%.
     pub fee: u32,
+    /// Contract the bundler routes this pool's swaps through.
+    pub router: Address,
 }
 
-impl From<StringRecord> for Pool {
-    fn from(record: StringRecord) -> Self {
-        let version = if record.get(1).unwrap() == "2" {
-            DexVariant::UniswapV2
-        } else {
-            DexVariant::UniswapV3
-        };
+/// A Uniswap V2 fork: its factory, the router that trades its pairs, the pair fee
+/// (in pips like `Pool.fee`, so 3000 = 0.30%) and the factory's deploy block.
+#[derive(Debug, Clone)]
+pub struct V2Factory {
+    pub address: Address,
+    pub router: Address,
+    pub fee: u32,
+    pub deploy_block: u64,
+}
+
+impl V2Factory {
+    pub fn new(address: &str, router: &str, fee: u32, deploy_block: u64) -> Self {
         Self {
-            address: H160::from_str(record.get(0).unwrap()).unwrap(),
-            version,
-            token0: H160::from_str(record.get(2).unwrap()).unwrap(),
-            token1: H160::from_str(record.get(3).unwrap()).unwrap(),
-            decimals0: record.get(4).unwrap().parse().unwrap(),
-            decimals1: record.get(5).unwrap().parse().unwrap(),
-            fee: record.get(6).unwrap().parse().unwrap(),
+            address: Address::from_str(address).unwrap(),
+            router: Address::from_str(router).unwrap(),
+            fee,
+            deploy_block,
         }
     }
 }
 
+/// Bumped whenever the pool cache's columns change; older caches are rebuilt.
+pub const POOL_CACHE_VERSION: u32 = 3;
+const POOL_CACHE_HEADER: [&str; 8] = [
+    "address",
+    "version",
+    "token0",
+    "token1",
+    "decimals0",
+    "decimals1",
+    "fee",
+    "router",
+];
+
+#[derive(Debug, Error)]
+pub enum PoolCacheError {
+    #[error("pool cache version {found:?} does not match {expected}")]
+    Version {
+        found: Option<String>,
+        expected: u32,
+    },
+    #[error("pool cache header {found:?} does not match {expected:?}")]
+    Header {
+        found: Vec<String>,
+        expected: Vec<String>,
+    },
+    #[error("pool cache line {line}: missing column `{column}`")]
+    MissingColumn { line: u64, column: &'static str },
+    #[error("pool cache line {line}: invalid `{column}` value {value:?}")]
+    InvalidColumn {
+        line: u64,
+        column: &'static str,
+        value: String,
+    },
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+}
+
+impl TryFrom<StringRecord> for Pool {
+    type Error = PoolCacheError;
+
+    fn try_from(record: StringRecord) -> std::result::Result<Self, Self::Error> {
+        let version = match parse_cache_field::<u8>(&record, 1)? {
+            2 => DexVariant::UniswapV2,
+            3 => DexVariant::UniswapV3,
+            4 => DexVariant::CurveStable,
+            5 => DexVariant::BalancerWeighted,
+            _ => return Err(invalid_cache_field(&record, 1)),
+        };
+        std::result::Result::Ok(Self {
+            address: parse_cache_field(&record, 0)?,
+            version,
+            token0: parse_cache_field(&record, 2)?,
+            token1: parse_cache_field(&record, 3)?,
+            decimals0: parse_cache_field(&record, 4)?,
+            decimals1: parse_cache_field(&record, 5)?,
+            fee: parse_cache_field(&record, 6)?,
+            router: parse_cache_field(&record, 7)?,
+        })
+    }
+}
+
+fn parse_cache_field<T: FromStr>(
+    record: &StringRecord,
+    idx: usize,
+) -> std::result::Result<T, PoolCacheError> {
+    let line = record.position().map(|pos| pos.line()).unwrap_or_default();
+    let column = POOL_CACHE_HEADER[idx];
+    let value = record
+        .get(idx)
+        .ok_or(PoolCacheError::MissingColumn { line, column })?;
+    value.parse().map_err(|_| invalid_cache_field(record, idx))
+}
+
+fn invalid_cache_field(record: &StringRecord, idx: usize) -> PoolCacheError {
+    PoolCacheError::InvalidColumn {
+        line: record.position().map(|pos| pos.line()).unwrap_or_default(),
+        column: POOL_CACHE_HEADER[idx],
+        value: record.get(idx).unwrap_or_default().to_string(),
+    }
+}
+
 impl Pool {
-    pub fn cache_row(&self) -> (String, i32, String, String, u8, u8, u32) {
+    pub fn cache_row(&self) -> (String, i32, String, String, u8, u8, u32, String) {
         (
             format!("{:?}", self.address),
             match self.version {
                 DexVariant::UniswapV2 => 2,
                 DexVariant::UniswapV3 => 3,
+                DexVariant::CurveStable => 4,
+                DexVariant::BalancerWeighted => 5,
             },
             format!("{:?}", self.token0),
             format!("{:?}", self.token1),
             self.decimals0,
             self.decimals1,
             self.fee,
+            format!("{:?}", self.router),
         )
     }
 }
 
-pub async fn load_all_pools_from_v2(
-    wss_url: String,
-    factory_addresses: Vec<&str>,
-    from_blocks: Vec<u64>,
+const POOL_CACHE_PATH: &str = "src/.cached-pools.csv";
+// Last block each factory's pairs were synced to, next to the pool cache
+const POOL_CACHE_BLOCKS_PATH: &str = "src/.cached-pools-blocks.csv";
+// Block range per eth_getLogs request when catching up on PairCreated events
+const PAIR_CREATED_BLOCK_RANGE: u64 = 50_000;
+
+/// Loads the V2 pairs of every factory, starting from the on-disk cache when there is one.
+/// Factories already in the cache only fetch `PairCreated` events since their last synced
+/// block; new factories are synced from their deploy block. The merged cache is then
+/// rewritten.
+pub async fn load_all_pools_from_v2(wss_url: String, factories: &[V2Factory]) -> Result<Vec<Pool>> {
+    let (mut pools_vec, mut synced_blocks) = if Path::new(POOL_CACHE_PATH).exists() {
+        match read_pool_cache() {
+            std::result::Result::Ok(cache) => cache,
+            // A cache from another schema is rebuilt rather than misread
+            Err(e @ (PoolCacheError::Version { .. } | PoolCacheError::Header { .. })) => {
+                info!("Discarding pool cache: {}", e);
+                (Vec::new(), HashMap::new())
+            }
+            Err(e) => return Err(e.into()),
+        }
+    } else {
+        (Vec::new(), HashMap::new())
+    };
+    let mut known: HashSet<Address> = pools_vec.iter().map(|pool| pool.address).collect();
+
+    let provider = ProviderBuilder::new()
+        .connect_ws(WsConnect::new(wss_url))
+        .await?;
+    let latest_block = provider.get_block_number().await?;
+
+    // Each factory is synced on its own so every pair keeps its factory's router
+    for factory in factories {
+        let from_block = match synced_blocks.get(&factory.address) {
+            Some(last_block) if *last_block >= latest_block => continue,
+            Some(last_block) => last_block + 1,
+            None => factory.deploy_block,
+        };
+        let new_pools = fetch_created_pairs(&provider, factory, from_block, latest_block).await?;
+        info!(
+            "Synced {} new pools from {:?}",
+            new_pools.len(),
+            factory.address
+        );
+
+        for pool in new_pools {
+            if known.insert(pool.address) {
+                pools_vec.push(pool);
+            }
+        }
+        synced_blocks.insert(factory.address, latest_block);
+    }
+    info!("Synced to {} pools", pools_vec.len());
+
+    write_pool_cache(&pools_vec, &synced_blocks)?;
+
+    Ok(pools_vec)
+}
+
+/// Pairs created by `factory` in `[from_block, to_block]`, read from its `PairCreated` events.
+/// Pairs whose tokens don't answer `decimals()` are skipped.
+async fn fetch_created_pairs<P: Provider>(
+    provider: &P,
+    factory: &V2Factory,
+    from_block: u64,
+    to_block: u64,
 ) -> Result<Vec<Pool>> {
-    // Load from cached file if the file exists
-    let file_path = Path::new("src/.cached-pools.csv");
-    let checkpoint_path = Some("src/sync_pools_checkpoint.json");
-    if file_path.exists() {
-        let mut reader = csv::Reader::from_path(file_path)?;
-
-        let mut pools_vec: Vec<Pool> = Vec::new();
-        for row in reader.records() {
-            let row = row.unwrap();
-            let pool = Pool::from(row);
-            pools_vec.push(pool);
+    let mut pools_vec = Vec::new();
+    let mut start = from_block;
+    while start <= to_block {
+        let end = std::cmp::min(start + PAIR_CREATED_BLOCK_RANGE, to_block);
+        let filter = Filter::new()
+            .address(factory.address)
+            .event_signature(UniswapV2Factory::PairCreated::SIGNATURE_HASH)
+            .from_block(start)
+            .to_block(end);
+        let events: Vec<UniswapV2Factory::PairCreated> = provider
+            .get_logs(&filter)
+            .await?
+            .iter()
+            .filter_map(|log| log.log_decode().ok())
+            .map(|log| log.inner.data)
+            .collect();
+
+        for chunk in events.chunks(250) {
+            let tokens: Vec<Address> = chunk
+                .iter()
+                .flat_map(|event| [event.token0, event.token1])
+                .collect();
+            let decimals = fetch_decimals(provider, &tokens).await?;
+
+            for (event, decimals) in chunk.iter().zip(decimals.chunks(2)) {
+                let (decimals0, decimals1) = match (decimals[0], decimals[1]) {
+                    (Some(decimals0), Some(decimals1)) => (decimals0, decimals1),
+                    _ => continue,
+                };
+                pools_vec.push(Pool {
+                    address: event.pair,
+                    version: DexVariant::UniswapV2,
+                    token0: event.token0,
+                    token1: event.token1,
+                    decimals0,
+                    decimals1,
+                    fee: factory.fee,
+                    router: factory.router,
+                });
+            }
         }
-        return Ok(pools_vec);
+        start = end + 1;
     }
 
-    let ws = Ws::connect(wss_url).await?;
-    let provider = Arc::new(Provider::new(ws));
+    Ok(pools_vec)
+}
+
+/// `decimals()` of each token in one multicall, `None` for tokens that don't answer it.
+async fn fetch_decimals<P: Provider>(provider: &P, tokens: &[Address]) -> Result<Vec<Option<u8>>> {
+    let mut multicall = provider
+        .multicall()
+        .dynamic::<Erc20Metadata::decimalsCall>();
+    for token in tokens {
+        let contract = Erc20Metadata::new(*token, provider);
+        multicall = multicall.add_call_dynamic(contract.decimals().into_call(true));
+    }
+    let result = multicall.aggregate3().await?;
+    Ok(result.into_iter().map(|decimals| decimals.ok()).collect())
+}
 
-    let mut dexes_data = Vec::new();
+/// Reads the pool cache and the per-factory synced blocks. The version line and header
+/// must match the current schema; individual bad rows are logged and skipped.
+fn read_pool_cache() -> std::result::Result<(Vec<Pool>, HashMap<Address, u64>), PoolCacheError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_path(POOL_CACHE_PATH)?;
+    let mut records = reader.records();
 
-    for i in 0..factory_addresses.len() {
-        dexes_data.push((
-            factory_addresses[i].clone(),
-            CfmmsDexVariant::UniswapV2,
-            from_blocks[i],
-        ))
+    let version = records.next().transpose()?;
+    let found = version
+        .as_ref()
+        .filter(|record| record.get(0) == Some("pool-cache"))
+        .and_then(|record| record.get(1));
+    if found != Some(POOL_CACHE_VERSION.to_string().as_str()) {
+        return Err(PoolCacheError::Version {
+            found: found.map(String::from),
+            expected: POOL_CACHE_VERSION,
+        });
     }
 
-    let dexes: Vec<_> = dexes_data
-        .into_iter()
-        .map(|(address, variant, number)| {
-            Dex::new(
-                H160::from_str(&address).unwrap(),
-                variant,
-                number,
-                Some(3000),
-            )
-        })
-        .collect();
+    let header = records.next().transpose()?.unwrap_or_default();
+    if header.iter().ne(POOL_CACHE_HEADER) {
+        return Err(PoolCacheError::Header {
+            found: header.iter().map(String::from).collect(),
+            expected: POOL_CACHE_HEADER.iter().map(|c| c.to_string()).collect(),
+        });
+    }
 
-    let pools_vec: Vec<CfmmsPool> = sync_pairs(dexes.clone(), provider.clone(), checkpoint_path).await?;
-    let pools_vec: Vec<Pool> = pools_vec
-        .into_iter()
-        .map(|pool| match pool {
-            CfmmsPool::UniswapV2(pool) => Pool {
-                address: pool.address,
-                version: DexVariant::UniswapV2,
-                token0: pool.token_a,
-                token1: pool.token_b,
-                decimals0: pool.token_a_decimals,
-                decimals1: pool.token_b_decimals,
-                fee: pool.fee,
-            },
-            CfmmsPool::UniswapV3(pool) => Pool {
-                address: pool.address,
-                version: DexVariant::UniswapV3,
-                token0: pool.token_a,
-                token1: pool.token_b,
-                decimals0: pool.token_a_decimals,
-                decimals1: pool.token_b_decimals,
-                fee: pool.fee,
-            },
-        })
-        .collect();
-    info!("Synced to {} pools", pools_vec.len());
+    let mut pools_vec: Vec<Pool> = Vec::new();
+    let mut skipped = 0;
+    for row in records {
+        match row.map_err(PoolCacheError::from).and_then(Pool::try_from) {
+            std::result::Result::Ok(pool) => pools_vec.push(pool),
+            Err(e) => {
+                info!("Skipping pool cache row: {}", e);
+                skipped += 1;
+            }
+        }
+    }
+    if skipped > 0 {
+        info!("Skipped {} malformed pool cache rows", skipped);
+    }
 
-    let mut writer = csv::Writer::from_path(file_path)?;
-    writer.write_record(&[
-        "address",
-        "version",
-        "token0",
-        "token1",
-        "decimals0",
-        "decimals1",
-        "fee",
-    ])?;
-
-    for pool in &pools_vec {
+    // Caches written before block tracking have no blocks file; their factories resync
+    let mut synced_blocks = HashMap::new();
+    if Path::new(POOL_CACHE_BLOCKS_PATH).exists() {
+        let mut reader = csv::Reader::from_path(POOL_CACHE_BLOCKS_PATH)?;
+        for row in reader.deserialize() {
+            let (factory, block): (String, u64) = row?;
+            match Address::from_str(&factory) {
+                std::result::Result::Ok(factory) => {
+                    synced_blocks.insert(factory, block);
+                }
+                Err(_) => info!("Skipping synced block for invalid factory {:?}", factory),
+            }
+        }
+    }
+
+    std::result::Result::Ok((pools_vec, synced_blocks))
+}
+
+/// Writes both cache files to temporary paths and renames them into place, so a crash
+/// mid-write never leaves a truncated cache behind.
+fn write_pool_cache(pools: &[Pool], synced_blocks: &HashMap<Address, u64>) -> Result<()> {
+    let pools_tmp = format!("{}.tmp", POOL_CACHE_PATH);
+    let mut writer = csv::WriterBuilder::new()
+        .flexible(true)
+        .from_path(&pools_tmp)?;
+    writer.write_record(["pool-cache", POOL_CACHE_VERSION.to_string().as_str()])?;
+    writer.write_record(POOL_CACHE_HEADER)?;
+    for pool in pools {
         writer.serialize(pool.cache_row())?;
     }
     writer.flush()?;
 
+    let blocks_tmp = format!("{}.tmp", POOL_CACHE_BLOCKS_PATH);
+    let mut writer = csv::Writer::from_path(&blocks_tmp)?;
+    writer.write_record(&["factory", "block"])?;
+    for (factory, block) in synced_blocks {
+        writer.serialize((format!("{:?}", factory), block))?;
+    }
+    writer.flush()?;
+
+    // Pools first: a stale blocks file only means refetching events already merged
+    fs::rename(&pools_tmp, POOL_CACHE_PATH)?;
+    fs::rename(&blocks_tmp, POOL_CACHE_BLOCKS_PATH)?;
+
+    Ok(())
+}
+
+/// One row of the Dune pool query. `factory` and `block_number` are optional; with them
+/// the cache also records how far each factory is synced.
+#[derive(Deserialize, Debug, Clone)]
+pub struct DunePoolRow {
+    pub address: String,
+    pub version: u8,
+    pub token0: String,
+    pub token1: String,
+    pub decimals0: u8,
+    pub decimals1: u8,
+    pub fee: u32,
+    pub factory: Option<String>,
+    pub block_number: Option<u64>,
+}
+
+impl DunePoolRow {
+    /// Builds the pool, taking its router from the matching entry in `factories`. Rows
+    /// without a factory, or from one not in `factories`, have no router to trade through
+    /// and are rejected. V2 pools take the factory's fee, since the query reports it in
+    /// another unit; V3 fees are already in pips.
+    pub fn to_pool(&self, factories: &[V2Factory]) -> Result<Pool> {
+        let version = match self.version {
+            2 => DexVariant::UniswapV2,
+            3 => DexVariant::UniswapV3,
+            other => return Err(anyhow!("unsupported pool version {}", other)),
+        };
+        let factory = match self.factory.as_deref() {
+            Some(factory) => Address::from_str(factory)?,
+            None => return Err(anyhow!("pool has no factory")),
+        };
+        let factory = factories
+            .iter()
+            .find(|known| known.address == factory)
+            .ok_or_else(|| anyhow!("unknown factory {:?}", factory))?;
+        let fee = match version {
+            DexVariant::UniswapV2 => factory.fee,
+            _ => self.fee,
+        };
+
+        Ok(Pool {
+            address: Address::from_str(&self.address)?,
+            version,
+            token0: Address::from_str(&self.token0)?,
+            token1: Address::from_str(&self.token1)?,
+            decimals0: self.decimals0,
+            decimals1: self.decimals1,
+            fee,
+            router: factory.router,
+        })
+    }
+}
+
+/// Bootstraps the pool cache from a Dune query instead of a full log sync. Rows are kept
+/// only if the pool's on-chain `token0()`/`token1()` match, and are merged into any
+/// existing cache.
+pub async fn load_pools_from_dune(
+    https_url: String,
+    dune_url: &str,
+    dune_api_key: &str,
+    query_id: u32,
+    factories: &[V2Factory],
+) -> Result<Vec<Pool>> {
+    let rows: Vec<DunePoolRow> = fetch_query_rows(dune_url, dune_api_key, query_id).await?;
+    info!("Dune query {} returned {} pools", query_id, rows.len());
+
+    let mut candidates = Vec::new();
+    for row in &rows {
+        match row.to_pool(factories) {
+            std::result::Result::Ok(pool) => candidates.push((pool, row)),
+            Err(e) => info!("Skipping Dune row {:?}: {:?}", row.address, e),
+        }
+    }
+
+    let provider = ProviderBuilder::new().connect_http(https_url.parse()?);
+    let mut validated = Vec::new();
+    let mut synced_blocks: HashMap<Address, u64> = HashMap::new();
+    for chunk in candidates.chunks(250) {
+        let mut token0_calls = provider.multicall().dynamic::<PoolTokens::token0Call>();
+        let mut token1_calls = provider.multicall().dynamic::<PoolTokens::token1Call>();
+        for (pool, _) in chunk {
+            let contract = PoolTokens::new(pool.address, &provider);
+            token0_calls = token0_calls.add_call_dynamic(contract.token0().into_call(true));
+            token1_calls = token1_calls.add_call_dynamic(contract.token1().into_call(true));
+        }
+        let (token0s, token1s) =
+            tokio::try_join!(token0_calls.aggregate3(), token1_calls.aggregate3())?;
+
+        for (i, (pool, row)) in chunk.iter().enumerate() {
+            let token_matches = |result: &std::result::Result<Address, _>, token: Address| matches!(result, std::result::Result::Ok(address) if *address == token);
+            if !token_matches(&token0s[i], pool.token0) || !token_matches(&token1s[i], pool.token1)
+            {
+                info!("Dropping Dune pool {:?}: tokens don't match", pool.address);
+                continue;
+            }
+            if let (Some(factory), Some(block)) = (&row.factory, row.block_number) {
+                let factory = Address::from_str(factory)?;
+                let synced = synced_blocks.entry(factory).or_default();
+                *synced = (*synced).max(block);
+            }
+            validated.push(pool.clone());
+        }
+    }
+    info!("Validated {} of {} Dune pools", validated.len(), rows.len());
+
+    // An unreadable cache fails the merge rather than being overwritten with Dune rows
+    let (mut pools_vec, mut cached_blocks) = if Path::new(POOL_CACHE_PATH).exists() {
+        read_pool_cache()?
+    } else {
+        (Vec::new(), HashMap::new())
+    };
+    let mut known: HashSet<Address> = pools_vec.iter().map(|pool| pool.address).collect();
+    for pool in validated {
+        if known.insert(pool.address) {
+            pools_vec.push(pool);
+        }
+    }
+    for (factory, block) in synced_blocks {
+        let synced = cached_blocks.entry(factory).or_default();
+        *synced = (*synced).max(block);
+    }
+    write_pool_cache(&pools_vec, &cached_blocks)?;
+
+    Ok(pools_vec)
+}
+
+/// Drops pools holding less than `min_weth` worth of tokens. Token prices come from
+/// V2 pools that pair the token with WETH and hold at least `min_weth` of WETH themselves,
+/// so dust pools can't set prices. V3 pools and pools with no priced token are kept,
+/// since they can't be valued.
+#[derive(Debug, Clone)]
+pub struct LiquidityFilter {
+    pub weth: Address,
+    pub min_weth: U256,
+}
+
+impl LiquidityFilter {
+    pub fn new(weth: Address, min_weth: U256) -> Self {
+        Self { weth, min_weth }
+    }
+
+    /// Price of each token in WETH wei per token wei, scaled by 1e18.
+    pub fn token_prices(
+        &self,
+        pools: &[Pool],
+        states: &HashMap<Address, PoolState>,
+    ) -> HashMap<Address, U256> {
+        let scale = U256::from(10).pow(U256::from(18));
+        // token -> (WETH reserve of the pricing pool, price)
+        let mut best: HashMap<Address, (U256, U256)> = HashMap::new();
+        for pool in pools {
+            let reserve = match states.get(&pool.address) {
+                Some(PoolState::UniswapV2(reserve)) => reserve,
+                _ => continue,
+            };
+            let (token, token_reserve, weth_reserve) = if pool.token0 == self.weth {
+                (pool.token1, reserve.reserve1, reserve.reserve0)
+            } else if pool.token1 == self.weth {
+                (pool.token0, reserve.reserve0, reserve.reserve1)
+            } else {
+                continue;
+            };
+            if token_reserve.is_zero() || weth_reserve < self.min_weth {
+                continue;
+            }
+            let price = match weth_reserve.checked_mul(scale) {
+                Some(scaled) => scaled / token_reserve,
+                None => continue,
+            };
+            let deepest = best.get(&token).map(|(depth, _)| *depth);
+            if deepest.map_or(true, |depth| weth_reserve > depth) {
+                best.insert(token, (weth_reserve, price));
+            }
+        }
+
+        let mut prices: HashMap<Address, U256> = best
+            .into_iter()
+            .map(|(token, (_, price))| (token, price))
+            .collect();
+        prices.insert(self.weth, scale);
+        prices
+    }
+
+    /// WETH value of a pool's balances, or `None` when the pool can't be valued.
+    /// If only one side has a price, the pool is assumed balanced and that side doubled.
+    pub fn pool_value(
+        &self,
+        pool: &Pool,
+        states: &HashMap<Address, PoolState>,
+        prices: &HashMap<Address, U256>,
+    ) -> Option<U256> {
+        let balances: Vec<(Address, U256)> = match states.get(&pool.address)? {
+            PoolState::UniswapV2(reserve) => vec![
+                (pool.token0, reserve.reserve0),
+                (pool.token1, reserve.reserve1),
+            ],
+            PoolState::UniswapV3(_) => return None,
+            PoolState::CurveStable(state) => state
+                .coins
+                .iter()
+                .cloned()
+                .zip(state.balances.iter().cloned())
+                .collect(),
+            PoolState::BalancerWeighted(state) => state
+                .tokens
+                .iter()
+                .cloned()
+                .zip(state.balances.iter().cloned())
+                .collect(),
+        };
+
+        let scale = U256::from(10).pow(U256::from(18));
+        let mut value = U256::ZERO;
+        let mut priced = 0;
+        for (token, balance) in &balances {
+            if let Some(price) = prices.get(token) {
+                value = value.saturating_add(balance.saturating_mul(*price) / scale);
+                priced += 1;
+            }
+        }
+        if priced == 0 {
+            return None;
+        }
+        Some(value.saturating_mul(U256::from(balances.len())) / U256::from(priced))
+    }
+
+    /// The V2 pools in `pools` that pair WETH with a token of `pools_to_price`, i.e. the
+    /// ones `token_prices` needs to value them.
+    pub fn pricing_pools(&self, pools: &[Pool], pools_to_price: &[Pool]) -> Vec<Pool> {
+        let tokens: HashSet<Address> = pools_to_price
+            .iter()
+            .flat_map(|pool| [pool.token0, pool.token1])
+            .collect();
+        pools
+            .iter()
+            .filter(|pool| matches!(pool.version, DexVariant::UniswapV2))
+            .filter(|pool| {
+                (pool.token0 == self.weth && tokens.contains(&pool.token1))
+                    || (pool.token1 == self.weth && tokens.contains(&pool.token0))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// The pools worth at least `min_weth`, plus any that can't be valued. Tokens are
+    /// priced off `pools` themselves.
+    pub fn filter(&self, pools: &[Pool], states: &HashMap<Address, PoolState>) -> Vec<Pool> {
+        let prices = self.token_prices(pools, states);
+        self.filter_with_prices(pools, states, &prices)
+    }
+
+    /// Like `filter`, with prices from `token_prices` over a wider pool set.
+    pub fn filter_with_prices(
+        &self,
+        pools: &[Pool],
+        states: &HashMap<Address, PoolState>,
+        prices: &HashMap<Address, U256>,
+    ) -> Vec<Pool> {
+        let kept: Vec<Pool> = pools
+            .iter()
+            .filter(|pool| match self.pool_value(pool, states, prices) {
+                Some(value) => value >= self.min_weth,
+                None => true,
+            })
+            .cloned()
+            .collect();
+        info!(
+            "Liquidity filter kept {} of {} pools",
+            kept.len(),
+            pools.len()
+        );
+        kept
+    }
+}
+
+/// Loads every plain Curve StableSwap pool in the registry. Each pool is expanded
+/// into one `Pool` per coin pair, all sharing the pool address, so path generation
+/// can treat Curve like any two-token pool.
+pub async fn load_curve_pools(https_url: String, registry: &str) -> Result<Vec<Pool>> {
+    let provider = ProviderBuilder::new().connect_http(https_url.parse()?);
+    let registry = CurveRegistry::new(Address::from_str(registry)?, &provider);
+
+    let pool_count = registry.pool_count().call().await?.to::<u64>();
+
+    let mut multicall = provider
+        .multicall()
+        .dynamic::<CurveRegistry::pool_listCall>();
+    for i in 0..pool_count {
+        multicall = multicall.add_dynamic(registry.pool_list(U256::from(i)));
+    }
+    let addresses: Vec<Address> = multicall.aggregate().await?;
+
+    let mut pools_vec = Vec::new();
+    for address in addresses {
+        let (is_meta, n_coins, coins, decimals, fees) = (
+            registry.is_meta(address),
+            registry.get_n_coins(address),
+            registry.get_coins(address),
+            registry.get_decimals(address),
+            registry.get_fees(address),
+        );
+        let (is_meta, n_coins, coins, decimals, fees) = tokio::try_join!(
+            is_meta.call(),
+            n_coins.call(),
+            coins.call(),
+            decimals.call(),
+            fees.call(),
+        )?;
+        // Metapools price their base LP token off another pool; not supported yet
+        if is_meta {
+            continue;
+        }
+
+        // 1e10 fixed point -> pips
+        let fee = match u32::try_from(fees[0] / U256::from(10).pow(U256::from(4))) {
+            std::result::Result::Ok(fee) => fee,
+            Err(_) => {
+                info!(
+                    "Skipping Curve pool {:?}: fee {} out of range",
+                    address, fees[0]
+                );
+                continue;
+            }
+        };
+        let n = n_coins[0].to::<usize>().min(coins.len());
+        for (i, j) in (0..n).tuple_combinations() {
+            pools_vec.push(Pool {
+                address,
+                version: DexVariant::CurveStable,
+                token0: coins[i],
+                token1: coins[j],
+                decimals0: decimals[i].to::<u8>(),
+                decimals1: decimals[j].to::<u8>(),
+                fee,
+                // Curve pools are swapped through directly
+                router: address,
+            });
+        }
+    }
+    info!("Loaded {} Curve pool pairs", pools_vec.len());
+
+    Ok(pools_vec)
+}
+
+/// Discovers Balancer V2 weighted pools from the Vault's `PoolRegistered` events.
+/// Pools without `getNormalizedWeights` (stable, linear, ...) are skipped. Like Curve,
+/// each pool is expanded into one `Pool` per token pair, with `fee` in pips.
+pub async fn load_balancer_weighted_pools(
+    https_url: String,
+    vault: &str,
+    from_block: u64,
+) -> Result<Vec<Pool>> {
+    let provider = ProviderBuilder::new().connect_http(https_url.parse()?);
+    let vault = BalancerVault::new(Address::from_str(vault)?, &provider);
+    let latest_block = provider.get_block_number().await?;
+
+    let mut registered = Vec::new();
+    let mut start = from_block;
+    while start <= latest_block {
+        let end = std::cmp::min(start + 50_000, latest_block);
+        let filter = Filter::new()
+            .address(*vault.address())
+            .event_signature(BalancerVault::PoolRegistered::SIGNATURE_HASH)
+            .from_block(start)
+            .to_block(end);
+        for log in provider.get_logs(&filter).await? {
+            if let [_, _, pool] = log.topics() {
+                registered.push(Address::from_word(*pool));
+            }
+        }
+        start = end + 1;
+    }
+    info!("Found {} registered Balancer pools", registered.len());
+
+    // Probe the registered pools in batches. Anything that isn't a weighted pool, or
+    // fails one of the calls, is skipped rather than aborting the whole load.
+    let mut probed = Vec::new();
+    for chunk in registered.chunks(250) {
+        let mut weight_calls = provider
+            .multicall()
+            .dynamic::<BalancerWeightedPool::getNormalizedWeightsCall>();
+        let mut pool_id_calls = provider
+            .multicall()
+            .dynamic::<BalancerWeightedPool::getPoolIdCall>();
+        let mut swap_fee_calls = provider
+            .multicall()
+            .dynamic::<BalancerWeightedPool::getSwapFeePercentageCall>();
+        for address in chunk {
+            let contract = BalancerWeightedPool::new(*address, &provider);
+            weight_calls =
+                weight_calls.add_call_dynamic(contract.getNormalizedWeights().into_call(true));
+            pool_id_calls = pool_id_calls.add_call_dynamic(contract.getPoolId().into_call(true));
+            swap_fee_calls =
+                swap_fee_calls.add_call_dynamic(contract.getSwapFeePercentage().into_call(true));
+        }
+        let (weights, pool_ids, swap_fees) = tokio::try_join!(
+            weight_calls.aggregate3(),
+            pool_id_calls.aggregate3(),
+            swap_fee_calls.aggregate3(),
+        )?;
+
+        for (i, address) in chunk.iter().enumerate() {
+            if let (
+                std::result::Result::Ok(_),
+                std::result::Result::Ok(pool_id),
+                std::result::Result::Ok(swap_fee),
+            ) = (&weights[i], &pool_ids[i], &swap_fees[i])
+            {
+                probed.push((*address, *pool_id, *swap_fee));
+            }
+        }
+    }
+
+    let mut pool_tokens = Vec::new();
+    for chunk in probed.chunks(250) {
+        let mut multicall = provider
+            .multicall()
+            .dynamic::<BalancerVault::getPoolTokensCall>();
+        for (_, pool_id, _) in chunk {
+            multicall = multicall.add_call_dynamic(vault.getPoolTokens(*pool_id).into_call(true));
+        }
+        let result = multicall.aggregate3().await?;
+
+        for ((address, _, swap_fee), tokens) in chunk.iter().zip(result) {
+            match tokens {
+                std::result::Result::Ok(tokens) => {
+                    pool_tokens.push((*address, *swap_fee, tokens.tokens))
+                }
+                Err(_) => continue,
+            }
+        }
+    }
+
+    let unique_tokens: Vec<Address> = pool_tokens
+        .iter()
+        .flat_map(|(_, _, tokens)| tokens.iter().cloned())
+        .unique()
+        .collect();
+    let mut token_decimals = HashMap::new();
+    for chunk in unique_tokens.chunks(250) {
+        let decimals = fetch_decimals(&provider, chunk).await?;
+        for (token, decimals) in chunk.iter().zip(decimals) {
+            if let Some(decimals) = decimals {
+                token_decimals.insert(*token, decimals);
+            }
+        }
+    }
+
+    let mut pools_vec = Vec::new();
+    for (address, swap_fee, tokens) in pool_tokens {
+        let decimals: Option<Vec<u8>> = tokens
+            .iter()
+            .map(|token| token_decimals.get(token).cloned())
+            .collect();
+        let decimals = match decimals {
+            Some(decimals) => decimals,
+            None => {
+                info!(
+                    "Skipping Balancer pool {:?}: token decimals unavailable",
+                    address
+                );
+                continue;
+            }
+        };
+
+        // 1e18 fixed point -> pips
+        let fee = match u32::try_from(swap_fee / U256::from(10).pow(U256::from(12))) {
+            std::result::Result::Ok(fee) => fee,
+            Err(_) => {
+                info!(
+                    "Skipping Balancer pool {:?}: fee {} out of range",
+                    address, swap_fee
+                );
+                continue;
+            }
+        };
+        for (i, j) in (0..tokens.len()).tuple_combinations() {
+            pools_vec.push(Pool {
+                address,
+                version: DexVariant::BalancerWeighted,
+                token0: tokens[i],
+                token1: tokens[j],
+                decimals0: decimals[i],
+                decimals1: decimals[j],
+                fee,
+                router: *vault.address(),
+            });
+        }
+    }
+    info!("Loaded {} Balancer weighted pool pairs", pools_vec.len());
+
     Ok(pools_vec)
 }
+
+#[cfg(test)]
+mod pools_tests {
+    use super::*;
+    use crate::multi::Reserve;
+
+    fn record(fields: &[&str]) -> StringRecord {
+        StringRecord::from(fields.to_vec())
+    }
+
+    #[test]
+    fn cache_row_round_trips() {
+        let row = [
+            "0x680a025da7b1be2c204d7745e809919bce074026",
+            "2",
+            "0x6b3595068778dd592e39a122f4f5a5cf09c90fe2",
+            "0xdac17f958d2ee523a2206206994597c13d831ec7",
+            "18",
+            "6",
+            "3000",
+            "0xd9e1ce17f2641f24ae83637ab66a2cca9c378b9f",
+        ];
+        let pool = Pool::try_from(record(&row)).unwrap();
+        let cached = pool.cache_row();
+        assert_eq!(cached.0, row[0]);
+        assert_eq!(cached.1, 2);
+        assert_eq!(cached.6, 3000);
+        assert_eq!(cached.7, row[7]);
+    }
+
+    #[test]
+    fn bad_column_is_named() {
+        let row = [
+            "0x680a025da7b1be2c204d7745e809919bce074026",
+            "2",
+            "0x6b3595068778dd592e39a122f4f5a5cf09c90fe2",
+            "0xdac17f958d2ee523a2206206994597c13d831ec7",
+            "eighteen",
+            "6",
+            "3000",
+            "0xd9e1ce17f2641f24ae83637ab66a2cca9c378b9f",
+        ];
+        match Pool::try_from(record(&row)) {
+            Err(PoolCacheError::InvalidColumn { column, value, .. }) => {
+                assert_eq!(column, "decimals0");
+                assert_eq!(value, "eighteen");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let mut row = row;
+        row[4] = "18";
+        match Pool::try_from(record(&row[..7])) {
+            Err(PoolCacheError::MissingColumn { column, .. }) => assert_eq!(column, "router"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn liquidity_filter_drops_dust_pools() {
+        let address = |byte: u8| Address::repeat_byte(byte);
+        let weth = address(0xee);
+        let pool = |pool: u8, token0: Address, token1: Address| Pool {
+            address: address(pool),
+            version: DexVariant::UniswapV2,
+            token0,
+            token1,
+            decimals0: 18,
+            decimals1: 18,
+            fee: 3000,
+            router: Address::ZERO,
+        };
+        let reserve = |reserve0: u64, reserve1: u64| {
+            PoolState::UniswapV2(Reserve {
+                reserve0: U256::from(10).pow(U256::from(18)) * U256::from(reserve0),
+                reserve1: U256::from(10).pow(U256::from(18)) * U256::from(reserve1),
+            })
+        };
+
+        // 1 token = 0.5 WETH, priced by the deep pool
+        let pools = vec![
+            pool(1, address(0xaa), weth),
+            pool(2, address(0xaa), address(0xbb)),
+            pool(3, address(0xaa), address(0xcc)),
+        ];
+        let states = HashMap::from([
+            (address(1), reserve(200, 100)),
+            (address(2), reserve(40, 40)),
+            (address(3), reserve(4, 4000)),
+        ]);
+
+        let filter =
+            LiquidityFilter::new(weth, U256::from(10).pow(U256::from(18)) * U256::from(10));
+        let kept = filter.filter(&pools, &states);
+        let kept: Vec<Address> = kept.iter().map(|pool| pool.address).collect();
+        // pool 2 is worth 2 * 20 WETH, pool 3 only 2 * 2 WETH
+        assert_eq!(kept, vec![address(1), address(2)]);
+
+        // Without its WETH pair neither of pool 3's tokens has a price, so it's kept
+        assert_eq!(filter.filter(&pools[2..], &states).len(), 1);
+
+        // Pricing off the full set values pools the WETH pair isn't filtered with
+        let pricing = filter.pricing_pools(&pools, &pools[1..]);
+        assert_eq!(pricing.len(), 1);
+        let prices = filter.token_prices(&pricing, &states);
+        let kept = filter.filter_with_prices(&pools[1..], &states, &prices);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].address, address(2));
+    }
+
+    #[tokio::test]
+    async fn dune_rows_map_to_pools() {
+        let body = r#"{"result":{"rows":[
+            {"address":"0x397ff1542f962076d0bfe58ea045ffa2d347aca0","version":2,
+             "token0":"0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+             "token1":"0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+             "decimals0":6,"decimals1":18,"fee":300,
+             "factory":"0xc0aee478e3658e2610c5f7a4a2e1777ce9e4f2ac","block_number":10829331},
+            {"address":"0x0000000000000000000000000000000000000001","version":7,
+             "token0":"0x0000000000000000000000000000000000000002",
+             "token1":"0x0000000000000000000000000000000000000003",
+             "decimals0":18,"decimals1":18,"fee":300,"factory":null,"block_number":null}
+        ]}}"#;
+        let (url, _) = crate::dune::dune_tests::mock_server("200 OK", body.to_string()).await;
+
+        let rows: Vec<DunePoolRow> = fetch_query_rows(&url, "key", 1).await.unwrap();
+        let factories = vec![V2Factory::new(
+            "0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac",
+            "0xd9e1cE17f2641f24aE83637ab66a2cca9C378B9F",
+            3000,
+            10794229,
+        )];
+
+        let pool = rows[0].to_pool(&factories).unwrap();
+        assert!(matches!(pool.version, DexVariant::UniswapV2));
+        assert_eq!(pool.decimals0, 6);
+        assert_eq!(pool.router, factories[0].router);
+        // The row's V2 fee is replaced by the factory's, in pips
+        assert_eq!(pool.fee, 3000);
+        assert!(rows[1].to_pool(&factories).is_err());
+
+        let mut row = rows[0].clone();
+        row.factory = None;
+        assert!(row.to_pool(&factories).is_err());
+        row.factory = Some("0x5c69bee701ef814a2b6a3edd4b1652cb9cc5aa6f".to_string());
+        assert!(row.to_pool(&factories).is_err());
+    }
+}
@@ -0,0 +1,287 @@
+//! Short history of the reserve changes each block made, so the strategy can undo the
+//! blocks a reorg orphaned and re-apply the canonical ones in their place.
+use alloy::{
+    primitives::{Address, B256},
+    providers::Provider,
+};
+use anyhow::{anyhow, Result};
+use std::collections::{HashMap, VecDeque};
+
+use crate::multi::PoolState;
+use crate::streams::NewBlock;
+
+/// Pool states as they were before a block changed them; `None` for pools the block
+/// added to the map.
+#[derive(Default, Debug, Clone)]
+pub struct ReserveDiff {
+    previous: HashMap<Address, Option<PoolState>>,
+}
+
+impl ReserveDiff {
+    /// Remembers `address`'s state before its first change in this block. Call it before
+    /// every write to `reserves`.
+    pub fn record(&mut self, address: Address, reserves: &HashMap<Address, PoolState>) {
+        self.previous
+            .entry(address)
+            .or_insert_with(|| reserves.get(&address).cloned());
+    }
+
+    pub fn len(&self) -> usize {
+        self.previous.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.previous.is_empty()
+    }
+
+    fn revert(self, reserves: &mut HashMap<Address, PoolState>) {
+        for (address, previous) in self.previous {
+            match previous {
+                Some(state) => {
+                    reserves.insert(address, state);
+                }
+                None => {
+                    reserves.remove(&address);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BlockEntry {
+    number: u64,
+    hash: B256,
+    diff: ReserveDiff,
+}
+
+/// The last `depth` applied blocks with their diffs, oldest first.
+#[derive(Debug, Clone)]
+pub struct BlockHistory {
+    depth: usize,
+    blocks: VecDeque<BlockEntry>,
+}
+
+impl BlockHistory {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            blocks: VecDeque::new(),
+        }
+    }
+
+    pub fn tip(&self) -> Option<(u64, B256)> {
+        self.blocks.back().map(|entry| (entry.number, entry.hash))
+    }
+
+    /// Hash of the applied block at `number`, if it's still in the history.
+    pub fn hash_at(&self, number: u64) -> Option<B256> {
+        self.blocks
+            .iter()
+            .rev()
+            .find(|entry| entry.number == number)
+            .map(|entry| entry.hash)
+    }
+
+    /// Whether `block` builds on the last applied block. With no history every block does.
+    pub fn extends_tip(&self, block: &NewBlock) -> bool {
+        match self.blocks.back() {
+            Some(tip) => tip.hash == block.parent_hash,
+            None => true,
+        }
+    }
+
+    pub fn push(&mut self, number: u64, hash: B256, diff: ReserveDiff) {
+        self.blocks.push_back(BlockEntry { number, hash, diff });
+        while self.blocks.len() > self.depth {
+            self.blocks.pop_front();
+        }
+    }
+
+    /// Reverts every block above `ancestor`, newest first, and returns how many were undone.
+    pub fn rollback_to(
+        &mut self,
+        ancestor: u64,
+        reserves: &mut HashMap<Address, PoolState>,
+    ) -> u64 {
+        let mut undone = 0;
+        while let Some(tip) = self.blocks.back() {
+            if tip.number <= ancestor {
+                break;
+            }
+            let entry = self.blocks.pop_back().unwrap();
+            entry.diff.revert(reserves);
+            undone += 1;
+        }
+        undone
+    }
+
+    /// Reverts everything, for reorgs deeper than the history. The caller has to re-read
+    /// the state of every pool afterwards.
+    pub fn rollback_all(&mut self, reserves: &mut HashMap<Address, PoolState>) -> u64 {
+        let mut undone = 0;
+        while let Some(entry) = self.blocks.pop_back() {
+            entry.diff.revert(reserves);
+            undone += 1;
+        }
+        undone
+    }
+
+    /// Finds the newest recorded block that's still canonical as of `block`, checking
+    /// older blocks' hashes against the node. Returns `None` if the reorg goes deeper
+    /// than the history.
+    pub async fn find_common_ancestor<P: Provider>(
+        &self,
+        provider: &P,
+        block: &NewBlock,
+    ) -> Result<Option<u64>> {
+        for entry in self.blocks.iter().rev() {
+            if entry.number >= block.block_number {
+                continue;
+            }
+            let canonical = if entry.number + 1 == block.block_number {
+                block.parent_hash
+            } else {
+                provider
+                    .get_block_by_number(entry.number.into())
+                    .await?
+                    .ok_or_else(|| anyhow!("block {} not found", entry.number))?
+                    .header
+                    .hash
+            };
+            if canonical == entry.hash {
+                return Ok(Some(entry.number));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod reorg_tests {
+    use super::*;
+    use crate::multi::Reserve;
+    use alloy::primitives::U256;
+    use alloy::{
+        providers::ProviderBuilder,
+        rpc::types::{Block, Header},
+        transports::mock::Asserter,
+    };
+
+    fn reserve(value: u64) -> PoolState {
+        Reserve {
+            reserve0: U256::from(value),
+            reserve1: U256::from(value),
+        }
+        .into()
+    }
+
+    fn reserve0(reserves: &HashMap<Address, PoolState>, address: &Address) -> Option<u64> {
+        match reserves.get(address)? {
+            PoolState::UniswapV2(reserve) => Some(reserve.reserve0.to::<u64>()),
+            _ => None,
+        }
+    }
+
+    /// A node's response to `eth_getBlockByNumber`.
+    pub(crate) fn rpc_block(number: u64, hash: B256) -> Block {
+        let mut header: Header = Header::default();
+        header.hash = hash;
+        header.inner.number = number;
+        Block::empty(header)
+    }
+
+    /// Applies `writes` to `reserves` as one block and records it.
+    fn apply(
+        history: &mut BlockHistory,
+        reserves: &mut HashMap<Address, PoolState>,
+        number: u64,
+        writes: &[(Address, u64)],
+    ) {
+        let mut diff = ReserveDiff::default();
+        for (address, value) in writes {
+            diff.record(*address, reserves);
+            reserves.insert(*address, reserve(*value));
+        }
+        history.push(number, B256::repeat_byte(number as u8), diff);
+    }
+
+    #[test]
+    fn rollback_restores_orphaned_blocks() {
+        let (a, b) = (Address::repeat_byte(0xa), Address::repeat_byte(0xb));
+        let mut reserves = HashMap::from([(a, reserve(1))]);
+        let mut history = BlockHistory::new(8);
+
+        apply(&mut history, &mut reserves, 10, &[(a, 2)]);
+        apply(&mut history, &mut reserves, 11, &[(a, 3), (a, 4), (b, 9)]);
+        apply(&mut history, &mut reserves, 12, &[(b, 10)]);
+
+        assert_eq!(history.rollback_to(10, &mut reserves), 2);
+        assert_eq!(reserve0(&reserves, &a), Some(2));
+        assert_eq!(reserve0(&reserves, &b), None);
+        assert_eq!(history.tip(), Some((10, B256::repeat_byte(10))));
+
+        assert_eq!(history.rollback_all(&mut reserves), 1);
+        assert_eq!(reserve0(&reserves, &a), Some(1));
+        assert_eq!(history.tip(), None);
+    }
+
+    #[test]
+    fn history_keeps_the_last_blocks() {
+        let a = Address::repeat_byte(0xa);
+        let mut reserves = HashMap::new();
+        let mut history = BlockHistory::new(2);
+        for number in 1..=3 {
+            apply(&mut history, &mut reserves, number, &[(a, number)]);
+        }
+
+        let next = NewBlock {
+            block_number: 4,
+            block_hash: B256::repeat_byte(4),
+            parent_hash: B256::repeat_byte(3),
+            ..Default::default()
+        };
+        assert!(history.extends_tip(&next));
+        let sibling = NewBlock {
+            parent_hash: B256::repeat_byte(2),
+            ..next
+        };
+        assert!(!history.extends_tip(&sibling));
+
+        // Block 1 fell out of the history, so its change can't be undone
+        assert_eq!(history.rollback_to(0, &mut reserves), 2);
+        assert_eq!(reserve0(&reserves, &a), Some(1));
+    }
+
+    #[tokio::test]
+    async fn common_ancestor_is_the_newest_canonical_block() {
+        let a = Address::repeat_byte(0xa);
+        let mut reserves = HashMap::new();
+        let mut history = BlockHistory::new(8);
+        for number in 10..=12 {
+            apply(&mut history, &mut reserves, number, &[(a, number)]);
+        }
+        assert_eq!(history.hash_at(11), Some(B256::repeat_byte(11)));
+        assert_eq!(history.hash_at(9), None);
+
+        // Block 13 builds on a replacement for 12, and 11 was replaced too
+        let block = NewBlock {
+            block_number: 13,
+            block_hash: B256::repeat_byte(0xd3),
+            parent_hash: B256::repeat_byte(0xd2),
+            ..Default::default()
+        };
+        let asserter = Asserter::new();
+        asserter.push_success(&rpc_block(11, B256::repeat_byte(0xd1)));
+        asserter.push_success(&rpc_block(10, B256::repeat_byte(10)));
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter.clone());
+        let ancestor = history.find_common_ancestor(&provider, &block).await;
+        assert_eq!(ancestor.unwrap(), Some(10));
+
+        // Every recorded block was replaced
+        asserter.push_success(&rpc_block(11, B256::repeat_byte(0xd1)));
+        asserter.push_success(&rpc_block(10, B256::repeat_byte(0xd0)));
+        let ancestor = history.find_common_ancestor(&provider, &block).await;
+        assert_eq!(ancestor.unwrap(), None);
+    }
+}
@@ -0,0 +1,313 @@
+//! Keeps pool states current from the streamed pool logs instead of a `get_logs` call
+//! per block. Logs are buffered as they arrive and applied in (block, log index) order
+//! once their block's header does, which is when the block's state counts as final.
+//! A header that arrives before any of its block's logs can't be finalized from the
+//! stream; `has_logs_for` tells the caller to read that block's logs from the node.
+use alloy::{
+    primitives::{Address, B256},
+    rpc::types::Log,
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::multi::PoolState;
+use crate::reorg::{BlockHistory, ReserveDiff};
+use crate::streams::NewBlock;
+use crate::utils::{apply_uniswap_v3_log, decode_sync_log};
+
+/// A block whose logs have all been applied, and the pools they changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinalizedBlock {
+    pub block_number: u64,
+    pub block_hash: B256,
+    pub touched: Vec<Address>,
+}
+
+pub struct ReserveStateManager {
+    tracked: HashSet<Address>,
+    /// Keyed by (block number, block hash, log index), so logs from two forks at the
+    /// same height don't overwrite or retract each other.
+    pending: BTreeMap<(u64, B256, u64), Log>,
+    /// Blocks any pool log arrived for, tracked pool or not.
+    seen: BTreeSet<(u64, B256)>,
+    /// Blocks up to here had their state read from the node, so their logs are ignored.
+    discarded_through: u64,
+}
+
+impl ReserveStateManager {
+    pub fn new(tracked: impl IntoIterator<Item = Address>) -> Self {
+        Self {
+            tracked: tracked.into_iter().collect(),
+            pending: BTreeMap::new(),
+            seen: BTreeSet::new(),
+            discarded_through: 0,
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Buffers `log` if it's a mined log from a tracked pool and returns whether it was
+    /// kept. A removed log drops the buffered log it retracts.
+    pub fn on_log(&mut self, log: Log) -> bool {
+        let block_hash = log.block_hash.unwrap_or_default();
+        let key = match (log.block_number, log.log_index) {
+            (Some(block_number), Some(log_index)) => (block_number, block_hash, log_index),
+            _ => return false,
+        };
+        if key.0 <= self.discarded_through {
+            return false;
+        }
+        if !log.removed {
+            self.seen.insert((key.0, block_hash));
+        }
+        if !self.tracked.contains(&log.address()) {
+            return false;
+        }
+        if log.removed {
+            self.pending.remove(&key);
+            return false;
+        }
+        self.pending.insert(key, log);
+        true
+    }
+
+    /// Applies every buffered log up to `block`, recording prior states in `diff`. Logs
+    /// at `block`'s height from another block hash belong to an orphaned fork and are
+    /// dropped. Logs from earlier blocks that arrived late are applied now, but only if
+    /// their block is still in `history` under the same hash.
+    pub fn finalize(
+        &mut self,
+        block: &NewBlock,
+        history: &BlockHistory,
+        reserves: &mut HashMap<Address, PoolState>,
+        diff: &mut ReserveDiff,
+    ) -> FinalizedBlock {
+        let ready = self._take_through(block.block_number);
+
+        let mut touched = Vec::new();
+        for ((block_number, _, _), log) in ready {
+            let canonical = if block_number == block.block_number {
+                Some(block.block_hash)
+            } else {
+                history.hash_at(block_number)
+            };
+            let orphaned = match log.block_hash {
+                Some(hash) => canonical != Some(hash),
+                None => false,
+            };
+            if orphaned {
+                continue;
+            }
+            let address = log.address();
+            if _apply_log(address, &log, reserves, diff) && !touched.contains(&address) {
+                touched.push(address);
+            }
+        }
+
+        FinalizedBlock {
+            block_number: block.block_number,
+            block_hash: block.block_hash,
+            touched,
+        }
+    }
+
+    /// Whether any pool log of `block` arrived. If none did, its header beat its logs,
+    /// and finalizing it would apply them a block late.
+    pub fn has_logs_for(&self, block: &NewBlock) -> bool {
+        self.seen.contains(&(block.block_number, block.block_hash))
+    }
+
+    /// Drops the buffered logs up to `block_number`, for blocks whose state was re-read
+    /// from the node instead. Logs for those blocks that arrive later are dropped too.
+    pub fn discard_through(&mut self, block_number: u64) {
+        self._take_through(block_number);
+        self.discarded_through = self.discarded_through.max(block_number);
+    }
+
+    fn _take_through(&mut self, block_number: u64) -> BTreeMap<(u64, B256, u64), Log> {
+        self.seen = self.seen.split_off(&(block_number + 1, B256::ZERO));
+        let later = self.pending.split_off(&(block_number + 1, B256::ZERO, 0));
+        std::mem::replace(&mut self.pending, later)
+    }
+}
+
+fn _apply_log(
+    address: Address,
+    log: &Log,
+    reserves: &mut HashMap<Address, PoolState>,
+    diff: &mut ReserveDiff,
+) -> bool {
+    match reserves.get(&address) {
+        Some(PoolState::UniswapV2(_)) => match decode_sync_log(log) {
+            Some(reserve) => {
+                diff.record(address, reserves);
+                reserves.insert(address, reserve.into());
+                true
+            }
+            None => false,
+        },
+        Some(PoolState::UniswapV3(_)) => {
+            diff.record(address, reserves);
+            match reserves.get_mut(&address) {
+                Some(PoolState::UniswapV3(state)) => apply_uniswap_v3_log(state, log),
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod reserve_state_tests {
+    use super::*;
+    use crate::abi::events::IUniswapV2Pair;
+    use crate::multi::Reserve;
+    use alloy::{primitives::aliases::U112, sol_types::SolEvent};
+
+    fn sync_log(pool: Address, block: u8, log_index: u64, reserve0: u64) -> Log {
+        let sync = IUniswapV2Pair::Sync {
+            reserve0: U112::from(reserve0),
+            reserve1: U112::from(1),
+        };
+        Log {
+            inner: alloy::primitives::Log {
+                address: pool,
+                data: sync.encode_log_data(),
+            },
+            block_number: Some(block as u64),
+            block_hash: Some(B256::repeat_byte(block)),
+            log_index: Some(log_index),
+            ..Default::default()
+        }
+    }
+
+    fn block(number: u8) -> NewBlock {
+        NewBlock {
+            block_number: number as u64,
+            block_hash: B256::repeat_byte(number),
+            ..Default::default()
+        }
+    }
+
+    fn reserve0(reserves: &HashMap<Address, PoolState>, pool: &Address) -> u64 {
+        match &reserves[pool] {
+            PoolState::UniswapV2(reserve) => reserve.reserve0.to::<u64>(),
+            _ => panic!("not a V2 pool"),
+        }
+    }
+
+    #[test]
+    fn applies_tracked_logs_in_log_order() {
+        let (pool, untracked) = (Address::repeat_byte(0xa), Address::repeat_byte(0xb));
+        let mut reserves = HashMap::from([(pool, PoolState::UniswapV2(Reserve::default()))]);
+        let mut manager = ReserveStateManager::new([pool]);
+
+        // Delivered out of order, and one for the next block
+        assert!(manager.on_log(sync_log(pool, 7, 5, 50)));
+        assert!(manager.on_log(sync_log(pool, 7, 2, 20)));
+        assert!(manager.on_log(sync_log(pool, 8, 0, 80)));
+        assert!(!manager.on_log(sync_log(untracked, 7, 3, 30)));
+
+        let mut diff = ReserveDiff::default();
+        let history = BlockHistory::new(8);
+        let finalized = manager.finalize(&block(7), &history, &mut reserves, &mut diff);
+        assert_eq!(finalized.touched, vec![pool]);
+        assert_eq!(reserve0(&reserves, &pool), 50);
+        assert_eq!(diff.len(), 1);
+        assert_eq!(manager.pending(), 1);
+        assert!(!reserves.contains_key(&untracked));
+    }
+
+    #[test]
+    fn removed_and_orphaned_logs_are_skipped() {
+        let pool = Address::repeat_byte(0xa);
+        let mut reserves = HashMap::from([(pool, PoolState::UniswapV2(Reserve::default()))]);
+        let mut manager = ReserveStateManager::new([pool]);
+
+        manager.on_log(sync_log(pool, 7, 1, 10));
+        let mut removed = sync_log(pool, 7, 1, 10);
+        removed.removed = true;
+        assert!(!manager.on_log(removed));
+
+        // Same height, but from a block that lost the race
+        let mut orphaned = sync_log(pool, 7, 2, 20);
+        orphaned.block_hash = Some(B256::repeat_byte(0xff));
+        manager.on_log(orphaned);
+
+        let history = BlockHistory::new(8);
+        let finalized = manager.finalize(
+            &block(7),
+            &history,
+            &mut reserves,
+            &mut ReserveDiff::default(),
+        );
+        assert!(finalized.touched.is_empty());
+        assert_eq!(reserve0(&reserves, &pool), 0);
+    }
+
+    #[test]
+    fn late_logs_only_apply_to_canonical_blocks() {
+        let pool = Address::repeat_byte(0xa);
+        let mut reserves = HashMap::from([(pool, PoolState::UniswapV2(Reserve::default()))]);
+        let mut manager = ReserveStateManager::new([pool]);
+        let mut history = BlockHistory::new(8);
+        history.push(6, B256::repeat_byte(6), ReserveDiff::default());
+
+        // Both arrive after block 6 was finalized; one is from a fork that lost
+        manager.on_log(sync_log(pool, 6, 1, 60));
+        let mut orphaned = sync_log(pool, 6, 2, 61);
+        orphaned.block_hash = Some(B256::repeat_byte(0xff));
+        manager.on_log(orphaned);
+        // And one from a block that fell out of the history
+        manager.on_log(sync_log(pool, 5, 0, 50));
+
+        let mut diff = ReserveDiff::default();
+        let finalized = manager.finalize(&block(7), &history, &mut reserves, &mut diff);
+        assert_eq!(finalized.touched, vec![pool]);
+        assert_eq!(reserve0(&reserves, &pool), 60);
+    }
+
+    #[test]
+    fn forks_at_one_height_keep_their_own_logs() {
+        let pool = Address::repeat_byte(0xa);
+        let mut reserves = HashMap::from([(pool, PoolState::UniswapV2(Reserve::default()))]);
+        let mut manager = ReserveStateManager::new([pool]);
+
+        // The losing fork's log shares the canonical one's height and index, and is
+        // retracted once the other fork wins
+        manager.on_log(sync_log(pool, 7, 1, 70));
+        let mut orphaned = sync_log(pool, 7, 1, 71);
+        orphaned.block_hash = Some(B256::repeat_byte(0xff));
+        manager.on_log(orphaned.clone());
+        orphaned.removed = true;
+        manager.on_log(orphaned);
+        assert_eq!(manager.pending(), 1);
+
+        let history = BlockHistory::new(8);
+        manager.finalize(
+            &block(7),
+            &history,
+            &mut reserves,
+            &mut ReserveDiff::default(),
+        );
+        assert_eq!(reserve0(&reserves, &pool), 70);
+    }
+
+    #[test]
+    fn headers_without_logs_and_replayed_blocks() {
+        let (pool, untracked) = (Address::repeat_byte(0xa), Address::repeat_byte(0xb));
+        let mut manager = ReserveStateManager::new([pool]);
+
+        // Any pool's log shows the block's logs are arriving
+        assert!(!manager.on_log(sync_log(untracked, 7, 0, 1)));
+        assert!(manager.has_logs_for(&block(7)));
+        assert!(!manager.has_logs_for(&block(8)));
+
+        // Once block 8 is read from the node, its stragglers are ignored
+        manager.discard_through(8);
+        assert!(!manager.on_log(sync_log(pool, 8, 0, 80)));
+        assert!(manager.on_log(sync_log(pool, 9, 0, 90)));
+        assert_eq!(manager.pending(), 1);
+    }
+}
@@ -1,8 +1,20 @@
-use ethers::types::U256;
+use alloy::primitives::{Address, U256};
+
+use crate::balancer_math::{calc_out_given_in, mul_up};
+use crate::multi::{BalancerWeightedState, CurveStableState, UniswapV3State};
+use crate::v3_math::{
+    compute_swap_step, get_sqrt_ratio_at_tick, get_tick_at_sqrt_ratio,
+    next_initialized_tick_within_one_word, FEE_PIPS_DENOMINATOR, MAX_SQRT_RATIO, MAX_TICK,
+    MIN_SQRT_RATIO, MIN_TICK,
+};
 
 pub struct UniswapV2Simulator;
 
 impl UniswapV2Simulator {
+    /// Pool fees are in pips like every `Pool.fee`, so 3000 is 0.30% and PancakeSwap's
+    /// 2500 is 0.25% without rounding.
+    pub const FEE_DENOMINATOR: u64 = FEE_PIPS_DENOMINATOR as u64;
+
     pub fn reserves_to_price(
         reserve0: U256,
         reserve1: U256,
@@ -10,8 +22,8 @@ impl UniswapV2Simulator {
         decimals1: u8,
         token0_in: bool,
     ) -> f64 {
-        let r0 = reserve0.as_u128() as f64;
-        let r1 = reserve1.as_u128() as f64;
+        let r0 = reserve0.to::<u128>() as f64;
+        let r1 = reserve1.to::<u128>() as f64;
         let d0 = decimals0 as i32;
         let d1 = decimals1 as i32;
         let mult = (10.0 as f64).powi(d0 - d1);
@@ -34,10 +46,470 @@ impl UniswapV2Simulator {
         reserve_out: U256,
         fee: U256,
     ) -> Option<U256> {
-        let fee = fee / U256::from(100);
-        let amount_in_with_fee = amount_in * (U256::from(1000) - fee);
-        let numerator = amount_in_with_fee * reserve_out;
-        let denominator = (reserve_in * 1000) + amount_in_with_fee;
+        let scale = U256::from(Self::FEE_DENOMINATOR);
+        // Amounts can come from pending transactions, so an overflow is a swap that
+        // reverts rather than a panic
+        let amount_in_with_fee = amount_in.checked_mul(scale.checked_sub(fee)?)?;
+        let numerator = amount_in_with_fee.checked_mul(reserve_out)?;
+        let denominator = reserve_in
+            .checked_mul(scale)?
+            .checked_add(amount_in_with_fee)?;
         numerator.checked_div(denominator)
     }
+
+    /// Input that buys exactly `amount_out`, rounded up like `UniswapV2Library.getAmountIn`.
+    /// `None` if the pool doesn't hold that much.
+    pub fn get_amount_in(
+        amount_out: U256,
+        reserve_in: U256,
+        reserve_out: U256,
+        fee: U256,
+    ) -> Option<U256> {
+        if amount_out >= reserve_out {
+            return None;
+        }
+        let scale = U256::from(Self::FEE_DENOMINATOR);
+        let numerator = reserve_in.checked_mul(amount_out)?.checked_mul(scale)?;
+        let denominator = (reserve_out - amount_out).checked_mul(scale.checked_sub(fee)?)?;
+        Some(numerator.checked_div(denominator)? + U256::ONE)
+    }
+
+    /// Folds the next hop `(reserve_in, reserve_out, fee)` into an existing virtual
+    /// pool, so that a chain of constant-product swaps behaves like a single swap
+    /// against `(virtual_in, virtual_out)` using the first hop's fee.
+    pub fn fold_virtual_reserves(
+        virtual_in: U256,
+        virtual_out: U256,
+        reserve_in: U256,
+        reserve_out: U256,
+        fee: U256,
+    ) -> Option<(U256, U256)> {
+        let scale = U256::from(Self::FEE_DENOMINATOR);
+        let gamma = scale.checked_sub(fee)?;
+        let denominator = (reserve_in * scale).checked_add(gamma.checked_mul(virtual_out)?)?;
+        if denominator.is_zero() {
+            return None;
+        }
+        let next_in = (virtual_in * scale).checked_mul(reserve_in)? / denominator;
+        let next_out = gamma.checked_mul(virtual_out)?.checked_mul(reserve_out)? / denominator;
+        Some((next_in, next_out))
+    }
+
+    /// Profit-maximizing input for a single (possibly virtual) constant-product pool:
+    /// `a* = (sqrt(D * g * E_in * E_out) - D * E_in) / g`, where `D = FEE_DENOMINATOR` and
+    /// `g = D - fee`.
+    /// Returns zero if the pool can't return more than it takes.
+    pub fn optimal_amount_in(virtual_in: U256, virtual_out: U256, fee: U256) -> Option<U256> {
+        let scale = U256::from(Self::FEE_DENOMINATOR);
+        let gamma = scale.checked_sub(fee)?;
+        if gamma.is_zero() {
+            return None;
+        }
+        if gamma.checked_mul(virtual_out)? <= virtual_in * scale {
+            return Some(U256::ZERO);
+        }
+        let root = (gamma * scale)
+            .checked_mul(virtual_in)?
+            .checked_mul(virtual_out)?
+            .root(2);
+        let scaled_in = virtual_in * scale;
+        if root <= scaled_in {
+            return Some(U256::ZERO);
+        }
+        Some((root - scaled_in) / gamma)
+    }
+}
+
+pub struct UniswapV3Simulator;
+
+impl UniswapV3Simulator {
+    /// Exact-input swap against a V3 pool snapshot, stepping through initialized
+    /// ticks the same way `UniswapV3Pool.swap` does. Returns `None` if the swap
+    /// runs past the fetched tick bitmap, crosses an initialized tick whose info
+    /// wasn't fetched, or can't be filled in full.
+    pub fn get_amount_out(
+        state: &UniswapV3State,
+        amount_in: U256,
+        zero_for_one: bool,
+    ) -> Option<U256> {
+        if amount_in.is_zero() {
+            return Some(U256::ZERO);
+        }
+
+        let sqrt_price_limit_x96 = if zero_for_one {
+            MIN_SQRT_RATIO + U256::ONE
+        } else {
+            MAX_SQRT_RATIO - U256::ONE
+        };
+
+        let mut amount_remaining = amount_in;
+        let mut amount_out = U256::ZERO;
+        let mut sqrt_price_x96 = state.sqrt_price_x96;
+        let mut tick = state.tick;
+        let mut liquidity = state.liquidity;
+
+        while !amount_remaining.is_zero() && sqrt_price_x96 != sqrt_price_limit_x96 {
+            let sqrt_price_start_x96 = sqrt_price_x96;
+
+            let (tick_next, initialized) = next_initialized_tick_within_one_word(
+                &state.tick_bitmap,
+                tick,
+                state.tick_spacing,
+                zero_for_one,
+            )?;
+            let tick_next = tick_next.clamp(MIN_TICK, MAX_TICK);
+            let sqrt_price_next_x96 = get_sqrt_ratio_at_tick(tick_next)?;
+
+            let sqrt_price_target_x96 = if zero_for_one {
+                sqrt_price_next_x96.max(sqrt_price_limit_x96)
+            } else {
+                sqrt_price_next_x96.min(sqrt_price_limit_x96)
+            };
+
+            let step = compute_swap_step(
+                sqrt_price_x96,
+                sqrt_price_target_x96,
+                liquidity,
+                amount_remaining,
+                state.fee,
+            )?;
+            sqrt_price_x96 = step.sqrt_price_next_x96;
+            amount_remaining = amount_remaining.checked_sub(step.amount_in + step.fee_amount)?;
+            amount_out += step.amount_out;
+
+            if sqrt_price_x96 == sqrt_price_next_x96 {
+                if initialized {
+                    // The bitmap marks the tick as initialized, so missing info means it
+                    // wasn't fetched rather than that it holds no liquidity
+                    let liquidity_net = state.ticks.get(&tick_next)?.liquidity_net;
+                    let liquidity_net = if zero_for_one {
+                        -liquidity_net
+                    } else {
+                        liquidity_net
+                    };
+                    liquidity = if liquidity_net < 0 {
+                        liquidity.checked_sub(liquidity_net.unsigned_abs())?
+                    } else {
+                        liquidity.checked_add(liquidity_net as u128)?
+                    };
+                }
+                tick = if zero_for_one {
+                    tick_next - 1
+                } else {
+                    tick_next
+                };
+            } else if sqrt_price_x96 != sqrt_price_start_x96 {
+                tick = get_tick_at_sqrt_ratio(sqrt_price_x96)?;
+            }
+        }
+
+        if !amount_remaining.is_zero() {
+            return None;
+        }
+        Some(amount_out)
+    }
+}
+
+pub struct CurveStableSimulator;
+
+impl CurveStableSimulator {
+    pub const PRECISION: u64 = 1_000_000_000_000_000_000;
+    pub const FEE_DENOMINATOR: u64 = 10_000_000_000;
+    const MAX_ITERATIONS: usize = 255;
+
+    /// Balances normalized to 18 decimals (and any lending rate) via `rates`.
+    pub fn xp(state: &CurveStableState) -> Option<Vec<U256>> {
+        state
+            .balances
+            .iter()
+            .zip(&state.rates)
+            .map(|(balance, rate)| Some(rate.checked_mul(*balance)? / U256::from(Self::PRECISION)))
+            .collect()
+    }
+
+    /// StableSwap invariant `D` by Newton's method. `a_precision` is 1 for legacy
+    /// pools, where this reduces to the original `get_D`, and 100 for newer ones.
+    pub fn get_d(xp: &[U256], amp: U256, a_precision: U256) -> Option<U256> {
+        let n = U256::from(xp.len());
+        let s = xp.iter().fold(U256::ZERO, |acc, x| acc + x);
+        if s.is_zero() {
+            return Some(U256::ZERO);
+        }
+
+        let ann = amp * n;
+        let mut d = s;
+        for _ in 0..Self::MAX_ITERATIONS {
+            let mut d_p = d;
+            for x in xp {
+                d_p = d_p.checked_mul(d)?.checked_div(x.checked_mul(n)?)?;
+            }
+            let d_prev = d;
+            let numerator = (ann * s / a_precision + d_p * n).checked_mul(d)?;
+            let denominator =
+                (ann.checked_sub(a_precision)? * d) / a_precision + (n + U256::ONE) * d_p;
+            d = numerator.checked_div(denominator)?;
+
+            if _abs_diff(d, d_prev) <= U256::ONE {
+                return Some(d);
+            }
+        }
+        // Vyper pools revert if D doesn't converge
+        None
+    }
+
+    /// New balance of coin `j` once coin `i`'s normalized balance becomes `x`.
+    pub fn get_y(
+        i: usize,
+        j: usize,
+        x: U256,
+        xp: &[U256],
+        amp: U256,
+        a_precision: U256,
+    ) -> Option<U256> {
+        if i == j || i >= xp.len() || j >= xp.len() {
+            return None;
+        }
+        let n = U256::from(xp.len());
+        let d = Self::get_d(xp, amp, a_precision)?;
+        let ann = amp * n;
+
+        let mut c = d;
+        let mut s = U256::ZERO;
+        for (k, xp_k) in xp.iter().enumerate() {
+            let x_k = if k == i {
+                x
+            } else if k != j {
+                *xp_k
+            } else {
+                continue;
+            };
+            s += x_k;
+            c = c.checked_mul(d)?.checked_div(x_k.checked_mul(n)?)?;
+        }
+        c = c.checked_mul(d)?.checked_mul(a_precision)? / (ann * n);
+        let b = s + d * a_precision / ann;
+
+        let mut y = d;
+        for _ in 0..Self::MAX_ITERATIONS {
+            let y_prev = y;
+            y = (y * y + c).checked_div((y * U256::from(2) + b).checked_sub(d)?)?;
+            if _abs_diff(y, y_prev) <= U256::ONE {
+                return Some(y);
+            }
+        }
+        None
+    }
+
+    /// `get_dy(i, j, dx)` as implemented by the pool contract: output of coin `j`
+    /// for `dx` of coin `i`, net of the swap fee.
+    pub fn get_dy(state: &CurveStableState, i: usize, j: usize, dx: U256) -> Option<U256> {
+        let xp = Self::xp(state)?;
+        let precision = U256::from(Self::PRECISION);
+        let fee_denominator = U256::from(Self::FEE_DENOMINATOR);
+
+        let x = xp
+            .get(i)?
+            .checked_add(dx.checked_mul(state.rates[i])? / precision)?;
+        let y = Self::get_y(i, j, x, &xp, state.amp, state.a_precision)?;
+        let dy = xp[j].checked_sub(y)?.checked_sub(U256::ONE)?;
+
+        if state.a_precision <= U256::ONE {
+            // Legacy pools convert back to coin units before taking the fee
+            let dy = dy * precision / state.rates[j];
+            let fee = state.fee * dy / fee_denominator;
+            dy.checked_sub(fee)
+        } else {
+            let fee = state.fee * dy / fee_denominator;
+            Some(dy.checked_sub(fee)? * precision / state.rates[j])
+        }
+    }
+
+    /// Swaps `amount_in` of `token_in` for `token_out`, resolving coin indices from the pool.
+    pub fn get_amount_out(
+        state: &CurveStableState,
+        amount_in: U256,
+        token_in: Address,
+        token_out: Address,
+    ) -> Option<U256> {
+        let i = state.coins.iter().position(|coin| *coin == token_in)?;
+        let j = state.coins.iter().position(|coin| *coin == token_out)?;
+        Self::get_dy(state, i, j, amount_in)
+    }
+}
+
+pub struct BalancerWeightedSimulator;
+
+impl BalancerWeightedSimulator {
+    /// Given-in swap through a weighted pool, following `BaseMinimalSwapInfoPool.onSwap`:
+    /// the fee comes off the raw input, then amounts are upscaled to 18 decimals.
+    pub fn get_amount_out(
+        state: &BalancerWeightedState,
+        amount_in: U256,
+        token_in: Address,
+        token_out: Address,
+    ) -> Option<U256> {
+        let i = state.tokens.iter().position(|token| *token == token_in)?;
+        let j = state.tokens.iter().position(|token| *token == token_out)?;
+
+        let fee_amount = mul_up(amount_in, state.swap_fee)?;
+        let amount_in = amount_in.checked_sub(fee_amount)?;
+
+        let scaling_in = state.scaling_factors[i];
+        let scaling_out = state.scaling_factors[j];
+        let amount_out = calc_out_given_in(
+            state.balances[i].checked_mul(scaling_in)?,
+            state.weights[i],
+            state.balances[j].checked_mul(scaling_out)?,
+            state.weights[j],
+            amount_in.checked_mul(scaling_in)?,
+        )?;
+
+        Some(amount_out / scaling_out)
+    }
+}
+
+fn _abs_diff(a: U256, b: U256) -> U256 {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+#[cfg(test)]
+mod simulator_tests {
+    use super::*;
+
+    fn simulate(amount_in: U256, pools: &[(U256, U256)], fee: U256) -> U256 {
+        pools
+            .iter()
+            .fold(amount_in, |amount, (reserve_in, reserve_out)| {
+                UniswapV2Simulator::get_amount_out(amount, *reserve_in, *reserve_out, fee).unwrap()
+            })
+    }
+
+    #[test]
+    fn closed_form_optimum_is_local_maximum() {
+        let unit = U256::from(10).pow(U256::from(18));
+        let fee = U256::from(3000);
+        let pools = vec![
+            (U256::from(1_000) * unit, U256::from(2_000_000) * unit),
+            (U256::from(2_000_000) * unit, U256::from(1_100) * unit),
+        ];
+
+        let (mut virtual_in, mut virtual_out) = pools[0];
+        for (reserve_in, reserve_out) in &pools[1..] {
+            (virtual_in, virtual_out) = UniswapV2Simulator::fold_virtual_reserves(
+                virtual_in,
+                virtual_out,
+                *reserve_in,
+                *reserve_out,
+                fee,
+            )
+            .unwrap();
+        }
+        let optimal = UniswapV2Simulator::optimal_amount_in(virtual_in, virtual_out, fee).unwrap();
+        assert!(!optimal.is_zero());
+
+        let profit = |amount_in: U256| {
+            let out = simulate(amount_in, &pools, fee);
+            out.to::<u128>() as i128 - amount_in.to::<u128>() as i128
+        };
+        let delta = unit / U256::from(1000);
+        assert!(profit(optimal) > 0);
+        assert!(profit(optimal) >= profit(optimal - delta));
+        assert!(profit(optimal) >= profit(optimal + delta));
+    }
+
+    #[test]
+    fn fees_below_a_tenth_of_a_percent_are_kept() {
+        let unit = U256::from(10).pow(U256::from(18));
+        let (amount_in, reserve) = (U256::from(1000) * unit, U256::from(1_000_000) * unit);
+
+        // PancakeSwap's 0.25% fee, which used to round down to 0.2%
+        let pancake =
+            UniswapV2Simulator::get_amount_out(amount_in, reserve, reserve, U256::from(2500));
+        assert_eq!(
+            pancake.unwrap(),
+            U256::from_str_radix("996505985279683515693", 10).unwrap()
+        );
+        let rounded =
+            UniswapV2Simulator::get_amount_out(amount_in, reserve, reserve, U256::from(2000));
+        assert!(pancake.unwrap() < rounded.unwrap());
+    }
+
+    #[test]
+    fn amount_in_is_the_least_that_buys_the_output() {
+        let unit = U256::from(10).pow(U256::from(18));
+        let (reserve_in, reserve_out) = (U256::from(1_000) * unit, U256::from(2_000_000) * unit);
+        let fee = U256::from(3000);
+        let amount_out = U256::from(5_000) * unit;
+
+        let amount_in =
+            UniswapV2Simulator::get_amount_in(amount_out, reserve_in, reserve_out, fee).unwrap();
+        let out = |amount_in| {
+            UniswapV2Simulator::get_amount_out(amount_in, reserve_in, reserve_out, fee).unwrap()
+        };
+        assert!(out(amount_in) >= amount_out);
+        assert!(out(amount_in - U256::ONE) < amount_out);
+
+        assert!(
+            UniswapV2Simulator::get_amount_in(reserve_out, reserve_in, reserve_out, fee).is_none()
+        );
+    }
+
+    // Same reference as balancer_math's vectors: onSwap's fee and scaling around
+    // WeightedMath._calcOutGivenIn, run outside this crate
+    #[test]
+    fn balancer_get_amount_out_matches_reference() {
+        let unit = |decimals: usize| U256::from(10).pow(U256::from(decimals));
+        let (usdc, weth) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        // 3M USDC / 800 WETH at 60/40 with a 0.3% fee
+        let state = BalancerWeightedState {
+            pool_id: [0; 32],
+            tokens: vec![usdc, weth],
+            balances: vec![U256::from(3_000_000) * unit(6), U256::from(800) * unit(18)],
+            weights: vec![U256::from(6) * unit(17), U256::from(4) * unit(17)],
+            scaling_factors: vec![unit(12), U256::ONE],
+            swap_fee: U256::from(3) * unit(15),
+        };
+
+        let out = BalancerWeightedSimulator::get_amount_out(
+            &state,
+            U256::from(1_000) * unit(6),
+            usdc,
+            weth,
+        );
+        assert_eq!(
+            out.unwrap(),
+            U256::from_str_radix("398634396034558400", 10).unwrap()
+        );
+        let out = BalancerWeightedSimulator::get_amount_out(&state, unit(18), weth, usdc);
+        assert_eq!(out.unwrap(), U256::from(2_489_914_299u64));
+    }
+
+    #[test]
+    fn curve_get_dy_matches_vyper() {
+        let unit = |decimals: u32| U256::from(10).pow(U256::from(decimals));
+        let state = CurveStableState {
+            coins: vec![
+                Address::with_last_byte(1),
+                Address::with_last_byte(2),
+                Address::with_last_byte(3),
+            ],
+            balances: vec![
+                U256::from(100_000_000) * unit(18),
+                U256::from(100_000_000) * unit(6),
+                U256::from(100_000_000) * unit(6),
+            ],
+            rates: vec![unit(18), unit(30), unit(30)],
+            amp: U256::from(2000),
+            a_precision: U256::ONE,
+            fee: U256::from(1_000_000),
+        };
+
+        // Reference value from the 3pool Vyper math on the same balances
+        let dy = CurveStableSimulator::get_dy(&state, 0, 1, U256::from(1000) * unit(18)).unwrap();
+        assert_eq!(dy, U256::from(999_899_996u64));
+    }
 }
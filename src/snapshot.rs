@@ -0,0 +1,346 @@
+//! Binary snapshot of the loaded pools and the paths generated from them, so a restart
+//! with the same base token, hop range, factories and pool set skips the liquidity
+//! valuation and path search.
+//!
+//! Layout (little endian): magic, format version, params, pool count, fixed-width pool
+//! records, path count, then each path as a hop count and `(pool index, zero_for_one)` hops.
+use alloy::primitives::{keccak256, Address};
+use anyhow::{anyhow, bail, Result};
+use log::info;
+use std::{collections::HashMap, fs, path::Path, time::Duration};
+
+use crate::paths::{ArbPath, Hop};
+use crate::pools::{DexVariant, Pool};
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"SNPS";
+const SNAPSHOT_VERSION: u32 = 3;
+
+/// Everything that determines the snapshot's contents; a snapshot is only reused when
+/// these match exactly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotParams {
+    pub base_token: Address,
+    pub min_hops: u8,
+    pub max_hops: u8,
+    /// `(factory, router, fee)` of every V2 factory pools were loaded from.
+    pub factories: Vec<(Address, Address, u32)>,
+    /// Liquidity threshold pools had to meet, in WETH wei.
+    pub min_weth: u128,
+    /// `pool_set_hash` of the loaded pools, so new pairs invalidate the snapshot.
+    pub pool_set: [u8; 32],
+}
+
+/// Order-independent hash of `pools`, covering every field a snapshot stores.
+pub fn pool_set_hash(pools: &[Pool]) -> [u8; 32] {
+    let mut records: Vec<Vec<u8>> = pools
+        .iter()
+        .map(|pool| {
+            let mut record = Vec::new();
+            _encode_pool(&mut record, pool);
+            record
+        })
+        .collect();
+    records.sort();
+    keccak256(records.concat()).0
+}
+
+#[derive(Debug, Clone)]
+pub struct PathSnapshot {
+    pub params: SnapshotParams,
+    pub pools: Vec<Pool>,
+    pub paths: Vec<ArbPath>,
+}
+
+impl PathSnapshot {
+    pub fn new(params: SnapshotParams, pools: Vec<Pool>, paths: Vec<ArbPath>) -> Self {
+        Self {
+            params,
+            pools,
+            paths,
+        }
+    }
+
+    /// Loads the snapshot at `path` if it exists, was built from `params` and is younger
+    /// than `max_age`. Pools are only valued when the snapshot is built, so an old one
+    /// would keep pools that have since grown deep enough out of every path.
+    pub fn load_matching(
+        path: &Path,
+        params: &SnapshotParams,
+        max_age: Duration,
+    ) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        // A modification time in the future counts as fresh
+        let age = fs::metadata(path)?
+            .modified()?
+            .elapsed()
+            .unwrap_or_default();
+        if age > max_age {
+            info!("Path snapshot is {}s old, rebuilding", age.as_secs());
+            return Ok(None);
+        }
+        let snapshot = Self::decode(&fs::read(path)?)?;
+        if snapshot.params != *params {
+            info!("Path snapshot was built with different parameters, rebuilding");
+            return Ok(None);
+        }
+        Ok(Some(snapshot))
+    }
+
+    /// Writes to a temporary file and renames it into place.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, self.encode()?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(SNAPSHOT_MAGIC);
+        buf.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+
+        buf.extend_from_slice(self.params.base_token.as_slice());
+        buf.push(self.params.min_hops);
+        buf.push(self.params.max_hops);
+        buf.extend_from_slice(&(self.params.factories.len() as u32).to_le_bytes());
+        for (factory, router, fee) in &self.params.factories {
+            buf.extend_from_slice(factory.as_slice());
+            buf.extend_from_slice(router.as_slice());
+            buf.extend_from_slice(&fee.to_le_bytes());
+        }
+        buf.extend_from_slice(&self.params.min_weth.to_le_bytes());
+        buf.extend_from_slice(&self.params.pool_set);
+
+        // Curve and Balancer pools share an address across pairs, so key on the tokens too
+        let mut pool_index = HashMap::new();
+        buf.extend_from_slice(&(self.pools.len() as u32).to_le_bytes());
+        for (idx, pool) in self.pools.iter().enumerate() {
+            pool_index.insert((pool.address, pool.token0, pool.token1), idx as u32);
+            _encode_pool(&mut buf, pool);
+        }
+
+        buf.extend_from_slice(&(self.paths.len() as u32).to_le_bytes());
+        for path in &self.paths {
+            buf.push(path.hops.len() as u8);
+            for hop in &path.hops {
+                let key = (hop.pool.address, hop.pool.token0, hop.pool.token1);
+                let idx = pool_index
+                    .get(&key)
+                    .ok_or_else(|| anyhow!("path uses pool {:?} not in snapshot", key.0))?;
+                buf.extend_from_slice(&idx.to_le_bytes());
+                buf.push(hop.zero_for_one as u8);
+            }
+        }
+
+        Ok(buf)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = SnapshotReader { bytes, pos: 0 };
+        if reader.take(4)? != SNAPSHOT_MAGIC {
+            bail!("not a path snapshot");
+        }
+        let version = reader.u32()?;
+        if version != SNAPSHOT_VERSION {
+            bail!(
+                "path snapshot version {} does not match {}",
+                version,
+                SNAPSHOT_VERSION
+            );
+        }
+
+        let base_token = reader.address()?;
+        let min_hops = reader.u8()?;
+        let max_hops = reader.u8()?;
+        let factory_count = reader.u32()? as usize;
+        let factories = (0..factory_count)
+            .map(|_| Ok((reader.address()?, reader.address()?, reader.u32()?)))
+            .collect::<Result<Vec<_>>>()?;
+        let min_weth = reader.u128()?;
+        let mut pool_set = [0u8; 32];
+        pool_set.copy_from_slice(reader.take(32)?);
+
+        let pool_count = reader.u32()? as usize;
+        let pools = (0..pool_count)
+            .map(|_| reader.pool())
+            .collect::<Result<Vec<_>>>()?;
+
+        let path_count = reader.u32()? as usize;
+        let mut paths = Vec::with_capacity(path_count);
+        for _ in 0..path_count {
+            let nhop = reader.u8()?;
+            let mut hops = Vec::with_capacity(nhop as usize);
+            for _ in 0..nhop {
+                let idx = reader.u32()? as usize;
+                let pool = pools
+                    .get(idx)
+                    .ok_or_else(|| anyhow!("hop references missing pool {}", idx))?;
+                hops.push(Hop::new(pool.clone(), reader.u8()? != 0));
+            }
+            paths.push(ArbPath::new(hops));
+        }
+
+        Ok(Self {
+            params: SnapshotParams {
+                base_token,
+                min_hops,
+                max_hops,
+                factories,
+                min_weth,
+                pool_set,
+            },
+            pools,
+            paths,
+        })
+    }
+}
+
+fn _encode_pool(buf: &mut Vec<u8>, pool: &Pool) {
+    buf.extend_from_slice(pool.address.as_slice());
+    buf.push(match pool.version {
+        DexVariant::UniswapV2 => 2,
+        DexVariant::UniswapV3 => 3,
+        DexVariant::CurveStable => 4,
+        DexVariant::BalancerWeighted => 5,
+    });
+    buf.extend_from_slice(pool.token0.as_slice());
+    buf.extend_from_slice(pool.token1.as_slice());
+    buf.push(pool.decimals0);
+    buf.push(pool.decimals1);
+    buf.extend_from_slice(&pool.fee.to_le_bytes());
+    buf.extend_from_slice(pool.router.as_slice());
+}
+
+struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> SnapshotReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos + len;
+        if end > self.bytes.len() {
+            bail!("path snapshot truncated at byte {}", self.pos);
+        }
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let mut word = [0u8; 4];
+        word.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(word))
+    }
+
+    fn u128(&mut self) -> Result<u128> {
+        let mut word = [0u8; 16];
+        word.copy_from_slice(self.take(16)?);
+        Ok(u128::from_le_bytes(word))
+    }
+
+    fn address(&mut self) -> Result<Address> {
+        Ok(Address::from_slice(self.take(20)?))
+    }
+
+    fn pool(&mut self) -> Result<Pool> {
+        let address = self.address()?;
+        let version = match self.u8()? {
+            2 => DexVariant::UniswapV2,
+            3 => DexVariant::UniswapV3,
+            4 => DexVariant::CurveStable,
+            5 => DexVariant::BalancerWeighted,
+            other => bail!("unknown pool version {}", other),
+        };
+        Ok(Pool {
+            address,
+            version,
+            token0: self.address()?,
+            token1: self.address()?,
+            decimals0: self.u8()?,
+            decimals1: self.u8()?,
+            fee: self.u32()?,
+            router: self.address()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod snapshot_tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_round_trips() {
+        let token = |byte: u8| Address::repeat_byte(byte);
+        let pool = |address: u8, token0: u8, token1: u8| Pool {
+            address: token(address),
+            version: DexVariant::UniswapV2,
+            token0: token(token0),
+            token1: token(token1),
+            decimals0: 18,
+            decimals1: 6,
+            fee: 3000,
+            router: token(0xee),
+        };
+        let pools = vec![pool(0xa0, 1, 2), pool(0xa1, 2, 3), pool(0xa2, 1, 3)];
+        let paths = vec![ArbPath::new(vec![
+            Hop::new(pools[0].clone(), true),
+            Hop::new(pools[1].clone(), true),
+            Hop::new(pools[2].clone(), false),
+        ])];
+        let params = SnapshotParams {
+            base_token: token(1),
+            min_hops: 3,
+            max_hops: 3,
+            factories: vec![(token(0xf0), token(0xee), 3000)],
+            min_weth: 10u128.pow(19),
+            pool_set: pool_set_hash(&pools),
+        };
+
+        let snapshot = PathSnapshot::new(params.clone(), pools, paths);
+        let decoded = PathSnapshot::decode(&snapshot.encode().unwrap()).unwrap();
+
+        assert_eq!(decoded.params, params);
+        assert_eq!(decoded.pools.len(), 3);
+        assert_eq!(decoded.paths.len(), 1);
+        let hops = &decoded.paths[0].hops;
+        assert_eq!(hops[2].pool.address, token(0xa2));
+        assert!(!hops[2].zero_for_one);
+        assert_eq!(hops[0].token_in(), token(1));
+        assert_eq!(hops[2].token_out(), token(1));
+        assert!(PathSnapshot::decode(&snapshot.encode().unwrap()[..40]).is_err());
+    }
+
+    #[test]
+    fn pool_set_hash_tracks_new_pools() {
+        let token = |byte: u8| Address::repeat_byte(byte);
+        let pool = |address: u8| Pool {
+            address: token(address),
+            version: DexVariant::UniswapV2,
+            token0: token(1),
+            token1: token(2),
+            decimals0: 18,
+            decimals1: 6,
+            fee: 3000,
+            router: token(0xee),
+        };
+
+        let pools = vec![pool(0xa0), pool(0xa1)];
+        let reordered = vec![pool(0xa1), pool(0xa0)];
+        assert_eq!(pool_set_hash(&pools), pool_set_hash(&reordered));
+
+        let mut grown = pools.clone();
+        grown.push(pool(0xa2));
+        assert_ne!(pool_set_hash(&pools), pool_set_hash(&grown));
+
+        let mut rerouted = pools.clone();
+        rerouted[0].router = token(0xef);
+        assert_ne!(pool_set_hash(&pools), pool_set_hash(&rerouted));
+    }
+}
@@ -0,0 +1,190 @@
+//! Conversions from `amms` state-space values to the native `Pool` and `PoolState`
+//! types, so paths and simulators can run on top of a `StateSpaceManager`'s live state.
+//!
+//! Only Uniswap V2 and V3 pools convert: `amms` doesn't expose the pool id and scaling
+//! factors a Balancer simulation needs, and ERC4626 vaults aren't traded here.
+use alloy::primitives::{Address, U256};
+use amms::amms::{
+    amm::{AutomatedMarketMaker, AMM},
+    uniswap_v2::UniswapV2Pool,
+    uniswap_v3::{Info, UniswapV3Pool},
+};
+use std::collections::HashMap;
+
+use crate::multi::{PoolState, Reserve, TickInfo, UniswapV3State};
+use crate::pools::{DexVariant, Pool};
+
+/// The native pool for `amm`. `amms` pools don't record their factory, so V2 pools take
+/// `v2_router`, which the caller picks for the one V2 factory it synced. V3 pools get no
+/// router: the executor only swaps through V2 pairs, so they're quoted but never traded.
+pub fn amm_to_pool(amm: &AMM, v2_router: Address) -> Option<Pool> {
+    match amm {
+        AMM::UniswapV2Pool(pool) => Some(Pool {
+            address: pool.address,
+            version: DexVariant::UniswapV2,
+            token0: pool.token_a.address(),
+            token1: pool.token_b.address(),
+            decimals0: pool.token_a.decimals(),
+            decimals1: pool.token_b.decimals(),
+            // amms keeps V2 fees in hundred-thousandths
+            fee: pool.fee as u32 * 10,
+            router: v2_router,
+        }),
+        AMM::UniswapV3Pool(pool) => Some(Pool {
+            address: pool.address,
+            version: DexVariant::UniswapV3,
+            token0: pool.token_a.address(),
+            token1: pool.token_b.address(),
+            decimals0: pool.token_a.decimals(),
+            decimals1: pool.token_b.decimals(),
+            fee: pool.fee,
+            router: Address::ZERO,
+        }),
+        _ => None,
+    }
+}
+
+/// Current state of `amm` in the form the simulators take.
+pub fn amm_to_state(amm: &AMM) -> Option<PoolState> {
+    match amm {
+        AMM::UniswapV2Pool(pool) => Some(v2_reserve(pool).into()),
+        AMM::UniswapV3Pool(pool) => Some(v3_state(pool).into()),
+        _ => None,
+    }
+}
+
+fn v2_reserve(pool: &UniswapV2Pool) -> Reserve {
+    Reserve {
+        reserve0: U256::from(pool.reserve_0),
+        reserve1: U256::from(pool.reserve_1),
+    }
+}
+
+/// `amms` syncs the whole tick bitmap rather than the words around the current tick,
+/// so the resulting state covers every initialized tick.
+fn v3_state(pool: &UniswapV3Pool) -> UniswapV3State {
+    UniswapV3State {
+        sqrt_price_x96: pool.sqrt_price,
+        liquidity: pool.liquidity,
+        tick: pool.tick,
+        tick_spacing: pool.tick_spacing,
+        fee: pool.fee,
+        tick_bitmap: pool
+            .tick_bitmap
+            .iter()
+            .map(|(word, bits)| (*word, *bits))
+            .collect(),
+        ticks: pool
+            .ticks
+            .iter()
+            .filter(|(_, info)| info.initialized)
+            .map(|(tick, info)| (*tick, tick_info(info)))
+            .collect(),
+    }
+}
+
+fn tick_info(info: &Info) -> TickInfo {
+    TickInfo {
+        liquidity_gross: info.liquidity_gross,
+        liquidity_net: info.liquidity_net,
+    }
+}
+
+/// Every convertible pool in the state space, with V2 pools routed through `v2_router`.
+pub fn state_space_pools<'a>(
+    amms: impl IntoIterator<Item = &'a AMM>,
+    v2_router: Address,
+) -> Vec<Pool> {
+    amms.into_iter()
+        .filter_map(|amm| amm_to_pool(amm, v2_router))
+        .collect()
+}
+
+/// The strategy's reserve map for every convertible pool in the state space.
+pub fn state_space_reserves<'a>(
+    amms: impl IntoIterator<Item = &'a AMM>,
+) -> HashMap<Address, PoolState> {
+    amms.into_iter()
+        .filter_map(|amm| Some((amm.address(), amm_to_state(amm)?)))
+        .collect()
+}
+
+/// Copies the state of the `updated` pools, as reported by `StateSpaceManager::subscribe`,
+/// into `reserves` and returns the ones that were tracked there.
+pub fn apply_state_space_updates(
+    state: &HashMap<Address, AMM>,
+    updated: &[Address],
+    reserves: &mut HashMap<Address, PoolState>,
+) -> Vec<Address> {
+    let mut touched = Vec::new();
+    for &pool in updated {
+        if !reserves.contains_key(&pool) {
+            continue;
+        }
+        if let Some(pool_state) = state.get(&pool).and_then(amm_to_state) {
+            reserves.insert(pool, pool_state);
+            touched.push(pool);
+        }
+    }
+    touched
+}
+
+#[cfg(test)]
+mod state_space_tests {
+    use super::*;
+    use amms::amms::Token;
+
+    fn v2_pool(address: u8, reserve_0: u128, reserve_1: u128) -> AMM {
+        AMM::UniswapV2Pool(UniswapV2Pool {
+            address: Address::repeat_byte(address),
+            token_a: Token::new_with_decimals(Address::repeat_byte(1), 18),
+            token_b: Token::new_with_decimals(Address::repeat_byte(2), 6),
+            reserve_0,
+            reserve_1,
+            fee: 300,
+        })
+    }
+
+    #[test]
+    fn v2_pools_convert() {
+        let amm = v2_pool(0xa0, 5, 7);
+        let router = Address::repeat_byte(0xee);
+
+        let pool = amm_to_pool(&amm, router).unwrap();
+        assert_eq!(pool.address, Address::repeat_byte(0xa0));
+        assert_eq!(pool.token0, Address::repeat_byte(1));
+        assert_eq!((pool.decimals0, pool.decimals1, pool.fee), (18, 6, 3000));
+        assert_eq!(pool.router, router);
+
+        match amm_to_state(&amm) {
+            Some(PoolState::UniswapV2(reserve)) => {
+                assert_eq!(reserve.reserve0, U256::from(5));
+                assert_eq!(reserve.reserve1, U256::from(7));
+            }
+            other => panic!("unexpected state {:?}", other),
+        }
+    }
+
+    #[test]
+    fn updates_only_touch_tracked_pools() {
+        let state: HashMap<Address, AMM> = [v2_pool(0xa0, 5, 7), v2_pool(0xa1, 1, 1)]
+            .into_iter()
+            .map(|amm| (amm.address(), amm))
+            .collect();
+        let mut reserves = HashMap::new();
+        reserves.insert(
+            Address::repeat_byte(0xa0),
+            PoolState::UniswapV2(Reserve::default()),
+        );
+
+        let updated = [Address::repeat_byte(0xa0), Address::repeat_byte(0xa1)];
+        let touched = apply_state_space_updates(&state, &updated, &mut reserves);
+
+        assert_eq!(touched, vec![Address::repeat_byte(0xa0)]);
+        assert_eq!(reserves.len(), 1);
+        match &reserves[&Address::repeat_byte(0xa0)] {
+            PoolState::UniswapV2(reserve) => assert_eq!(reserve.reserve1, U256::from(7)),
+            other => panic!("unexpected state {:?}", other),
+        }
+    }
+}
@@ -1,115 +1,343 @@
-use ethers::{
-    providers::{Provider, Ws},
-    types::{Address, H160, U256},
+use alloy::{
+    network::Network,
+    primitives::{Address, I256, U256},
+    providers::Provider,
 };
-use log::info;
-use std::{collections::HashMap, str::FromStr, sync::Arc};
-use tokio::sync::broadcast::Sender;
-
-use crate::constants::{get_blacklist_tokens, Env, WEI};
-use crate::multi::batch_get_uniswap_v2_reserves;
-use crate::paths::generate_triangular_paths;
-use crate::pools::{load_all_pools_from_v2, Pool};
+use amms::state_space::StateSpaceManager;
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use log::{debug, info};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::broadcast::{error::RecvError, Sender};
+
+use crate::backrun::{build_backrun_bundle, BackrunEngine};
+use crate::bundler::Bundler;
+use crate::constants::{
+    get_v2_factories, Env, BACKRUN_PRIORITY_FEE_GWEI, BALANCER_VAULT_ADDRESS,
+    BALANCER_VAULT_DEPLOY_BLOCK, CURVE_REGISTRY_ADDRESS, GWEI, LIQUIDITY_FILTER_REFRESH_BLOCKS,
+    MIN_WETH_THRESHOLD, PATH_SNAPSHOT_MAX_AGE_SECS, PATH_SNAPSHOT_PATH, REORG_HISTORY_DEPTH,
+    SWAP_ROUTER_02_ADDRESS, UNISWAP_V2_ROUTER_ADDRESS, V3_TICK_WORD_RADIUS, WEI, WETH_ADDRESS,
+};
+use crate::multi::{batch_get_pool_states, PoolState};
+use crate::optimizer::SearchConfig;
+use crate::paths::{generate_triangular_paths, ArbPath};
+use crate::pools::{
+    load_all_pools_from_v2, load_balancer_weighted_pools, load_curve_pools, DexVariant,
+    LiquidityFilter, Pool,
+};
+use crate::reorg::{BlockHistory, ReserveDiff};
+use crate::reserve_state::ReserveStateManager;
 use crate::simulator::UniswapV2Simulator;
-use crate::streams::Event;
-use crate::utils::get_touched_pool_reserves;
+use crate::snapshot::{pool_set_hash, PathSnapshot, SnapshotParams};
+use crate::state_space::{apply_state_space_updates, state_space_pools, state_space_reserves};
+use crate::streams::{Event, NewBlock, Reorg};
+use crate::tax_detector::detect_token_taxes;
+use crate::tokens::TokenRegistry;
+use crate::utils::{get_touched_pool_reserves, update_touched_v3_states};
 
-pub async fn event_handler(provider: Arc<Provider<Ws>>, event_sender: Sender<Event>) {
+pub async fn event_handler<P: Provider + 'static>(provider: Arc<P>, event_sender: Sender<Event>) {
     /*
     Current addresses are all from the Ethereum network.
     Please change them according to your chain of interest.
     */
-    let env = Env::new();
+    let env = match Env::new() {
+        Ok(env) => env,
+        Err(e) => {
+            info!("Error loading environment: {:?}", e);
+            return;
+        }
+    };
+
+    let factories = get_v2_factories();
 
-    let factory_addresses = vec!["0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac"];
-    let router_addresses = vec!["0xd9e1cE17f2641f24aE83637ab66a2cca9C378B9F"];
-    let factory_blocks = vec![10794229u64];
+    // Performing USDC triangular arbitrage
+    let usdc_address = Address::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap();
+
+    let weth_address = WETH_ADDRESS;
+    let liquidity_filter = LiquidityFilter::new(weth_address, U256::from(MIN_WETH_THRESHOLD));
 
-    let pools_vec = load_all_pools_from_v2(env.wss_url.clone(), factory_addresses, factory_blocks)
+    // Always refresh the pool set, which is incremental once the cache exists, so pairs
+    // created since the snapshot was taken still invalidate it
+    let mut loaded_pools = load_all_pools_from_v2(env.wss_url.clone(), &factories)
         .await
         .unwrap();
-    info!("Initial pool count: {}", pools_vec.len());
+    match load_curve_pools(env.https_url.clone(), CURVE_REGISTRY_ADDRESS).await {
+        Ok(curve_pools) => loaded_pools.extend(curve_pools),
+        Err(e) => info!("Error loading Curve pools: {:?}", e),
+    }
+    match load_balancer_weighted_pools(
+        env.https_url.clone(),
+        BALANCER_VAULT_ADDRESS,
+        BALANCER_VAULT_DEPLOY_BLOCK,
+    )
+    .await
+    {
+        Ok(balancer_pools) => loaded_pools.extend(balancer_pools),
+        Err(e) => info!("Error loading Balancer pools: {:?}", e),
+    }
+    info!("Initial pool count: {}", loaded_pools.len());
 
-    // Performing USDC triangular arbitrage
-    let usdc_address = H160::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap();
-    let usdc_decimals = 6;
+    let snapshot_path = Path::new(PATH_SNAPSHOT_PATH);
+    let snapshot_params = SnapshotParams {
+        base_token: usdc_address,
+        min_hops: 3,
+        max_hops: 3,
+        factories: factories
+            .iter()
+            .map(|factory| (factory.address, factory.router, factory.fee))
+            .collect(),
+        min_weth: MIN_WETH_THRESHOLD,
+        pool_set: pool_set_hash(&loaded_pools),
+    };
+    let snapshot = match PathSnapshot::load_matching(
+        snapshot_path,
+        &snapshot_params,
+        Duration::from_secs(PATH_SNAPSHOT_MAX_AGE_SECS),
+    ) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            info!("Error loading path snapshot: {:?}", e);
+            None
+        }
+    };
 
-    let paths = generate_triangular_paths(&pools_vec, usdc_address);
+    let paths = match snapshot {
+        Some(snapshot) => {
+            info!(
+                "Loaded {} pools and {} paths from snapshot",
+                snapshot.pools.len(),
+                snapshot.paths.len()
+            );
+            snapshot.paths
+        }
+        None => {
+            // Value every pool before path generation so dust pools never enter a path.
+            // Pools dropped here only come back once the snapshot expires.
+            let states = batch_get_pool_states(
+                env.https_url.clone(),
+                loaded_pools.clone(),
+                V3_TICK_WORD_RADIUS,
+            )
+            .await;
+            let pools_vec = liquidity_filter.filter(&loaded_pools, &states);
+
+            let paths = generate_triangular_paths(&pools_vec, usdc_address);
+            let snapshot = PathSnapshot::new(snapshot_params, pools_vec, paths);
+            if let Err(e) = snapshot.save(snapshot_path) {
+                info!("Error saving path snapshot: {:?}", e);
+            }
+            snapshot.paths
+        }
+    };
 
-    let blacklist_tokens = get_blacklist_tokens();
+    let path_tokens: Vec<Address> = paths
+        .iter()
+        .flat_map(|path| path.pools().flat_map(|pool| [pool.token0, pool.token1]))
+        .collect();
+    let mut registry = match TokenRegistry::load(env.https_url.clone(), &path_tokens).await {
+        Ok(registry) => registry,
+        Err(e) => {
+            info!("Error loading token registry: {:?}", e);
+            TokenRegistry::default()
+        }
+    };
+    let usdc_decimals = registry.decimals(&usdc_address).unwrap_or(6) as i32;
+
+    // Classify tokens not seen before so tax, rebasing and unsellable ones are dropped
+    // below
+    let path_pools: Vec<Pool> = paths
+        .iter()
+        .flat_map(|path| path.pools().cloned())
+        .collect();
+    if let Err(e) = detect_token_taxes(&env.https_url, &path_pools, &mut registry).await {
+        info!("Error detecting token taxes: {:?}", e);
+    }
+
+    let paths: Vec<ArbPath> = paths
+        .into_iter()
+        .filter(|path| !registry.excludes_path(path))
+        .collect();
+    info!("Tradable path count: {}", paths.len());
 
     let mut pools = HashMap::new();
 
     for path in &paths {
-        if !path.should_blacklist(&blacklist_tokens) {
-            pools.insert(path.pool_1.address.clone(), path.pool_1.clone());
-            pools.insert(path.pool_2.address.clone(), path.pool_2.clone());
-            pools.insert(path.pool_3.address.clone(), path.pool_3.clone());
+        for pool in path.pools() {
+            pools.insert(pool.address.clone(), pool.clone());
         }
     }
     info!("New pool count: {:?}", pools.len());
 
     let pools_vec: Vec<Pool> = pools.values().cloned().collect();
-    let mut reserves =
-        batch_get_uniswap_v2_reserves(env.https_url.clone(), pools_vec.clone()).await;
+    let mut reserves = batch_get_pool_states(
+        env.https_url.clone(),
+        pools_vec.clone(),
+        V3_TICK_WORD_RADIUS,
+    )
+    .await;
+
+    // Pools whose state isn't derived from logs and is re-read every block
+    let polled_pools: Vec<Pool> = pools_vec
+        .iter()
+        .filter(|pool| {
+            matches!(
+                pool.version,
+                DexVariant::CurveStable | DexVariant::BalancerWeighted
+            )
+        })
+        .cloned()
+        .collect();
+
+    // Price path tokens off every loaded WETH pair, not just those that made it into a path
+    let pricing_pools = liquidity_filter.pricing_pools(&loaded_pools, &pools_vec);
+    let mut liquid_pools = refresh_liquid_pools(
+        &env.https_url,
+        &liquidity_filter,
+        &pools_vec,
+        &pricing_pools,
+        &reserves,
+    )
+    .await;
+    let mut last_filter_block = 0u64;
+    let mut history = BlockHistory::new(REORG_HISTORY_DEPTH);
+    let mut reserve_state = ReserveStateManager::new(reserves.keys().cloned());
+
+    // Pending router swaps are only decoded when backrunning is switched on
+    let backrun = match env.backrun.then(Bundler::new) {
+        Some(Ok(bundler)) => Some((
+            BackrunEngine::new(&pools_vec, U256::from(1000), SearchConfig::default())
+                .with_router_alias(
+                    Address::from_str(SWAP_ROUTER_02_ADDRESS).unwrap(),
+                    Address::from_str(UNISWAP_V2_ROUTER_ADDRESS).unwrap(),
+                ),
+            Arc::new(bundler),
+        )),
+        Some(Err(e)) => {
+            info!("Error creating bundler, not backrunning: {:?}", e);
+            None
+        }
+        None => None,
+    };
+    let mut latest_block: Option<NewBlock> = None;
+    let mut min_backrun_profit = I256::ZERO;
 
     let mut event_receiver = event_sender.subscribe();
+    // Set when the receiver fell behind and dropped events, which may have been logs
+    let mut lagged = false;
 
     loop {
         match event_receiver.recv().await {
             Ok(event) => match event {
                 Event::Block(block) => {
                     info!("{:?}", block);
-                    let touched_reserves =
-                        match get_touched_pool_reserves(provider.clone(), block.block_number).await
+                    latest_block = Some(block.clone());
+                    let mut touched_pools = Vec::new();
+                    let mut refreshed = false;
+                    if lagged {
+                        // Diffs from before the gap no longer match the re-read state
+                        reserves = batch_get_pool_states(
+                            env.https_url.clone(),
+                            pools_vec.clone(),
+                            V3_TICK_WORD_RADIUS,
+                        )
+                        .await;
+                        history = BlockHistory::new(REORG_HISTORY_DEPTH);
+                        touched_pools.extend(reserves.keys().cloned());
+                        refreshed = true;
+                        lagged = false;
+                    } else if !history.extends_tip(&block) {
+                        match handle_reorg(
+                            provider.clone(),
+                            &env.https_url,
+                            &block,
+                            &mut history,
+                            &mut reserves,
+                            &pools_vec,
+                        )
+                        .await
                         {
-                            Ok(response) => response,
-                            Err(e) => {
-                                info!("Error from get_touched_pool_reserves: {:?}", e);
-                                HashMap::new()
+                            Ok((reorg, touched, full_refresh)) => {
+                                // Blocks below this one were replayed over RPC, so their
+                                // streamed logs must not be applied a second time
+                                reserve_state.discard_through(block.block_number - 1);
+                                touched_pools.extend(touched);
+                                refreshed = full_refresh;
+                                if reorg.depth > 0 {
+                                    let _ = event_sender.send(Event::Reorg(reorg));
+                                }
                             }
-                        };
-                    let mut touched_pools = Vec::new();
-                    for (address, reserve) in touched_reserves.into_iter() {
-                        if reserves.contains_key(&address) {
-                            reserves.insert(address, reserve);
+                            Err(e) => info!("Error handling reorg: {:?}", e),
+                        }
+                    }
+
+                    // The block's pool logs arrived on the log stream ahead of its header.
+                    // A full refresh already read this block's state, so they're dropped.
+                    // If none have arrived yet, the node's logs are used instead.
+                    let mut diff = ReserveDiff::default();
+                    if refreshed {
+                        reserve_state.discard_through(block.block_number);
+                    } else if !reserve_state.has_logs_for(&block) {
+                        let touched = replay_block_logs(
+                            provider.clone(),
+                            block.block_number,
+                            &mut reserves,
+                            &mut diff,
+                        )
+                        .await;
+                        reserve_state.discard_through(block.block_number);
+                        touched_pools.extend(touched);
+                    } else {
+                        let finalized =
+                            reserve_state.finalize(&block, &history, &mut reserves, &mut diff);
+                        touched_pools.extend(finalized.touched.iter().cloned());
+                        let _ = event_sender.send(Event::BlockStateFinalized(finalized));
+                    }
+                    if !polled_pools.is_empty() {
+                        let polled_states = batch_get_pool_states(
+                            env.https_url.clone(),
+                            polled_pools.clone(),
+                            V3_TICK_WORD_RADIUS,
+                        )
+                        .await;
+                        for (address, state) in polled_states {
+                            diff.record(address, &reserves);
+                            reserves.insert(address, state);
                             touched_pools.push(address);
                         }
                     }
+                    history.push(block.block_number, block.block_hash, diff);
                     info!("{:?}", touched_pools);
 
-                    let mut spreads = HashMap::new();
-                    for (idx, path) in (&paths).iter().enumerate() {
-                        let touched_path = touched_pools
-                            .iter()
-                            .map(|pool| path.has_pool(&pool) as i32)
-                            .sum::<i32>()
-                            >= 1;
-
-                        if touched_path {
-                            let one_token_in = U256::from(1);
-                            let simulated = path.simulate_v2_path(one_token_in, &reserves);
-
-                            match simulated {
-                                Some(price_quote) => {
-                                    let one_usdc_in = one_token_in * U256::from(usdc_decimals);
-                                    let _out = price_quote.as_u128() as i128;
-                                    let _in = one_usdc_in.as_u128() as i128;
-                                    let spread = _out - _in;
-
-                                    if spread > 0 {
-                                        spreads.insert(idx, spread);
-                                    }
-                                }
-                                None => {}
-                            }
-                        }
+                    // Reserves drift, so re-run the liquidity filter every so often
+                    let block_number = block.block_number;
+                    if block_number >= last_filter_block + LIQUIDITY_FILTER_REFRESH_BLOCKS {
+                        liquid_pools = refresh_liquid_pools(
+                            &env.https_url,
+                            &liquidity_filter,
+                            &pools_vec,
+                            &pricing_pools,
+                            &reserves,
+                        )
+                        .await;
+                        last_filter_block = block_number;
                     }
 
+                    let spreads =
+                        find_spreads(&paths, &touched_pools, &liquid_pools, &reserves, &registry);
+
                     let usdc_weth_address =
                         Address::from_str("0x397FF1542f962076d0BFE58eA045FfA2d347ACa0").unwrap();
                     let pool = pools.get(&usdc_weth_address).unwrap();
-                    let reserve = reserves.get(&usdc_weth_address).unwrap();
+                    let reserve = match reserves.get(&usdc_weth_address) {
+                        Some(PoolState::UniswapV2(reserve)) => reserve,
+                        _ => continue,
+                    };
                     let weth_price = UniswapV2Simulator::reserves_to_price(
                         reserve.reserve0,
                         reserve.reserve1,
@@ -122,10 +350,11 @@ pub async fn event_handler(provider: Arc<Provider<Ws>>, event_sender: Sender<Eve
                     let estimated_gas_usage = U256::from(550000);
                     let gas_cost_in_wei = base_fee * estimated_gas_usage;
                     let gas_cost_in_wmatic =
-                        (gas_cost_in_wei.as_u64() as f64) / ((*WEI).as_u64() as f64);
+                        (gas_cost_in_wei.to::<u64>() as f64) / ((*WEI).to::<u64>() as f64);
                     let gas_cost_in_usdc = weth_price * gas_cost_in_wmatic;
                     let gas_cost_in_usdc =
                         U256::from((gas_cost_in_usdc * ((10 as f64).powi(usdc_decimals))) as u64);
+                    min_backrun_profit = I256::from_raw(gas_cost_in_usdc);
 
                     let mut sorted_spreads: Vec<_> = spreads.iter().collect();
                     sorted_spreads.sort_by_key(|x| x.1);
@@ -134,22 +363,402 @@ pub async fn event_handler(provider: Arc<Provider<Ws>>, event_sender: Sender<Eve
                     for spread in sorted_spreads {
                         let path_idx = spread.0;
                         let path = &paths[*path_idx];
-                        let opt = path.optimize_amount_in(U256::from(1000), 10, &reserves);
-                        let excess_profit =
-                            (opt.1.as_u128() as i128) - (gas_cost_in_usdc.as_u128() as i128);
+                        let opt = path.optimize_amount_in(
+                            U256::from(1000),
+                            &SearchConfig::default(),
+                            &reserves,
+                        );
+                        let excess_profit = i128::try_from(opt.1).unwrap_or(i128::MAX)
+                            - (gas_cost_in_usdc.to::<u128>() as i128);
 
                         // TODO
                         if excess_profit > 0 {}
                     }
                 }
-                Event::PendingTx(_) => {
-                    // not using pending tx
+                Event::PendingTx(tx) => {
+                    let ((engine, bundler), block) = match (&backrun, &latest_block) {
+                        (Some(backrun), Some(block)) => (backrun, block.clone()),
+                        _ => continue,
+                    };
+                    let opportunity = match engine.find(&tx, &paths, &reserves, min_backrun_profit)
+                    {
+                        Some(opportunity) => opportunity,
+                        None => continue,
+                    };
+                    let path = paths[opportunity.path_index].clone();
+                    info!(
+                        "Backrunning {:?} on {} for {} profit",
+                        tx.inner.inner().tx_hash(),
+                        registry.path_label(&path),
+                        opportunity.profit
+                    );
+
+                    let priority_fee = U256::from(BACKRUN_PRIORITY_FEE_GWEI) * *GWEI;
+                    let max_fee = block.next_base_fee + priority_fee;
+                    let bundler = bundler.clone();
+                    // Sending waits on the relay, which mustn't hold up the event loop
+                    tokio::spawn(async move {
+                        let bundle = build_backrun_bundle(
+                            &bundler,
+                            &opportunity,
+                            &path,
+                            block.block_number,
+                            priority_fee,
+                            max_fee,
+                        )
+                        .await;
+                        match bundle {
+                            Ok(bundle) => match bundler.send_bundle(bundle).await {
+                                Ok(hash) => info!("Backrun bundle included: {:?}", hash),
+                                Err(e) => info!("Error sending backrun bundle: {:?}", e),
+                            },
+                            Err(e) => info!("Error building backrun bundle: {:?}", e),
+                        }
+                    });
+                }
+                Event::Log(log) => {
+                    reserve_state.on_log(log);
+                }
+                Event::Connection(connection) => {
+                    info!("{:?}", connection);
+                }
+                Event::Reorg(reorg) => {
+                    info!("{:?}", reorg);
                 }
-                Event::Log(_) => {
-                    // not using logs
+                Event::BlockStateFinalized(_) => {
+                    // sent by this handler for other listeners
+                }
+            },
+            Err(RecvError::Lagged(skipped)) => {
+                info!("Event handler skipped {} events, re-reading pools", skipped);
+                lagged = true;
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Fetches and applies the V2 `Sync` and V3 pool logs of `block_number` to the tracked
+/// pools in `reserves`, recording their prior states in `diff`. Used to replay blocks
+/// after a reorg, whose logs the stream never delivered. Returns the pools it changed.
+async fn replay_block_logs<P: Provider>(
+    provider: Arc<P>,
+    block_number: u64,
+    reserves: &mut HashMap<Address, PoolState>,
+    diff: &mut ReserveDiff,
+) -> Vec<Address> {
+    let touched_reserves = match get_touched_pool_reserves(provider.clone(), block_number).await {
+        Ok(response) => response,
+        Err(e) => {
+            info!("Error from get_touched_pool_reserves: {:?}", e);
+            HashMap::new()
+        }
+    };
+    let mut touched_pools = Vec::new();
+    for (address, reserve) in touched_reserves.into_iter() {
+        if let Some(PoolState::UniswapV2(_)) = reserves.get(&address) {
+            diff.record(address, reserves);
+            reserves.insert(address, reserve.into());
+            touched_pools.push(address);
+        }
+    }
+    match update_touched_v3_states(provider.clone(), block_number, reserves, diff).await {
+        Ok(touched) => touched_pools.extend(touched),
+        Err(e) => info!("Error from update_touched_v3_states: {:?}", e),
+    }
+    touched_pools
+}
+
+/// Rolls `reserves` back to the newest block `block` still builds on and re-applies the
+/// canonical blocks up to its parent. Returns the reorg, the pools it changed, and
+/// whether the reorg outran the history so every pool was re-read at the chain head.
+async fn handle_reorg<P: Provider>(
+    provider: Arc<P>,
+    https_url: &str,
+    block: &NewBlock,
+    history: &mut BlockHistory,
+    reserves: &mut HashMap<Address, PoolState>,
+    pools: &[Pool],
+) -> Result<(Reorg, Vec<Address>, bool)> {
+    let ancestor = match history
+        .find_common_ancestor(provider.as_ref(), block)
+        .await?
+    {
+        Some(ancestor) => ancestor,
+        None => {
+            let depth = history.rollback_all(reserves);
+            *reserves =
+                batch_get_pool_states(https_url.to_string(), pools.to_vec(), V3_TICK_WORD_RADIUS)
+                    .await;
+            let reorg = Reorg {
+                block_number: block.block_number,
+                depth,
+            };
+            return Ok((reorg, reserves.keys().cloned().collect(), true));
+        }
+    };
+    let depth = history.rollback_to(ancestor, reserves);
+
+    // Blocks between the ancestor and `block` are either replacements for orphaned ones
+    // or blocks the stream skipped
+    let mut touched = Vec::new();
+    for number in ancestor + 1..block.block_number {
+        let hash = provider
+            .get_block_by_number(number.into())
+            .await?
+            .ok_or_else(|| anyhow!("block {} not found", number))?
+            .header
+            .hash;
+        let mut diff = ReserveDiff::default();
+        touched.extend(replay_block_logs(provider.clone(), number, reserves, &mut diff).await);
+        history.push(number, hash, diff);
+    }
+
+    let reorg = Reorg {
+        block_number: block.block_number,
+        depth,
+    };
+    Ok((reorg, touched, false))
+}
+
+/// Addresses of the `pools` the liquidity filter keeps, with token prices read from
+/// fresh states of `pricing_pools`.
+async fn refresh_liquid_pools(
+    https_url: &str,
+    liquidity_filter: &LiquidityFilter,
+    pools: &[Pool],
+    pricing_pools: &[Pool],
+    reserves: &HashMap<Address, PoolState>,
+) -> HashSet<Address> {
+    let pricing_states = batch_get_pool_states(
+        https_url.to_string(),
+        pricing_pools.to_vec(),
+        V3_TICK_WORD_RADIUS,
+    )
+    .await;
+    let prices = liquidity_filter.token_prices(pricing_pools, &pricing_states);
+    liquidity_filter
+        .filter_with_prices(pools, reserves, &prices)
+        .iter()
+        .map(|pool| pool.address)
+        .collect()
+}
+
+/// Spreads of the paths that touch one of `touched_pools` and only use liquid pools,
+/// quoted for one whole input token and keyed by path index. Only positive spreads are kept.
+fn find_spreads(
+    paths: &[ArbPath],
+    touched_pools: &[Address],
+    liquid_pools: &HashSet<Address>,
+    reserves: &HashMap<Address, PoolState>,
+    registry: &TokenRegistry,
+) -> HashMap<usize, i128> {
+    let mut spreads = HashMap::new();
+    for (idx, path) in paths.iter().enumerate() {
+        let touched_path = touched_pools
+            .iter()
+            .map(|pool| path.has_pool(&pool) as i32)
+            .sum::<i32>()
+            >= 1;
+
+        let liquid_path = path
+            .pools()
+            .all(|pool| liquid_pools.contains(&pool.address));
+
+        if touched_path && liquid_path {
+            let unit = U256::from(10).pow(U256::from(path.token_in_decimals()));
+            let simulated = path.simulate_path_with_taxes(unit, reserves, registry);
+
+            match simulated {
+                Some(price_quote) => {
+                    let _out = price_quote.to::<u128>() as i128;
+                    let _in = unit.to::<u128>() as i128;
+                    let spread = _out - _in;
+
+                    if spread > 0 {
+                        debug!("{} spread: {}", registry.path_label(path), spread);
+                        spreads.insert(idx, spread);
+                    }
                 }
+                None => {}
+            }
+        }
+    }
+    spreads
+}
+
+/// Watches spreads on a `StateSpaceManager`'s live-synced pools instead of the pool cache
+/// and per-block multicalls. Paths are built from the state space's V2 and V3 pools, with
+/// V2 pools routed through `v2_router`, and every update the manager reports re-quotes
+/// the paths through the updated pools and logs the best spread.
+///
+/// This is a monitor: sizing and sending arbs, backrunning and reorg handling stay with
+/// `event_handler`.
+pub async fn state_space_monitor<N, P>(
+    manager: Arc<StateSpaceManager<N, P>>,
+    v2_router: Address,
+) -> Result<()>
+where
+    N: Network,
+    P: Provider<N> + Clone + 'static,
+{
+    let env = Env::new()?;
+
+    let usdc_address = Address::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap();
+    let weth_address = WETH_ADDRESS;
+    let liquidity_filter = LiquidityFilter::new(weth_address, U256::from(MIN_WETH_THRESHOLD));
+
+    let (pools_vec, mut reserves) = {
+        let state = manager.state.read().await;
+        (
+            state_space_pools(state.state.values(), v2_router),
+            state_space_reserves(state.state.values()),
+        )
+    };
+    info!("State space pool count: {}", pools_vec.len());
+
+    let pools_vec = liquidity_filter.filter(&pools_vec, &reserves);
+    let paths = generate_triangular_paths(&pools_vec, usdc_address);
+
+    let path_tokens: Vec<Address> = paths
+        .iter()
+        .flat_map(|path| path.pools().flat_map(|pool| [pool.token0, pool.token1]))
+        .collect();
+    let mut registry = match TokenRegistry::load(env.https_url.clone(), &path_tokens).await {
+        Ok(registry) => registry,
+        Err(e) => {
+            info!("Error loading token registry: {:?}", e);
+            TokenRegistry::default()
+        }
+    };
+    if let Err(e) = detect_token_taxes(&env.https_url, &pools_vec, &mut registry).await {
+        info!("Error detecting token taxes: {:?}", e);
+    }
+    let paths: Vec<ArbPath> = paths
+        .into_iter()
+        .filter(|path| !registry.excludes_path(path))
+        .collect();
+    info!("Tradable path count: {}", paths.len());
+
+    // Only keep the pools a path goes through, so updates elsewhere are ignored
+    let path_pools: HashSet<Address> = paths
+        .iter()
+        .flat_map(|path| path.pools().map(|pool| pool.address))
+        .collect();
+    reserves.retain(|address, _| path_pools.contains(address));
+
+    let mut liquid_pools = path_pools.clone();
+    let mut updates = 0u64;
+
+    let mut stream = manager.subscribe()?;
+    while let Some(result) = stream.next().await {
+        let updated = match result {
+            Ok(updated) => updated,
+            Err(e) => {
+                info!("Error from state space subscription: {:?}", e);
+                continue;
+            }
+        };
+        let touched_pools = {
+            let state = manager.state.read().await;
+            apply_state_space_updates(&state.state, &updated, &mut reserves)
+        };
+
+        // Each update is one block's worth of logs
+        updates += 1;
+        if updates % LIQUIDITY_FILTER_REFRESH_BLOCKS == 0 {
+            liquid_pools = liquidity_filter
+                .filter(&pools_vec, &reserves)
+                .iter()
+                .map(|pool| pool.address)
+                .collect();
+        }
+
+        let spreads = find_spreads(&paths, &touched_pools, &liquid_pools, &reserves, &registry);
+        if let Some((idx, spread)) = spreads.iter().max_by_key(|(_, spread)| **spread) {
+            info!(
+                "Best spread {} on {}",
+                spread,
+                registry.path_label(&paths[*idx])
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod strategy_tests {
+    use super::*;
+    use crate::abi::events::IUniswapV2Pair;
+    use crate::multi::Reserve;
+    use crate::reorg::reorg_tests::rpc_block;
+    use alloy::{
+        primitives::{aliases::U112, B256},
+        providers::ProviderBuilder,
+        rpc::types::Log,
+        sol_types::SolEvent,
+        transports::mock::Asserter,
+    };
+
+    #[tokio::test]
+    async fn reorg_handling_replays_skipped_blocks() {
+        let pool = Address::repeat_byte(0xa);
+        let mut reserves = HashMap::from([(pool, PoolState::UniswapV2(Reserve::default()))]);
+        let mut history = BlockHistory::new(8);
+        history.push(10, B256::repeat_byte(10), ReserveDiff::default());
+
+        // The stream skipped blocks 11 and 12
+        let block = NewBlock {
+            block_number: 13,
+            block_hash: B256::repeat_byte(13),
+            parent_hash: B256::repeat_byte(12),
+            ..Default::default()
+        };
+        let sync = IUniswapV2Pair::Sync {
+            reserve0: U112::from(110),
+            reserve1: U112::from(1),
+        };
+        let sync_log = Log {
+            inner: alloy::primitives::Log {
+                address: pool,
+                data: sync.encode_log_data(),
             },
-            Err(_) => {}
+            block_number: Some(11),
+            block_hash: Some(B256::repeat_byte(11)),
+            log_index: Some(0),
+            ..Default::default()
+        };
+        let no_logs: Vec<Log> = Vec::new();
+
+        // The ancestor lookup, then each skipped block's hash, Sync logs and V3 logs
+        let asserter = Asserter::new();
+        asserter.push_success(&rpc_block(10, B256::repeat_byte(10)));
+        asserter.push_success(&rpc_block(11, B256::repeat_byte(11)));
+        asserter.push_success(&vec![sync_log]);
+        asserter.push_success(&no_logs);
+        asserter.push_success(&rpc_block(12, B256::repeat_byte(12)));
+        asserter.push_success(&no_logs);
+        asserter.push_success(&no_logs);
+        let provider = Arc::new(ProviderBuilder::new().connect_mocked_client(asserter));
+
+        let (reorg, touched, full_refresh) =
+            handle_reorg(provider, "", &block, &mut history, &mut reserves, &[])
+                .await
+                .unwrap();
+        assert_eq!(
+            reorg,
+            Reorg {
+                block_number: 13,
+                depth: 0
+            }
+        );
+        assert_eq!(touched, vec![pool]);
+        assert!(!full_refresh);
+        assert_eq!(history.tip(), Some((12, B256::repeat_byte(12))));
+        match &reserves[&pool] {
+            PoolState::UniswapV2(reserve) => assert_eq!(reserve.reserve0, U256::from(110)),
+            other => panic!("unexpected state {:?}", other),
         }
     }
 }
@@ -1,74 +1,414 @@
-use ethers::{
-    providers::{Provider, Ws},
-    types::{Filter, Log, Transaction, U256, U64},
+use alloy::{
+    primitives::{B256, U256},
+    providers::{Provider, ProviderBuilder, WsConnect},
+    rpc::types::{Filter, Header, Log, Transaction},
+    sol_types::SolEvent,
 };
-use ethers_providers::Middleware;
-use std::sync::Arc;
-use tokio::sync::broadcast::Sender;
-use tokio_stream::StreamExt;
+use anyhow::Result;
+use futures::StreamExt;
+use log::info;
+use std::{
+    collections::{HashSet, VecDeque},
+    hash::Hash,
+    time::{Duration, Instant},
+};
+use tokio::sync::{broadcast::Sender, mpsc};
+use url::Url;
 
+use crate::abi::events::{IUniswapV2Pair, IUniswapV3Pool};
+use crate::reserve_state::FinalizedBlock;
 use crate::utils::calculate_next_block_base_fee;
 
+const RECONNECT_MIN_DELAY: Duration = Duration::from_millis(500);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+// Uptime after which a dropped connection counts as a fresh failure rather than a flap
+const RECONNECT_STABLE_AFTER: Duration = Duration::from_secs(30);
+// Keys remembered for deduplication; pending transactions arrive by the thousand per block
+const RECENT_KEYS_CAPACITY: usize = 16_384;
+
 #[derive(Default, Debug, Clone)]
 pub struct NewBlock {
-    pub block_number: U64,
+    pub block_number: u64,
+    pub block_hash: B256,
+    pub parent_hash: B256,
     pub base_fee: U256,
     pub next_base_fee: U256,
 }
 
+impl NewBlock {
+    pub fn from_header(header: &Header) -> Self {
+        let base_fee = header.base_fee_per_gas.unwrap_or_default();
+        Self {
+            block_number: header.number,
+            block_hash: header.hash,
+            parent_hash: header.parent_hash,
+            base_fee: U256::from(base_fee),
+            next_base_fee: U256::from(calculate_next_block_base_fee(
+                header.gas_used,
+                header.gas_limit,
+                base_fee,
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamKind {
+    NewBlocks,
+    PendingTransactions,
+    UniswapV2Events,
+    /// V2 `Sync` plus V3 `Swap`, `Mint` and `Burn` logs, for `ReserveStateManager`.
+    PoolEvents,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    /// The connection or subscription failed or ended; it's retried after `retry_in`.
+    Disconnected {
+        reason: String,
+        retry_in: Duration,
+    },
+}
+
+/// A supervised subscription changing state. `endpoint` is the URL's host, so API keys
+/// in the path or query never reach the logs.
+#[derive(Debug, Clone)]
+pub struct ConnectionEvent {
+    pub kind: StreamKind,
+    pub endpoint: String,
+    pub state: ConnectionState,
+}
+
+/// The strategy rolled back `depth` orphaned blocks and re-applied the canonical chain
+/// up to `block_number`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reorg {
+    pub block_number: u64,
+    pub depth: u64,
+}
+
 #[derive(Debug, Clone)]
 pub enum Event {
     Block(NewBlock),
     PendingTx(Transaction),
     Log(Log),
+    Connection(ConnectionEvent),
+    Reorg(Reorg),
+    BlockStateFinalized(FinalizedBlock),
 }
 
-pub async fn stream_new_blocks(provider: Arc<Provider<Ws>>, event_sender: Sender<Event>) {
-    let stream = provider.subscribe_blocks().await.unwrap();
-    let mut stream = stream.filter_map(|block| match block.number {
-        Some(number) => Some(NewBlock {
-            block_number: number,
-            base_fee: block.base_fee_per_gas.unwrap_or_default(),
-            next_base_fee: U256::from(calculate_next_block_base_fee(
-                block.gas_used,
-                block.gas_limit,
-                block.base_fee_per_gas.unwrap_or_default(),
-            )),
-        }),
-        None => None,
-    });
-
-    while let Some(block) = stream.next().await {
-        match event_sender.send(Event::Block(block)) {
-            Ok(_) => {}
-            Err(_) => {}
+/// Identifies an item delivered by more than one endpoint. A removed log is a different
+/// item from the log it retracts.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum StreamKey {
+    Block(B256),
+    Transaction(B256),
+    Log {
+        block_hash: B256,
+        log_index: u64,
+        removed: bool,
+    },
+}
+
+/// Subscribes to `kind` on every endpoint and forwards whichever copy of each block,
+/// transaction or log arrives first. Each endpoint reconnects on its own with
+/// exponential backoff, so this only returns once `event_sender` has no receivers.
+pub async fn supervise_stream(
+    kind: StreamKind,
+    endpoints: Vec<String>,
+    event_sender: Sender<Event>,
+) {
+    let (item_sender, mut item_receiver) = mpsc::unbounded_channel();
+    for endpoint in endpoints {
+        tokio::spawn(_supervise_endpoint(
+            kind,
+            endpoint,
+            item_sender.clone(),
+            event_sender.clone(),
+        ));
+    }
+    drop(item_sender);
+
+    let mut seen = RecentKeys::new(RECENT_KEYS_CAPACITY);
+    while let Some((key, event)) = item_receiver.recv().await {
+        if seen.insert(key) && event_sender.send(event).is_err() {
+            break;
         }
     }
 }
 
-pub async fn stream_pending_transactions(provider: Arc<Provider<Ws>>, event_sender: Sender<Event>) {
-    let stream = provider.subscribe_pending_txs().await.unwrap();
-    let mut stream = stream.transactions_unordered(256).fuse();
+async fn _supervise_endpoint(
+    kind: StreamKind,
+    endpoint: String,
+    items: mpsc::UnboundedSender<(StreamKey, Event)>,
+    event_sender: Sender<Event>,
+) {
+    let label = endpoint_label(&endpoint);
+    let mut backoff = Backoff::new(RECONNECT_MIN_DELAY, RECONNECT_MAX_DELAY);
 
-    while let Some(result) = stream.next().await {
-        match result {
-            Ok(tx) => match event_sender.send(Event::PendingTx(tx)) {
-                Ok(_) => {}
-                Err(_) => {}
-            },
-            Err(_) => {}
+    while !items.is_closed() {
+        let mut connected_at = None;
+        let result = _stream_endpoint(
+            kind,
+            &endpoint,
+            &label,
+            &items,
+            &event_sender,
+            &mut connected_at,
+        )
+        .await;
+        if let Some(connected_at) = connected_at {
+            backoff.connection_ended(connected_at.elapsed(), RECONNECT_STABLE_AFTER);
+        }
+        let reason = match result {
+            Ok(()) => "subscription ended".to_string(),
+            Err(e) => e.to_string(),
         };
+        let retry_in = backoff.next_delay();
+        info!(
+            "{:?} stream from {} disconnected ({}), retrying in {:?}",
+            kind, label, reason, retry_in
+        );
+        _send_connection(
+            &event_sender,
+            kind,
+            &label,
+            ConnectionState::Disconnected { reason, retry_in },
+        );
+        tokio::time::sleep(retry_in).await;
     }
 }
 
-pub async fn stream_uniswap_v2_events(provider: Arc<Provider<Ws>>, event_sender: Sender<Event>) {
-    let filter = Filter::new().event("Sync(uint112,uint112)");
-    let mut stream = provider.subscribe_logs(&filter).await.unwrap();
+/// Connects to `endpoint` and forwards `kind` items until the subscription ends.
+/// Errors mean the connection or subscription never came up. `connected_at` is set once
+/// the subscription is live.
+async fn _stream_endpoint(
+    kind: StreamKind,
+    endpoint: &str,
+    label: &str,
+    items: &mpsc::UnboundedSender<(StreamKey, Event)>,
+    event_sender: &Sender<Event>,
+    connected_at: &mut Option<Instant>,
+) -> Result<()> {
+    let provider = ProviderBuilder::new()
+        .connect_ws(WsConnect::new(endpoint))
+        .await?;
+
+    match kind {
+        StreamKind::NewBlocks => {
+            let mut stream = provider.subscribe_blocks().await?.into_stream();
+            _send_connection(event_sender, kind, label, ConnectionState::Connected);
+            *connected_at = Some(Instant::now());
+            while let Some(header) = stream.next().await {
+                let key = StreamKey::Block(header.hash);
+                let event = Event::Block(NewBlock::from_header(&header));
+                if items.send((key, event)).is_err() {
+                    break;
+                }
+            }
+        }
+        StreamKind::PendingTransactions => {
+            let mut stream = provider
+                .subscribe_full_pending_transactions()
+                .await?
+                .into_stream();
+            _send_connection(event_sender, kind, label, ConnectionState::Connected);
+            *connected_at = Some(Instant::now());
+            while let Some(tx) = stream.next().await {
+                let key = StreamKey::Transaction(*tx.inner.tx_hash());
+                if items.send((key, Event::PendingTx(tx))).is_err() {
+                    break;
+                }
+            }
+        }
+        StreamKind::UniswapV2Events | StreamKind::PoolEvents => {
+            let filter = _log_filter(kind);
+            let mut stream = provider.subscribe_logs(&filter).await?.into_stream();
+            _send_connection(event_sender, kind, label, ConnectionState::Connected);
+            *connected_at = Some(Instant::now());
+            while let Some(log) = stream.next().await {
+                let key = StreamKey::Log {
+                    block_hash: log.block_hash.unwrap_or_default(),
+                    log_index: log.log_index.unwrap_or_default(),
+                    removed: log.removed,
+                };
+                if items.send((key, Event::Log(log))).is_err() {
+                    break;
+                }
+            }
+        }
+    }
 
-    while let Some(result) = stream.next().await {
-        match event_sender.send(Event::Log(result)) {
-            Ok(_) => {}
-            Err(_) => {}
+    Ok(())
+}
+
+fn _log_filter(kind: StreamKind) -> Filter {
+    match kind {
+        StreamKind::PoolEvents => Filter::new().event_signature(vec![
+            IUniswapV2Pair::Sync::SIGNATURE_HASH,
+            IUniswapV3Pool::Swap::SIGNATURE_HASH,
+            IUniswapV3Pool::Mint::SIGNATURE_HASH,
+            IUniswapV3Pool::Burn::SIGNATURE_HASH,
+        ]),
+        _ => Filter::new().event_signature(IUniswapV2Pair::Sync::SIGNATURE_HASH),
+    }
+}
+
+fn _send_connection(
+    event_sender: &Sender<Event>,
+    kind: StreamKind,
+    endpoint: &str,
+    state: ConnectionState,
+) {
+    let event = ConnectionEvent {
+        kind,
+        endpoint: endpoint.to_string(),
+        state,
+    };
+    match event_sender.send(Event::Connection(event)) {
+        Ok(_) => {}
+        Err(_) => {}
+    }
+}
+
+/// The host and port of `endpoint`, without the path and query where providers put keys.
+pub fn endpoint_label(endpoint: &str) -> String {
+    match Url::parse(endpoint) {
+        Ok(url) => match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            _ => "unknown endpoint".to_string(),
+        },
+        Err(_) => "invalid endpoint".to_string(),
+    }
+}
+
+/// Exponential reconnect delay, doubling from `min` up to `max`.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max,
+            next: min,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.next = self.min;
+    }
+
+    /// Called when a connection drops after being up for `uptime`. Only one that stayed
+    /// up for `stable_after` retries quickly again; an endpoint that accepts and then
+    /// drops every subscription keeps backing off.
+    pub fn connection_ended(&mut self, uptime: Duration, stable_after: Duration) {
+        if uptime >= stable_after {
+            self.reset();
+        }
+    }
+}
+
+/// The last `capacity` keys seen, for dropping duplicates from racing endpoints.
+struct RecentKeys<K> {
+    capacity: usize,
+    keys: HashSet<K>,
+    order: VecDeque<K>,
+}
+
+impl<K: Hash + Eq + Clone> RecentKeys<K> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            keys: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Returns false if `key` was already seen.
+    fn insert(&mut self, key: K) -> bool {
+        if !self.keys.insert(key.clone()) {
+            return false;
+        }
+        self.order.push_back(key);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.keys.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod streams_tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_max_and_resets() {
+        let mut backoff = Backoff::new(Duration::from_millis(500), Duration::from_secs(3));
+        let delays: Vec<u128> = (0..5).map(|_| backoff.next_delay().as_millis()).collect();
+        assert_eq!(delays, vec![500, 1000, 2000, 3000, 3000]);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn backoff_only_resets_after_a_stable_connection() {
+        let stable_after = Duration::from_secs(30);
+        let mut backoff = Backoff::new(Duration::from_millis(500), Duration::from_secs(3));
+        backoff.next_delay();
+
+        // Subscribed, then dropped straight away
+        backoff.connection_ended(Duration::from_millis(10), stable_after);
+        assert_eq!(backoff.next_delay(), Duration::from_millis(1000));
+
+        backoff.connection_ended(Duration::from_secs(60), stable_after);
+        assert_eq!(backoff.next_delay(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn recent_keys_drop_duplicates_and_forget_oldest() {
+        let block = |byte: u8| StreamKey::Block(B256::repeat_byte(byte));
+        let mut seen = RecentKeys::new(2);
+        assert!(seen.insert(block(1)));
+        assert!(!seen.insert(block(1)));
+        assert!(seen.insert(block(2)));
+        assert!(seen.insert(block(3)));
+        // Evicted once a third key arrived
+        assert!(seen.insert(block(1)));
+
+        let log = |removed: bool| StreamKey::Log {
+            block_hash: B256::repeat_byte(9),
+            log_index: 4,
+            removed,
         };
+        assert!(seen.insert(log(false)));
+        assert!(seen.insert(log(true)));
+    }
+
+    #[test]
+    fn endpoint_label_hides_keys() {
+        assert_eq!(
+            endpoint_label("wss://eth-mainnet.example.io/v2/secret-key"),
+            "eth-mainnet.example.io"
+        );
+        assert_eq!(
+            endpoint_label("ws://192.168.200.182:8546"),
+            "192.168.200.182:8546"
+        );
     }
 }
@@ -0,0 +1,272 @@
+//! Classifies tokens by moving a small amount through a forked chain: out of a pool
+//! (a buy), between two holders, and back into the pool (a sell). Any shortfall is the
+//! token's tax; a balance that moves on its own is a rebase.
+use alloy::{
+    primitives::{Address, U256},
+    providers::{ext::AnvilApi, DynProvider, Provider, ProviderBuilder},
+};
+use anyhow::{anyhow, Result};
+use log::info;
+use std::{
+    collections::HashMap,
+    net::{SocketAddr, TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::multi::Erc20Token;
+use crate::pools::{DexVariant, Pool};
+use crate::tokens::TokenRegistry;
+
+const BPS: u64 = 10_000;
+// Fraction of the pool's balance moved in each probe
+const PROBE_DIVISOR: u64 = 1_000;
+// Time skipped between two balance reads when looking for rebases
+const REBASE_PROBE_SECONDS: u64 = 86_400;
+// How long a forking anvil gets to start listening
+const ANVIL_STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenClass {
+    Normal,
+    /// Taxes measured on a buy (pool to holder), a holder-to-holder transfer and a sell
+    /// (holder to pool), in basis points of the amount sent.
+    FeeOnTransfer {
+        buy_bps: u32,
+        transfer_bps: u32,
+        sell_bps: u32,
+    },
+    Rebasing,
+    Unsellable,
+}
+
+impl TokenClass {
+    /// Tax paid when a token passes between two pools in a path: it's bought out of
+    /// one and sold into the next.
+    pub fn effective_tax_bps(&self) -> u32 {
+        match self {
+            TokenClass::FeeOnTransfer {
+                buy_bps, sell_bps, ..
+            } => {
+                let kept = (BPS - *buy_bps as u64) * (BPS - *sell_bps as u64) / BPS;
+                (BPS - kept) as u32
+            }
+            _ => 0,
+        }
+    }
+}
+
+/// A running `anvil` process, killed when dropped.
+struct AnvilProcess {
+    child: Child,
+    endpoint: String,
+}
+
+impl AnvilProcess {
+    /// Starts `anvil --fork-url fork_url` on a free local port and waits for it to listen.
+    fn spawn(fork_url: &str) -> Result<Self> {
+        let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+        let child = Command::new("anvil")
+            .args(["--fork-url", fork_url, "--port", &port.to_string()])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| anyhow!("failed to spawn anvil: {}", e))?;
+        let mut anvil = Self {
+            child,
+            endpoint: format!("http://127.0.0.1:{}", port),
+        };
+
+        let address = SocketAddr::from(([127, 0, 0, 1], port));
+        let started = Instant::now();
+        while TcpStream::connect(address).is_err() {
+            if let Some(status) = anvil.child.try_wait()? {
+                return Err(anyhow!("anvil exited on startup: {}", status));
+            }
+            if started.elapsed() > ANVIL_STARTUP_TIMEOUT {
+                return Err(anyhow!("anvil didn't start listening on port {}", port));
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        Ok(anvil)
+    }
+}
+
+impl Drop for AnvilProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A local Anvil fork of `https_url`; dropping the detector stops the node.
+pub struct TaxDetector {
+    _anvil: AnvilProcess,
+    provider: DynProvider,
+}
+
+impl TaxDetector {
+    /// Requires the `anvil` binary on the PATH. Fails if the node can't be started or
+    /// doesn't start listening in time.
+    pub fn spawn(https_url: &str) -> Result<Self> {
+        let anvil = AnvilProcess::spawn(https_url)?;
+        let provider = ProviderBuilder::new()
+            .connect_http(anvil.endpoint.parse()?)
+            .erased();
+        Ok(Self {
+            _anvil: anvil,
+            provider,
+        })
+    }
+
+    /// Classifies `token` using `pool`, which must hold a balance of it, as the
+    /// counterparty. The fork is reverted afterwards so probes don't affect each other.
+    pub async fn classify(&self, token: Address, pool: Address) -> Result<TokenClass> {
+        let snapshot = self.provider.anvil_snapshot().await?;
+        let class = self._classify(token, pool).await;
+        self.provider.anvil_revert(snapshot).await?;
+        class
+    }
+
+    async fn _classify(&self, token: Address, pool: Address) -> Result<TokenClass> {
+        let accounts = self.provider.get_accounts().await?;
+        let (buyer, holder) = match accounts[..] {
+            [buyer, holder, ..] => (buyer, holder),
+            _ => return Err(anyhow!("anvil has fewer than two accounts")),
+        };
+        let contract = Erc20Token::new(token, self.provider.clone());
+
+        let amount = contract.balanceOf(pool).call().await? / U256::from(PROBE_DIVISOR);
+        if amount.is_zero() {
+            return Err(anyhow!("pool {:?} holds no {:?}", pool, token));
+        }
+
+        // Let the pool itself send, as it would on a swap
+        self.provider.anvil_impersonate_account(pool).await?;
+        self.provider
+            .anvil_set_balance(pool, U256::from(10).pow(U256::from(18)))
+            .await?;
+
+        let bought = match self._transfer(&contract, pool, buyer, amount).await {
+            Some(bought) if !bought.is_zero() => bought,
+            _ => return Ok(TokenClass::Unsellable),
+        };
+        let transferred = match self._transfer(&contract, buyer, holder, bought).await {
+            Some(transferred) if !transferred.is_zero() => transferred,
+            _ => return Ok(TokenClass::Unsellable),
+        };
+
+        self.provider
+            .anvil_increase_time(REBASE_PROBE_SECONDS)
+            .await?;
+        self.provider.evm_mine(None).await?;
+        if contract.balanceOf(holder).call().await? != transferred {
+            return Ok(TokenClass::Rebasing);
+        }
+
+        let sold = match self._transfer(&contract, holder, pool, transferred).await {
+            Some(sold) if !sold.is_zero() => sold,
+            _ => return Ok(TokenClass::Unsellable),
+        };
+
+        let buy_bps = _tax_bps(amount, bought);
+        let transfer_bps = _tax_bps(bought, transferred);
+        let sell_bps = _tax_bps(transferred, sold);
+        if buy_bps == 0 && transfer_bps == 0 && sell_bps == 0 {
+            Ok(TokenClass::Normal)
+        } else {
+            Ok(TokenClass::FeeOnTransfer {
+                buy_bps,
+                transfer_bps,
+                sell_bps,
+            })
+        }
+    }
+
+    /// Sends `amount` and returns how much `to` actually received, or `None` on revert.
+    async fn _transfer(
+        &self,
+        contract: &Erc20Token::Erc20TokenInstance<DynProvider>,
+        from: Address,
+        to: Address,
+        amount: U256,
+    ) -> Option<U256> {
+        let before = contract.balanceOf(to).call().await.ok()?;
+        let call = contract.transfer(to, amount).from(from);
+        let receipt = call.send().await.ok()?.get_receipt().await.ok()?;
+        if !receipt.status() {
+            return None;
+        }
+        let after = contract.balanceOf(to).call().await.ok()?;
+        after.checked_sub(before)
+    }
+}
+
+fn _tax_bps(sent: U256, received: U256) -> u32 {
+    if received >= sent {
+        return 0;
+    }
+    ((sent - received) * U256::from(BPS) / sent).to::<u32>()
+}
+
+/// Classifies every unchecked token in `registry` against a V2 pool from `pools` that
+/// trades it, records the results and refreshes the token cache.
+pub async fn detect_token_taxes(
+    https_url: &str,
+    pools: &[Pool],
+    registry: &mut TokenRegistry,
+) -> Result<HashMap<Address, TokenClass>> {
+    let unchecked = registry.unchecked();
+    if unchecked.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let mut counterparty = HashMap::new();
+    for pool in pools {
+        if matches!(pool.version, DexVariant::UniswapV2) {
+            counterparty.entry(pool.token0).or_insert(pool.address);
+            counterparty.entry(pool.token1).or_insert(pool.address);
+        }
+    }
+
+    let detector = TaxDetector::spawn(https_url)?;
+    let mut classes = HashMap::new();
+    for token in unchecked {
+        let pool = match counterparty.get(&token) {
+            Some(pool) => *pool,
+            None => continue,
+        };
+        match detector.classify(token, pool).await {
+            Ok(class) => {
+                if class != TokenClass::Normal {
+                    info!("{} is {:?}", registry.symbol(&token), class);
+                }
+                registry.set_class(&token, &class);
+                classes.insert(token, class);
+            }
+            Err(e) => info!("Error classifying {:?}: {:?}", token, e),
+        }
+    }
+    registry.write_cache()?;
+
+    Ok(classes)
+}
+
+#[cfg(test)]
+mod tax_detector_tests {
+    use super::*;
+
+    #[test]
+    fn effective_tax_compounds_buy_and_sell() {
+        let class = TokenClass::FeeOnTransfer {
+            buy_bps: 500,
+            transfer_bps: 500,
+            sell_bps: 500,
+        };
+        // 0.95 * 0.95 = 0.9025 kept
+        assert_eq!(class.effective_tax_bps(), 975);
+        assert_eq!(TokenClass::Normal.effective_tax_bps(), 0);
+        assert_eq!(_tax_bps(U256::from(1000), U256::from(900)), 1000);
+    }
+}
@@ -0,0 +1,304 @@
+use alloy::{
+    primitives::Address,
+    providers::{MulticallItem, Provider, ProviderBuilder},
+};
+use anyhow::{Ok, Result};
+use log::info;
+use std::{collections::HashMap, fs, path::Path, str::FromStr};
+
+use crate::constants::{get_blacklist_tokens, WHITELIST_TOKENS};
+use crate::multi::Erc20Metadata;
+use crate::paths::ArbPath;
+use crate::tax_detector::TokenClass;
+
+const TOKEN_CACHE_PATH: &str = "src/.cached-tokens.csv";
+const TOKEN_CACHE_HEADER: [&str; 9] = [
+    "address",
+    "symbol",
+    "name",
+    "decimals",
+    "checked",
+    "fee_on_transfer",
+    "rebasing",
+    "unsellable",
+    "tax_bps",
+];
+// Tokens per multicall; each token adds three calls
+const TOKENS_PER_MULTICALL: usize = 100;
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct TokenFlags {
+    pub fee_on_transfer: bool,
+    pub rebasing: bool,
+    pub unsellable: bool,
+    pub blacklisted: bool,
+    pub whitelisted: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub address: Address,
+    pub symbol: String,
+    pub name: String,
+    pub decimals: u8,
+    pub flags: TokenFlags,
+    /// Whether the transfer behaviour below was measured by `tax_detector`.
+    pub checked: bool,
+    /// Share of each pool-to-pool transfer lost to the token's tax, in basis points.
+    pub tax_bps: u32,
+}
+
+/// Metadata for every token the bot trades, cached in `src/.cached-tokens.csv`.
+/// Blacklist and whitelist flags always come from `constants`, not the cache.
+#[derive(Default, Debug, Clone)]
+pub struct TokenRegistry {
+    pub tokens: HashMap<Address, Token>,
+}
+
+impl TokenRegistry {
+    /// Loads the cached tokens, fetches metadata for any of `addresses` not cached yet
+    /// and rewrites the cache.
+    pub async fn load(https_url: String, addresses: &[Address]) -> Result<Self> {
+        let mut registry = if Path::new(TOKEN_CACHE_PATH).exists() {
+            Self::read_cache()?
+        } else {
+            Self::default()
+        };
+
+        let mut missing: Vec<Address> = addresses
+            .iter()
+            .filter(|address| !registry.tokens.contains_key(*address))
+            .cloned()
+            .collect();
+        missing.sort();
+        missing.dedup();
+
+        if !missing.is_empty() {
+            let provider = ProviderBuilder::new().connect_http(https_url.parse()?);
+            for chunk in missing.chunks(TOKENS_PER_MULTICALL) {
+                for token in fetch_tokens(&provider, chunk).await? {
+                    registry.tokens.insert(token.address, token);
+                }
+            }
+            info!("Fetched metadata for {} tokens", missing.len());
+            registry.write_cache()?;
+        }
+
+        registry.apply_lists();
+        Ok(registry)
+    }
+
+    pub fn get(&self, address: &Address) -> Option<&Token> {
+        self.tokens.get(address)
+    }
+
+    /// The token's symbol, or its address if it isn't known.
+    pub fn symbol(&self, address: &Address) -> String {
+        match self.tokens.get(address) {
+            Some(token) => token.symbol.clone(),
+            None => format!("{:?}", address),
+        }
+    }
+
+    pub fn decimals(&self, address: &Address) -> Option<u8> {
+        self.tokens.get(address).map(|token| token.decimals)
+    }
+
+    pub fn flags(&self, address: &Address) -> TokenFlags {
+        self.tokens
+            .get(address)
+            .map(|token| token.flags.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn set_flags(&mut self, address: &Address, flags: TokenFlags) {
+        if let Some(token) = self.tokens.get_mut(address) {
+            token.flags = flags;
+        }
+    }
+
+    pub fn tax_bps(&self, address: &Address) -> u32 {
+        self.tokens
+            .get(address)
+            .map(|token| token.tax_bps)
+            .unwrap_or_default()
+    }
+
+    /// Tokens whose transfer behaviour hasn't been measured yet. Whitelisted tokens
+    /// are known to be plain ERC20s and never need checking.
+    pub fn unchecked(&self) -> Vec<Address> {
+        self.tokens
+            .values()
+            .filter(|token| !token.checked && !token.flags.whitelisted)
+            .map(|token| token.address)
+            .collect()
+    }
+
+    /// Records a detector result for `address`.
+    pub fn set_class(&mut self, address: &Address, class: &TokenClass) {
+        if let Some(token) = self.tokens.get_mut(address) {
+            token.checked = true;
+            token.flags.fee_on_transfer = false;
+            token.flags.rebasing = false;
+            token.flags.unsellable = false;
+            token.tax_bps = 0;
+            match class {
+                TokenClass::Normal => {}
+                TokenClass::FeeOnTransfer { .. } => {
+                    token.flags.fee_on_transfer = true;
+                    token.tax_bps = class.effective_tax_bps();
+                }
+                TokenClass::Rebasing => token.flags.rebasing = true,
+                TokenClass::Unsellable => token.flags.unsellable = true,
+            }
+        }
+    }
+
+    /// Rebasing balances and tokens that can't be sold back can't be simulated at all.
+    /// Fee-on-transfer tokens are quoted net of their tax, but the executor swaps through
+    /// the pair without the router's fee-on-transfer functions, so they aren't traded.
+    pub fn is_tradable(&self, address: &Address) -> bool {
+        let flags = self.flags(address);
+        !(flags.blacklisted || flags.unsellable || flags.rebasing || flags.fee_on_transfer)
+    }
+
+    pub fn excludes_path(&self, path: &ArbPath) -> bool {
+        path.pools()
+            .any(|pool| !self.is_tradable(&pool.token0) || !self.is_tradable(&pool.token1))
+    }
+
+    /// Renders a path as its token symbols, e.g. `USDC→WETH→LINK→USDC`.
+    pub fn path_label(&self, path: &ArbPath) -> String {
+        let mut symbols: Vec<String> = path
+            .hops
+            .iter()
+            .map(|hop| self.symbol(&hop.token_in()))
+            .collect();
+        if let Some(last) = path.hops.last() {
+            symbols.push(self.symbol(&last.token_out()));
+        }
+        symbols.join("→")
+    }
+
+    fn apply_lists(&mut self) {
+        let blacklist = get_blacklist_tokens();
+        for (address, token) in self.tokens.iter_mut() {
+            token.flags.blacklisted = blacklist.contains(address);
+            token.flags.whitelisted = WHITELIST_TOKENS.contains(address);
+        }
+    }
+
+    fn read_cache() -> Result<Self> {
+        let mut reader = csv::Reader::from_path(TOKEN_CACHE_PATH)?;
+        if reader.headers()?.iter().ne(TOKEN_CACHE_HEADER) {
+            info!("Discarding token cache with an outdated header");
+            return Ok(Self::default());
+        }
+
+        let mut tokens = HashMap::new();
+        for row in reader.deserialize() {
+            let (
+                address,
+                symbol,
+                name,
+                decimals,
+                checked,
+                fee_on_transfer,
+                rebasing,
+                unsellable,
+                tax_bps,
+            ): (String, String, String, u8, bool, bool, bool, bool, u32) = row?;
+            let address = Address::from_str(&address)?;
+            tokens.insert(
+                address,
+                Token {
+                    address,
+                    symbol,
+                    name,
+                    decimals,
+                    flags: TokenFlags {
+                        fee_on_transfer,
+                        rebasing,
+                        unsellable,
+                        ..Default::default()
+                    },
+                    checked,
+                    tax_bps,
+                },
+            );
+        }
+        Ok(Self { tokens })
+    }
+
+    pub fn write_cache(&self) -> Result<()> {
+        let tmp = format!("{}.tmp", TOKEN_CACHE_PATH);
+        let mut writer = csv::Writer::from_path(&tmp)?;
+        writer.write_record(TOKEN_CACHE_HEADER)?;
+        for token in self.tokens.values() {
+            writer.serialize((
+                format!("{:?}", token.address),
+                &token.symbol,
+                &token.name,
+                token.decimals,
+                token.checked,
+                token.flags.fee_on_transfer,
+                token.flags.rebasing,
+                token.flags.unsellable,
+                token.tax_bps,
+            ))?;
+        }
+        writer.flush()?;
+        fs::rename(&tmp, TOKEN_CACHE_PATH)?;
+        Ok(())
+    }
+}
+
+/// Reads symbol, name and decimals for `addresses`, one multicall per method. Calls are
+/// allowed to fail individually: tokens like MKR return `bytes32` symbols, and those fall
+/// back to the address.
+async fn fetch_tokens<P: Provider>(provider: &P, addresses: &[Address]) -> Result<Vec<Token>> {
+    let mut symbol_calls = provider.multicall().dynamic::<Erc20Metadata::symbolCall>();
+    let mut name_calls = provider.multicall().dynamic::<Erc20Metadata::nameCall>();
+    let mut decimals_calls = provider
+        .multicall()
+        .dynamic::<Erc20Metadata::decimalsCall>();
+    for address in addresses {
+        let contract = Erc20Metadata::new(*address, provider);
+        symbol_calls = symbol_calls.add_call_dynamic(contract.symbol().into_call(true));
+        name_calls = name_calls.add_call_dynamic(contract.name().into_call(true));
+        decimals_calls = decimals_calls.add_call_dynamic(contract.decimals().into_call(true));
+    }
+    let (symbols, names, decimals) = tokio::try_join!(
+        symbol_calls.aggregate3(),
+        name_calls.aggregate3(),
+        decimals_calls.aggregate3(),
+    )?;
+
+    let mut tokens = Vec::new();
+    for (i, address) in addresses.iter().enumerate() {
+        let fallback = format!("{:?}", address);
+        let symbol = match &symbols[i] {
+            std::result::Result::Ok(symbol) => symbol.clone(),
+            Err(_) => fallback.clone(),
+        };
+        let name = match &names[i] {
+            std::result::Result::Ok(name) => name.clone(),
+            Err(_) => fallback,
+        };
+        // Without decimals no amount can be converted, so leave the token out
+        let decimals = match decimals[i] {
+            std::result::Result::Ok(decimals) => decimals,
+            Err(_) => continue,
+        };
+        tokens.push(Token {
+            address: *address,
+            symbol,
+            name,
+            decimals,
+            flags: TokenFlags::default(),
+            checked: false,
+            tax_bps: 0,
+        });
+    }
+    Ok(tokens)
+}
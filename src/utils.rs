@@ -1,16 +1,18 @@
-use anyhow::Result;
-use ethers::{
-    self,
-    abi::{decode, ParamType, Token},
-    providers::{Middleware, Provider, Ws},
-    types::{Filter, H160, U256, U64},
+use alloy::{
+    primitives::{Address, U256},
+    providers::Provider,
+    rpc::types::{Filter, Log},
+    sol_types::SolEvent,
 };
+use anyhow::Result;
 use fern::colors::{Color, ColoredLevelConfig};
 use log::LevelFilter;
 use rand::Rng;
 use std::{collections::HashMap, sync::Arc};
 
-use crate::multi::Reserve;
+use crate::abi::events::{IUniswapV2Pair, IUniswapV3Pool};
+use crate::multi::{PoolState, Reserve, UniswapV3State};
+use crate::reorg::ReserveDiff;
 
 pub fn setup_logger() -> Result<()> {
     let colors = ColoredLevelConfig {
@@ -41,16 +43,13 @@ pub fn setup_logger() -> Result<()> {
     Ok(())
 }
 
-pub fn calculate_next_block_base_fee(
-    gas_used: U256,
-    gas_limit: U256,
-    base_fee_per_gas: U256,
-) -> U256 {
-    let gas_used = gas_used;
+pub fn calculate_next_block_base_fee(gas_used: u64, gas_limit: u64, base_fee_per_gas: u64) -> u64 {
+    let gas_used = gas_used as u128;
+    let base_fee_per_gas = base_fee_per_gas as u128;
 
-    let mut target_gas_used = gas_limit / 2;
-    target_gas_used = if target_gas_used == U256::zero() {
-        U256::one()
+    let mut target_gas_used = (gas_limit / 2) as u128;
+    target_gas_used = if target_gas_used == 0 {
+        1
     } else {
         target_gas_used
     };
@@ -58,60 +57,281 @@ pub fn calculate_next_block_base_fee(
     let new_base_fee = {
         if gas_used > target_gas_used {
             base_fee_per_gas
-                + ((base_fee_per_gas * (gas_used - target_gas_used)) / target_gas_used)
-                    / U256::from(8u64)
+                + ((base_fee_per_gas * (gas_used - target_gas_used)) / target_gas_used) / 8
         } else {
             base_fee_per_gas
-                - ((base_fee_per_gas * (target_gas_used - gas_used)) / target_gas_used)
-                    / U256::from(8u64)
+                - ((base_fee_per_gas * (target_gas_used - gas_used)) / target_gas_used) / 8
         }
     };
 
     let seed = rand::thread_rng().gen_range(0..9);
-    new_base_fee + seed
+    new_base_fee as u64 + seed
 }
 
-pub async fn get_touched_pool_reserves(
-    provider: Arc<Provider<Ws>>,
-    block_number: U64,
-) -> Result<HashMap<H160, Reserve>> {
-    let sync_event = "Sync(uint112,uint112)";
+pub async fn get_touched_pool_reserves<P: Provider>(
+    provider: Arc<P>,
+    block_number: u64,
+) -> Result<HashMap<Address, Reserve>> {
     let event_filter = Filter::new()
         .from_block(block_number)
         .to_block(block_number)
-        .event(sync_event);
+        .event_signature(IUniswapV2Pair::Sync::SIGNATURE_HASH);
 
     let logs = provider.get_logs(&event_filter).await?;
+    Ok(latest_sync_reserves(&logs))
+}
 
-    let mut tx_idx = HashMap::new();
+/// The last reserves each pair reported in a block's `Sync` logs. Logs are ordered by
+/// (transaction index, log index), since a multi-hop swap can sync one pair several times
+/// in one transaction. Removed and malformed logs are skipped.
+pub fn latest_sync_reserves(logs: &[Log]) -> HashMap<Address, Reserve> {
+    let mut positions: HashMap<Address, (u64, u64)> = HashMap::new();
     let mut reserves = HashMap::new();
 
+    for log in logs {
+        if log.removed {
+            continue;
+        }
+        let reserve = match decode_sync_log(log) {
+            Some(reserve) => reserve,
+            None => continue,
+        };
+        let address = log.address();
+        let position = (
+            log.transaction_index.unwrap_or_default(),
+            log.log_index.unwrap_or_default(),
+        );
+        let newer = match positions.get(&address) {
+            Some(previous) => position > *previous,
+            None => true,
+        };
+
+        if newer {
+            reserves.insert(address, reserve);
+            positions.insert(address, position);
+        }
+    }
+
+    reserves
+}
+
+/// The reserves in a V2 `Sync` log, or `None` if it isn't one. Both words must fit in
+/// a `uint112`, as the pair stores them.
+pub fn decode_sync_log(log: &Log) -> Option<Reserve> {
+    if log.topic0() != Some(&IUniswapV2Pair::Sync::SIGNATURE_HASH) {
+        return None;
+    }
+    let data = &log.data().data;
+    if data.len() != 64 {
+        return None;
+    }
+    Some(Reserve {
+        reserve0: _decode_uint112(&data[..32])?,
+        reserve1: _decode_uint112(&data[32..])?,
+    })
+}
+
+fn _decode_uint112(word: &[u8]) -> Option<U256> {
+    // A uint112 fills the low 14 bytes of its word
+    if word[..18].iter().any(|byte| *byte != 0) {
+        return None;
+    }
+    Some(U256::from_be_slice(word))
+}
+
+/// Applies a V3 `Swap`, `Mint` or `Burn` log to the pool's state.
+/// Returns false if the log isn't one of those events or fails to decode.
+pub fn apply_uniswap_v3_log(state: &mut UniswapV3State, log: &Log) -> bool {
+    let topic0 = match log.topic0() {
+        Some(topic) => *topic,
+        None => return false,
+    };
+
+    if topic0 == IUniswapV3Pool::Swap::SIGNATURE_HASH {
+        match log.log_decode::<IUniswapV3Pool::Swap>() {
+            Ok(decoded) => {
+                let swap = decoded.inner.data;
+                state.apply_swap(
+                    U256::from(swap.sqrtPriceX96),
+                    swap.liquidity,
+                    swap.tick.as_i32(),
+                );
+                true
+            }
+            Err(_) => false,
+        }
+    } else if topic0 == IUniswapV3Pool::Mint::SIGNATURE_HASH {
+        match log.log_decode::<IUniswapV3Pool::Mint>() {
+            Ok(decoded) => {
+                let mint = decoded.inner.data;
+                state.update_position(
+                    mint.tickLower.as_i32(),
+                    mint.tickUpper.as_i32(),
+                    mint.amount as i128,
+                );
+                true
+            }
+            Err(_) => false,
+        }
+    } else if topic0 == IUniswapV3Pool::Burn::SIGNATURE_HASH {
+        match log.log_decode::<IUniswapV3Pool::Burn>() {
+            Ok(decoded) => {
+                let burn = decoded.inner.data;
+                state.update_position(
+                    burn.tickLower.as_i32(),
+                    burn.tickUpper.as_i32(),
+                    -(burn.amount as i128),
+                );
+                true
+            }
+            Err(_) => false,
+        }
+    } else {
+        false
+    }
+}
+
+/// Fetches the block's V3 `Swap`/`Mint`/`Burn` logs and applies them, in log order,
+/// to any tracked V3 pool in `states`, recording each pool's prior state in `diff`.
+/// Returns the addresses that were updated.
+pub async fn update_touched_v3_states<P: Provider>(
+    provider: Arc<P>,
+    block_number: u64,
+    states: &mut HashMap<Address, PoolState>,
+    diff: &mut ReserveDiff,
+) -> Result<Vec<Address>> {
+    let event_filter = Filter::new()
+        .from_block(block_number)
+        .to_block(block_number)
+        .event_signature(vec![
+            IUniswapV3Pool::Swap::SIGNATURE_HASH,
+            IUniswapV3Pool::Mint::SIGNATURE_HASH,
+            IUniswapV3Pool::Burn::SIGNATURE_HASH,
+        ]);
+
+    let mut logs = provider.get_logs(&event_filter).await?;
+    logs.sort_by_key(|log| log.log_index.unwrap_or_default());
+
+    let mut touched = Vec::new();
     for log in &logs {
-        let decoded = decode(&[ParamType::Uint(256), ParamType::Uint(256)], &log.data);
-        match decoded {
-            Ok(data) => {
-                let idx = log.transaction_index.unwrap_or_default();
-                let prev_tx_idx = tx_idx.get(&log.address);
-                let update = (*prev_tx_idx.unwrap_or(&U64::zero())) <= idx;
-
-                if update {
-                    let reserve0 = match data[0] {
-                        Token::Uint(rs) => rs,
-                        _ => U256::zero(),
-                    };
-                    let reserve1 = match data[1] {
-                        Token::Uint(rs) => rs,
-                        _ => U256::zero(),
-                    };
-                    let reserve = Reserve { reserve0, reserve1 };
-
-                    reserves.insert(log.address, reserve);
-                    tx_idx.insert(log.address, idx);
-                }
+        let address = log.address();
+        if let Some(PoolState::UniswapV3(_)) = states.get(&address) {
+            diff.record(address, states);
+        }
+        if let Some(PoolState::UniswapV3(state)) = states.get_mut(&address) {
+            if apply_uniswap_v3_log(state, log) && !touched.contains(&address) {
+                touched.push(address);
             }
-            Err(_) => {}
         }
     }
 
-    Ok(reserves)
+    Ok(touched)
+}
+
+#[cfg(test)]
+mod utils_tests {
+    use super::*;
+
+    const UNISWAP_WETH_USDC: &str = "0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc";
+    const UNISWAP_DAI_WETH: &str = "0xa478c2975ab1ea89e8196811f51a7b7ade33eb11";
+    const SUSHI_WETH_USDC: &str = "0x397ff1542f962076d0bfe58ea045ffa2d347aca0";
+
+    fn sync_log(pair: &str, position: (u64, u64), reserves: (U256, U256), removed: bool) -> Log {
+        let mut data = reserves.0.to_be_bytes::<32>().to_vec();
+        data.extend_from_slice(&reserves.1.to_be_bytes::<32>());
+        Log {
+            inner: alloy::primitives::Log::new_unchecked(
+                pair.parse().unwrap(),
+                vec![IUniswapV2Pair::Sync::SIGNATURE_HASH],
+                data.into(),
+            ),
+            transaction_index: Some(position.0),
+            log_index: Some(position.1),
+            removed,
+            ..Default::default()
+        }
+    }
+
+    // Syncs from one block, out of order as a provider may return them. Built here
+    // rather than recorded, so each edge case has a log of its own.
+    fn block_logs() -> Vec<Log> {
+        let reserves =
+            |reserve0: u128, reserve1: u128| (U256::from(reserve0), U256::from(reserve1));
+        vec![
+            sync_log(
+                UNISWAP_WETH_USDC,
+                (3, 14),
+                reserves(41_020_000_000_000, 12_400 * 10u128.pow(18)),
+                false,
+            ),
+            sync_log(
+                UNISWAP_WETH_USDC,
+                (3, 10),
+                reserves(41_000_000_000_000, 12_410 * 10u128.pow(18)),
+                false,
+            ),
+            sync_log(
+                UNISWAP_DAI_WETH,
+                (0, 0),
+                reserves(5_000_000 * 10u128.pow(18), 2_000 * 10u128.pow(18)),
+                false,
+            ),
+            sync_log(
+                SUSHI_WETH_USDC,
+                (1, 2),
+                reserves(20_000_000_000_000, 6_000 * 10u128.pow(18)),
+                false,
+            ),
+            sync_log(
+                SUSHI_WETH_USDC,
+                (5, 20),
+                reserves(20_050_000_000_000, 5_990 * 10u128.pow(18)),
+                false,
+            ),
+            sync_log(SUSHI_WETH_USDC, (7, 31), reserves(1, 1), true),
+            // reserve0 doesn't fit in a uint112
+            sync_log(
+                UNISWAP_WETH_USDC,
+                (9, 40),
+                (U256::from(1) << 112, U256::from(1)),
+                false,
+            ),
+        ]
+    }
+
+    fn pair(address: &str) -> Address {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn latest_sync_follows_transaction_and_log_order() {
+        let reserves = latest_sync_reserves(&block_logs());
+        assert_eq!(reserves.len(), 3);
+
+        // Synced twice in transaction 3; log 14 is the later one
+        let uniswap = &reserves[&pair(UNISWAP_WETH_USDC)];
+        assert_eq!(uniswap.reserve0, U256::from(41_020_000_000_000u64));
+
+        // Only synced in transaction 0
+        let other = &reserves[&pair(UNISWAP_DAI_WETH)];
+        assert_eq!(
+            other.reserve0,
+            U256::from(5_000_000_000_000_000_000_000_000u128)
+        );
+    }
+
+    #[test]
+    fn removed_and_oversized_syncs_are_ignored() {
+        let reserves = latest_sync_reserves(&block_logs());
+
+        // Transaction 7's log was removed, so transaction 5's reserves stand
+        let sushi = &reserves[&pair(SUSHI_WETH_USDC)];
+        assert_eq!(sushi.reserve0, U256::from(20_050_000_000_000u64));
+
+        let oversized = block_logs()
+            .into_iter()
+            .find(|log| log.transaction_index == Some(9))
+            .unwrap();
+        assert!(decode_sync_log(&oversized).is_none());
+    }
 }
@@ -0,0 +1,544 @@
+//! Integer ports of the Uniswap V3 core libraries (`FullMath`, `TickMath`,
+//! `SqrtPriceMath`, `SwapMath`, `TickBitmap`). Rounding follows the Solidity
+//! code exactly so simulated swaps match on-chain results to the wei.
+use alloy::primitives::{ruint::UintTryFrom, U256, U512};
+use std::collections::HashMap;
+
+pub const MIN_TICK: i32 = -887272;
+pub const MAX_TICK: i32 = -MIN_TICK;
+/// `getSqrtRatioAtTick(MIN_TICK)`
+pub const MIN_SQRT_RATIO: U256 = U256::from_limbs([4295128739, 0, 0, 0]);
+/// `getSqrtRatioAtTick(MAX_TICK)`
+pub const MAX_SQRT_RATIO: U256 =
+    U256::from_limbs([0x5d951d5263988d26, 0xefd1fc6a50648849, 0xfffd8963, 0]);
+/// 2^96
+pub const Q96: U256 = U256::from_limbs([0, 1 << 32, 0, 0]);
+pub const FEE_PIPS_DENOMINATOR: u32 = 1_000_000;
+
+// 2^128 / sqrt(1.0001^(2^i)) for i = 1..=19, applied for each set bit of |tick|
+const TICK_RATIO_FACTORS: [(u32, u128); 19] = [
+    (0x2, 0xfff97272373d413259a46990580e213a),
+    (0x4, 0xfff2e50f5f656932ef12357cf3c7fdcc),
+    (0x8, 0xffe5caca7e10e4e61c3624eaa0941cd0),
+    (0x10, 0xffcb9843d60f6159c9db58835c926644),
+    (0x20, 0xff973b41fa98c081472e6896dfb254c0),
+    (0x40, 0xff2ea16466c96a3843ec78b326b52861),
+    (0x80, 0xfe5dee046a99a2a811c461f1969c3053),
+    (0x100, 0xfcbe86c7900a88aedcffc83b479aa3a4),
+    (0x200, 0xf987a7253ac413176f2b074cf7815e54),
+    (0x400, 0xf3392b0822b70005940c7a398e4b70f3),
+    (0x800, 0xe7159475a2c29b7443b29c7fa6e889d9),
+    (0x1000, 0xd097f3bdfd2022b8845ad8f792aa5825),
+    (0x2000, 0xa9f746462d870fdf8a65dc1f90e061e5),
+    (0x4000, 0x70d869a156d2a1b890bb3df62baf32f7),
+    (0x8000, 0x31be135f97d08fd981231505542fcfa6),
+    (0x10000, 0x9aa508b5b7a84e1c677de54f3e99bc9),
+    (0x20000, 0x5d6af8dedb81196699c329225ee604),
+    (0x40000, 0x2216e584f5fa1ea926041bedfe98),
+    (0x80000, 0x48a170391f7dc42444e8fa2),
+];
+
+// FullMath
+
+pub fn mul_div(a: U256, b: U256, denominator: U256) -> Option<U256> {
+    if denominator.is_zero() {
+        return None;
+    }
+    let product: U512 = a.widening_mul(b);
+    U256::uint_try_from(product / U512::from(denominator)).ok()
+}
+
+pub fn mul_div_rounding_up(a: U256, b: U256, denominator: U256) -> Option<U256> {
+    if denominator.is_zero() {
+        return None;
+    }
+    let product: U512 = a.widening_mul(b);
+    let (quotient, remainder) = product.div_rem(U512::from(denominator));
+    let quotient = U256::uint_try_from(quotient).ok()?;
+    if remainder.is_zero() {
+        Some(quotient)
+    } else {
+        quotient.checked_add(U256::ONE)
+    }
+}
+
+pub fn div_rounding_up(a: U256, b: U256) -> Option<U256> {
+    if b.is_zero() {
+        return None;
+    }
+    let (quotient, remainder) = a.div_rem(b);
+    if remainder.is_zero() {
+        Some(quotient)
+    } else {
+        Some(quotient + U256::ONE)
+    }
+}
+
+// TickMath
+
+pub fn get_sqrt_ratio_at_tick(tick: i32) -> Option<U256> {
+    let abs_tick = tick.unsigned_abs();
+    if abs_tick > MAX_TICK as u32 {
+        return None;
+    }
+
+    let mut ratio = if abs_tick & 0x1 != 0 {
+        U256::from(0xfffcb933bd6fad37aa2d162d1a594001u128)
+    } else {
+        U256::ONE << 128
+    };
+    for (bit, factor) in TICK_RATIO_FACTORS {
+        if abs_tick & bit != 0 {
+            ratio = (ratio * U256::from(factor)) >> 128;
+        }
+    }
+
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+
+    // Q128.128 -> Q64.96, rounding up so getTickAtSqrtRatio stays consistent
+    let rounding = if (ratio & U256::from(u32::MAX)).is_zero() {
+        U256::ZERO
+    } else {
+        U256::ONE
+    };
+    Some((ratio >> 32) + rounding)
+}
+
+/// Greatest tick whose sqrt ratio is <= `sqrt_price_x96`, which is exactly what
+/// `TickMath.getTickAtSqrtRatio` returns. Found by binary search over
+/// `get_sqrt_ratio_at_tick` instead of porting the log2 approximation.
+pub fn get_tick_at_sqrt_ratio(sqrt_price_x96: U256) -> Option<i32> {
+    if sqrt_price_x96 < MIN_SQRT_RATIO || sqrt_price_x96 >= MAX_SQRT_RATIO {
+        return None;
+    }
+
+    let mut low = MIN_TICK;
+    let mut high = MAX_TICK;
+    while low < high {
+        let mid = low + (high - low + 1) / 2;
+        if get_sqrt_ratio_at_tick(mid)? <= sqrt_price_x96 {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    Some(low)
+}
+
+// SqrtPriceMath
+
+pub fn get_amount_0_delta(
+    sqrt_ratio_a_x96: U256,
+    sqrt_ratio_b_x96: U256,
+    liquidity: u128,
+    round_up: bool,
+) -> Option<U256> {
+    let (lower, upper) = if sqrt_ratio_a_x96 > sqrt_ratio_b_x96 {
+        (sqrt_ratio_b_x96, sqrt_ratio_a_x96)
+    } else {
+        (sqrt_ratio_a_x96, sqrt_ratio_b_x96)
+    };
+    if lower.is_zero() {
+        return None;
+    }
+
+    let numerator1: U256 = U256::from(liquidity) << 96;
+    let numerator2 = upper - lower;
+
+    if round_up {
+        div_rounding_up(mul_div_rounding_up(numerator1, numerator2, upper)?, lower)
+    } else {
+        Some(mul_div(numerator1, numerator2, upper)? / lower)
+    }
+}
+
+pub fn get_amount_1_delta(
+    sqrt_ratio_a_x96: U256,
+    sqrt_ratio_b_x96: U256,
+    liquidity: u128,
+    round_up: bool,
+) -> Option<U256> {
+    let (lower, upper) = if sqrt_ratio_a_x96 > sqrt_ratio_b_x96 {
+        (sqrt_ratio_b_x96, sqrt_ratio_a_x96)
+    } else {
+        (sqrt_ratio_a_x96, sqrt_ratio_b_x96)
+    };
+
+    if round_up {
+        mul_div_rounding_up(U256::from(liquidity), upper - lower, Q96)
+    } else {
+        mul_div(U256::from(liquidity), upper - lower, Q96)
+    }
+}
+
+fn get_next_sqrt_price_from_amount_0_rounding_up(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    amount: U256,
+) -> Option<U256> {
+    if amount.is_zero() {
+        return Some(sqrt_price_x96);
+    }
+    let numerator1: U256 = U256::from(liquidity) << 96;
+
+    let (product, overflow) = amount.overflowing_mul(sqrt_price_x96);
+    if !overflow {
+        let (denominator, overflow) = numerator1.overflowing_add(product);
+        if !overflow {
+            return mul_div_rounding_up(numerator1, sqrt_price_x96, denominator);
+        }
+    }
+
+    div_rounding_up(
+        numerator1,
+        (numerator1 / sqrt_price_x96).checked_add(amount)?,
+    )
+}
+
+fn get_next_sqrt_price_from_amount_1_rounding_down(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    amount: U256,
+) -> Option<U256> {
+    if liquidity == 0 {
+        return None;
+    }
+    let quotient = if amount.bit_len() <= 160 {
+        (amount << 96) / U256::from(liquidity)
+    } else {
+        mul_div(amount, Q96, U256::from(liquidity))?
+    };
+    sqrt_price_x96.checked_add(quotient)
+}
+
+pub fn get_next_sqrt_price_from_input(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    amount_in: U256,
+    zero_for_one: bool,
+) -> Option<U256> {
+    if sqrt_price_x96.is_zero() || liquidity == 0 {
+        return None;
+    }
+    if zero_for_one {
+        get_next_sqrt_price_from_amount_0_rounding_up(sqrt_price_x96, liquidity, amount_in)
+    } else {
+        get_next_sqrt_price_from_amount_1_rounding_down(sqrt_price_x96, liquidity, amount_in)
+    }
+}
+
+// SwapMath
+
+#[derive(Debug, Clone)]
+pub struct SwapStep {
+    pub sqrt_price_next_x96: U256,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub fee_amount: U256,
+}
+
+/// `SwapMath.computeSwapStep` for the exact-input case.
+pub fn compute_swap_step(
+    sqrt_price_current_x96: U256,
+    sqrt_price_target_x96: U256,
+    liquidity: u128,
+    amount_remaining: U256,
+    fee_pips: u32,
+) -> Option<SwapStep> {
+    let zero_for_one = sqrt_price_current_x96 >= sqrt_price_target_x96;
+    let fee_complement = U256::from(FEE_PIPS_DENOMINATOR.checked_sub(fee_pips)?);
+
+    let amount_remaining_less_fee = mul_div(
+        amount_remaining,
+        fee_complement,
+        U256::from(FEE_PIPS_DENOMINATOR),
+    )?;
+    let amount_in_to_target = if zero_for_one {
+        get_amount_0_delta(
+            sqrt_price_target_x96,
+            sqrt_price_current_x96,
+            liquidity,
+            true,
+        )?
+    } else {
+        get_amount_1_delta(
+            sqrt_price_current_x96,
+            sqrt_price_target_x96,
+            liquidity,
+            true,
+        )?
+    };
+
+    let sqrt_price_next_x96 = if amount_remaining_less_fee >= amount_in_to_target {
+        sqrt_price_target_x96
+    } else {
+        get_next_sqrt_price_from_input(
+            sqrt_price_current_x96,
+            liquidity,
+            amount_remaining_less_fee,
+            zero_for_one,
+        )?
+    };
+    let max = sqrt_price_next_x96 == sqrt_price_target_x96;
+
+    let (amount_in, amount_out) = if zero_for_one {
+        let amount_in = if max {
+            amount_in_to_target
+        } else {
+            get_amount_0_delta(sqrt_price_next_x96, sqrt_price_current_x96, liquidity, true)?
+        };
+        let amount_out = get_amount_1_delta(
+            sqrt_price_next_x96,
+            sqrt_price_current_x96,
+            liquidity,
+            false,
+        )?;
+        (amount_in, amount_out)
+    } else {
+        let amount_in = if max {
+            amount_in_to_target
+        } else {
+            get_amount_1_delta(sqrt_price_current_x96, sqrt_price_next_x96, liquidity, true)?
+        };
+        let amount_out = get_amount_0_delta(
+            sqrt_price_current_x96,
+            sqrt_price_next_x96,
+            liquidity,
+            false,
+        )?;
+        (amount_in, amount_out)
+    };
+
+    let fee_amount = if !max {
+        // The remainder of the input goes to the fee when we stop short of the target
+        amount_remaining.checked_sub(amount_in)?
+    } else {
+        mul_div_rounding_up(amount_in, U256::from(fee_pips), fee_complement)?
+    };
+
+    Some(SwapStep {
+        sqrt_price_next_x96,
+        amount_in,
+        amount_out,
+        fee_amount,
+    })
+}
+
+// TickBitmap
+
+fn position(compressed: i32) -> (i16, u8) {
+    ((compressed >> 8) as i16, (compressed & 0xff) as u8)
+}
+
+/// `TickBitmap.nextInitializedTickWithinOneWord`. Returns `None` if the word it
+/// needs wasn't fetched, since treating it as empty would silently skip liquidity.
+pub fn next_initialized_tick_within_one_word(
+    tick_bitmap: &HashMap<i16, U256>,
+    tick: i32,
+    tick_spacing: i32,
+    lte: bool,
+) -> Option<(i32, bool)> {
+    let mut compressed = tick / tick_spacing;
+    if tick < 0 && tick % tick_spacing != 0 {
+        compressed -= 1;
+    }
+
+    if lte {
+        let (word_pos, bit_pos) = position(compressed);
+        let mask = (U256::ONE << bit_pos) - U256::ONE + (U256::ONE << bit_pos);
+        let masked = *tick_bitmap.get(&word_pos)? & mask;
+
+        let initialized = !masked.is_zero();
+        let next = if initialized {
+            let msb = (masked.bit_len() - 1) as i32;
+            (compressed - (bit_pos as i32 - msb)) * tick_spacing
+        } else {
+            (compressed - bit_pos as i32) * tick_spacing
+        };
+        Some((next, initialized))
+    } else {
+        let (word_pos, bit_pos) = position(compressed + 1);
+        let mask = !((U256::ONE << bit_pos) - U256::ONE);
+        let masked = *tick_bitmap.get(&word_pos)? & mask;
+
+        let initialized = !masked.is_zero();
+        let next = if initialized {
+            let lsb = masked.trailing_zeros() as i32;
+            (compressed + 1 + (lsb - bit_pos as i32)) * tick_spacing
+        } else {
+            (compressed + 1 + (255 - bit_pos as i32)) * tick_spacing
+        };
+        Some((next, initialized))
+    }
+}
+
+/// Word position in the tick bitmap that holds `tick`.
+pub fn tick_word(tick: i32, tick_spacing: i32) -> i16 {
+    let mut compressed = tick / tick_spacing;
+    if tick < 0 && tick % tick_spacing != 0 {
+        compressed -= 1;
+    }
+    position(compressed).0
+}
+
+#[cfg(test)]
+mod v3_math_tests {
+    use super::*;
+    use crate::multi::{TickInfo, UniswapV3State};
+    use crate::simulator::UniswapV3Simulator;
+
+    #[test]
+    fn sqrt_ratio_at_tick_bounds() {
+        assert_eq!(get_sqrt_ratio_at_tick(MIN_TICK).unwrap(), MIN_SQRT_RATIO);
+        assert_eq!(get_sqrt_ratio_at_tick(MAX_TICK).unwrap(), MAX_SQRT_RATIO);
+        assert_eq!(get_sqrt_ratio_at_tick(0).unwrap(), Q96);
+        assert!(get_sqrt_ratio_at_tick(MAX_TICK + 1).is_none());
+    }
+
+    #[test]
+    fn tick_at_sqrt_ratio_round_trips() {
+        for tick in [MIN_TICK, -200_000, -1, 0, 1, 50, 195_000, MAX_TICK - 1] {
+            let ratio = get_sqrt_ratio_at_tick(tick).unwrap();
+            assert_eq!(get_tick_at_sqrt_ratio(ratio).unwrap(), tick);
+            assert_eq!(get_tick_at_sqrt_ratio(ratio + U256::ONE).unwrap(), tick);
+        }
+    }
+
+    fn dec(value: &str) -> U256 {
+        U256::from_str_radix(value, 10).unwrap()
+    }
+
+    fn assert_step(step: SwapStep, sqrt_price_next_x96: U256, amounts: [&str; 3]) {
+        assert_eq!(step.sqrt_price_next_x96, sqrt_price_next_x96);
+        assert_eq!(step.amount_in, dec(amounts[0]));
+        assert_eq!(step.amount_out, dec(amounts[1]));
+        assert_eq!(step.fee_amount, dec(amounts[2]));
+    }
+
+    // Vectors from v3-core's SwapMath.spec.ts
+    #[test]
+    fn swap_step_matches_reference() {
+        let e18 = U256::from(10).pow(U256::from(18));
+        // encodePriceSqrt(101, 100) and encodePriceSqrt(1000, 100)
+        let price_101_100 = dec("79623317895830914510639640423");
+        let price_1000_100 = dec("250541448375047931186413801569");
+
+        // Exact input capped at the price target, one for zero
+        let step = compute_swap_step(Q96, price_101_100, 2 * 10u128.pow(18), e18, 600).unwrap();
+        assert_step(
+            step,
+            price_101_100,
+            ["9975124224178055", "9925619580021728", "5988667735148"],
+        );
+
+        // Exact input fully spent before the target, one for zero
+        let step = compute_swap_step(Q96, price_1000_100, 2 * 10u128.pow(18), e18, 600).unwrap();
+        assert_eq!(step.amount_in + step.fee_amount, e18);
+        let after =
+            get_next_sqrt_price_from_input(Q96, 2 * 10u128.pow(18), e18 - step.fee_amount, false)
+                .unwrap();
+        assert_step(
+            step,
+            after,
+            [
+                "999400000000000000",
+                "666399946655997866",
+                "600000000000000",
+            ],
+        );
+
+        // Entire input taken as fee
+        let step = compute_swap_step(
+            U256::from(2413),
+            dec("79887613182836312"),
+            1985041575832132834610021537970,
+            U256::from(10),
+            1872,
+        )
+        .unwrap();
+        assert_step(step, U256::from(2413), ["0", "0", "10"]);
+
+        // Target price of 1 uses only part of the input
+        let step = compute_swap_step(
+            U256::from(2),
+            U256::ONE,
+            1,
+            dec("3915081100057732413702495386755767"),
+            1,
+        )
+        .unwrap();
+        assert_step(
+            step,
+            U256::ONE,
+            [
+                "39614081257132168796771975168",
+                "0",
+                "39614120871253040049813",
+            ],
+        );
+    }
+
+    /// Positions over [-120, 120] with 1e18 liquidity and [-600, 600] with 2e18, priced
+    /// at tick 0 with 60 tick spacing and a 0.3% fee.
+    fn two_position_pool() -> UniswapV3State {
+        let e18 = 10i128.pow(18);
+        let ticks: HashMap<i32, TickInfo> =
+            [(-600, 2 * e18), (-120, e18), (120, -e18), (600, -2 * e18)]
+                .into_iter()
+                .map(|(tick, liquidity_net)| {
+                    let info = TickInfo {
+                        liquidity_gross: liquidity_net.unsigned_abs(),
+                        liquidity_net,
+                    };
+                    (tick, info)
+                })
+                .collect();
+        let mut tick_bitmap: HashMap<i16, U256> = HashMap::new();
+        for tick in ticks.keys() {
+            let (word, bit) = position(tick / 60);
+            *tick_bitmap.entry(word).or_default() |= U256::ONE << bit;
+        }
+        UniswapV3State {
+            sqrt_price_x96: Q96,
+            liquidity: 3 * 10u128.pow(18),
+            tick: 0,
+            tick_spacing: 60,
+            fee: 3000,
+            tick_bitmap,
+            ticks,
+        }
+    }
+
+    // Expected outputs follow UniswapV3Pool.swap step by step with the same positions
+    #[test]
+    fn full_swaps_match_reference() {
+        let state = two_position_pool();
+        let amount_in = U256::from(5) * U256::from(10).pow(U256::from(16));
+
+        // Both directions cross the inner position's boundary and stop inside the outer one
+        let zero_for_one = UniswapV3Simulator::get_amount_out(&state, amount_in, true).unwrap();
+        assert_eq!(zero_for_one, dec("48873971596049803"));
+        let one_for_zero = UniswapV3Simulator::get_amount_out(&state, amount_in, false).unwrap();
+        assert_eq!(one_for_zero, dec("48873971596049803"));
+
+        // Small enough to stay inside the starting tick range
+        let within =
+            UniswapV3Simulator::get_amount_out(&state, U256::from(10).pow(U256::from(15)), true)
+                .unwrap();
+        assert_eq!(within, dec("996668773744192"));
+    }
+
+    #[test]
+    fn crossing_an_unfetched_tick_fails() {
+        let mut state = two_position_pool();
+        state.ticks.remove(&-120);
+        let amount_in = U256::from(5) * U256::from(10).pow(U256::from(16));
+
+        // The bitmap still marks -120, so crossing it can't be simulated
+        assert!(UniswapV3Simulator::get_amount_out(&state, amount_in, true).is_none());
+        // The other direction never reaches it
+        assert!(UniswapV3Simulator::get_amount_out(&state, amount_in, false).is_some());
+    }
+}